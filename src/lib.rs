@@ -30,6 +30,55 @@ mod vox_writer {
     use std::io::{Seek, SeekFrom, Write};
     use std::mem;
 
+    mod animation;
+    mod bake;
+    mod binvox;
+    mod components;
+    mod csg;
+    mod flood;
+    mod gif;
+    mod goxel;
+    mod heightmap;
+    mod kvx;
+    mod las;
+    mod lod;
+    mod morphology;
+    mod nbt;
+    mod palette;
+    mod png;
+    mod point_cloud;
+    mod qubicle;
+    mod ramp;
+    mod raw_volume;
+    mod resample;
+    mod schematic;
+    mod sdf;
+    mod shapes;
+    mod slices;
+    mod sprite;
+    mod text;
+    mod transform;
+    mod zlib;
+
+    pub use self::bake::{BakeLighting, BakeOptions};
+    pub use self::binvox::Binvox;
+    pub use self::components::{Connectivity, VoxComponent};
+    pub use self::csg::{CsgOperation, CsgPrecedence};
+    pub use self::goxel::{GoxFile, GoxLayer, GoxMaterial};
+    pub use self::heightmap::{Heightmap, HeightmapColoring, HeightmapFill, HeightmapOptions};
+    pub use self::las::{LasColorMode, LasFile, LasOptions, LasPoint};
+    pub use self::morphology::{MorphologyColor, StructuringElement};
+    pub use self::point_cloud::{PointCloud, XyzColumns, XyzOptions};
+    pub use self::qubicle::{QubicleColorFormat, QubicleMatrix, QubicleModel, QubicleZAxis};
+    pub use self::ramp::{ColorRamp, RampSource};
+    pub use self::raw_volume::{RawSampleType, RawVolume, TransferFunction};
+    pub use self::schematic::{Schematic, SchematicOptions, SchematicVersion};
+    pub use self::sdf::Sdf;
+    pub use self::shapes::{Axis, ShapeFill};
+    pub use self::slices::SliceMapping;
+    pub use self::sprite::SpriteOptions;
+    pub use self::text::{BitmapFont, BitmapGlyph};
+
     /// samples code 1
     ///
     /// let mut vox = VoxWriter::create_empty();
@@ -93,6 +142,7 @@ mod vox_writer {
 
     ///////////////////////////////////////////////////////////////////////////////////////////////////
 
+    #[derive(Clone)]
     struct Point3<T> {
         pub x: T,
         pub y: T,
@@ -117,6 +167,7 @@ mod vox_writer {
 
     ///////////////////////////////////////////////////////////////////////////////////////////////////
 
+    #[derive(Clone)]
     struct AABBCC {
         lower_bound: Point3<f64>, // the lower left vertex
         upper_bound: Point3<f64>, // the upper right vertex
@@ -158,6 +209,10 @@ mod vox_writer {
         assert_eq!(get_id_char('V', 'O', 'X', ' '), 542658390);
     }
 
+    fn invalid_data(msg: &str) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+    }
+
     fn get_id_u8(a: u8, b: u8, c: u8, d: u8) -> u32 {
         return ((a as i32) | ((b as i32) << 8) | ((c as i32) << 16) | ((d as i32) << 24)) as u32;
     }
@@ -219,6 +274,200 @@ mod vox_writer {
             );
             assert_eq!(stru.get_size(), 32);
         }
+
+        #[test]
+        fn test_get_voxel_negative_pos() {
+            let mut vox = VoxWriter::create_empty();
+            vox.add_voxel(-1, 0, 200, 5);
+            vox.add_voxel(3, -130, 0, 6);
+            assert_eq!(vox.get_voxel(-1, 0, 200), Some(5));
+            assert_eq!(vox.get_voxel(3, -130, 0), Some(6));
+            assert_eq!(vox.get_voxel(0, 0, 0), None);
+            assert_eq!(vox.get_bounds(), Some(([-1, -130, 0], [3, 0, 200])));
+            let mut count = 0;
+            vox.for_each_voxel(|x, y, z, c| {
+                assert_eq!(vox.get_voxel(x, y, z), Some(c));
+                count += 1;
+            });
+            assert_eq!(count, 2);
+        }
+
+        /// the (model id, _f) of the models of a nSHP chunk
+        type ShapeFrames = Vec<(i32, Option<String>)>;
+
+        /// the models of the nSHP chunks of a saved file, and the voxel counts of the XYZI chunks
+        fn read_shapes(data: &[u8]) -> (Vec<ShapeFrames>, Vec<i32>) {
+            let i32_at = |p: usize| i32::from_le_bytes(data[p..p + 4].try_into().unwrap());
+            let read_dict = |p: &mut usize| {
+                let mut dict = vec![];
+                let count = i32_at(*p);
+                *p += 4;
+                for _ in 0..count {
+                    let mut kv = vec![];
+                    for _ in 0..2 {
+                        let len = i32_at(*p) as usize;
+                        kv.push(String::from_utf8_lossy(&data[*p + 4..*p + 4 + len]).to_string());
+                        *p += 4 + len;
+                    }
+                    dict.push((kv[0].clone(), kv[1].clone()));
+                }
+                dict
+            };
+            let mut shapes = vec![];
+            let mut xyzi = vec![];
+            // the chunks of the MAIN chunk
+            let mut pos = 20;
+            while pos < data.len() {
+                let id = &data[pos..pos + 4];
+                let size = i32_at(pos + 4) as usize;
+                let mut p = pos + 12;
+                if id == b"nSHP" {
+                    p += 4;
+                    read_dict(&mut p);
+                    let count = i32_at(p);
+                    p += 4;
+                    let mut models = vec![];
+                    for _ in 0..count {
+                        let model_id = i32_at(p);
+                        p += 4;
+                        let frame = read_dict(&mut p).into_iter().find(|kv| kv.0 == "_f");
+                        models.push((model_id, frame.map(|kv| kv.1)));
+                    }
+                    shapes.push(models);
+                } else if id == b"XYZI" {
+                    xyzi.push(i32_at(p));
+                }
+                pos += 12 + size;
+            }
+            (shapes, xyzi)
+        }
+
+        /// the _t of the nTRN chunks of a saved file
+        fn read_translations(data: &[u8]) -> Vec<[i32; 3]> {
+            let i32_at = |p: usize| i32::from_le_bytes(data[p..p + 4].try_into().unwrap());
+            let mut translations = vec![];
+            let mut pos = 20;
+            while pos < data.len() {
+                let size = i32_at(pos + 4) as usize;
+                if &data[pos..pos + 4] == b"nTRN" {
+                    // node id, attributes, child id, reserved id, layer id, frames
+                    let mut p = pos + 20;
+                    for _ in 0..i32_at(p - 4) * 2 {
+                        p += 4 + i32_at(p) as usize;
+                    }
+                    // the dict of the first frame
+                    p += 20;
+                    let mut t = [0; 3];
+                    for _ in 0..i32_at(p - 4) {
+                        let key_len = i32_at(p) as usize;
+                        let value_at = p + 4 + key_len;
+                        let value_len = i32_at(value_at) as usize;
+                        let value = &data[value_at + 4..value_at + 4 + value_len];
+                        if &data[p + 4..p + 4 + key_len] == b"_t" {
+                            let v: Vec<i32> = String::from_utf8_lossy(value)
+                                .split(' ')
+                                .map(|v| v.parse().unwrap())
+                                .collect();
+                            t = [v[0], v[1], v[2]];
+                        }
+                        p = value_at + 4 + value_len;
+                    }
+                    translations.push(t);
+                }
+                pos += 12 + size;
+            }
+            translations
+        }
+
+        #[test]
+        fn test_save_split_volume() {
+            // two components moved to nodes at their min corner, then a voxel in the main volume
+            let mut vox = VoxWriter::create_empty();
+            vox.draw_box([-4, 0, 0], [-1, 3, 3], 1, ShapeFill::Solid);
+            vox.draw_box([20, 0, 0], [21, 1, 1], 2, ShapeFill::Solid);
+            vox.split_components(Connectivity::Faces, 1, "part");
+            assert_eq!(vox.get_nodes()[1].offset, [20, 0, 0]);
+            vox.add_voxel(10, 5, 2, 3);
+            vox.add_voxel(11, 5, 2, 3);
+            let file = std::env::temp_dir().join("vox_writer_test_split_volume.vox");
+            vox.save_to_file(file.to_string_lossy().to_string())
+                .unwrap();
+            let data = std::fs::read(&file).unwrap();
+            std::fs::remove_file(&file).unwrap();
+
+            // root, main cube, then the node and its cube for each node
+            let t = read_translations(&data);
+            assert_eq!(t.len(), 6);
+            assert_eq!(t[3], [63; 3]);
+            assert_eq!(t[5], [63; 3]);
+            // the voxel at 10 5 2 is at 10 5 2 of the model of pivot 63, the first voxels of the
+            // nodes are at 0 0 0 of their models, so the world offsets between them are kept
+            let main = [0, 1, 2].map(|i| t[1][i] + [10, 5, 2][i] - 63);
+            assert_eq!([0, 1, 2].map(|i| t[2][i] - main[i]), [-14, -5, -2]);
+            assert_eq!([0, 1, 2].map(|i| t[4][i] - main[i]), [10, -5, -2]);
+        }
+
+        #[test]
+        fn test_save_animated_node() {
+            let mut vox = VoxWriter::create_empty();
+            vox.add_voxel(0, 0, 0, 1);
+            // a cell filled in the frame 0 only, and one filled in the frame 1 only
+            let mut frame0 = VoxWriter::create_empty();
+            frame0.add_voxel(0, 0, 0, 2);
+            let mut frame1 = VoxWriter::create_empty();
+            frame1.add_voxel(200, 0, 0, 3);
+            vox.add_animated_node("anim".to_string(), vec![frame0, frame1], [0; 3], 0);
+            let file = std::env::temp_dir().join("vox_writer_test_animated_node.vox");
+            vox.save_to_file(file.to_string_lossy().to_string())
+                .unwrap();
+            let data = std::fs::read(&file).unwrap();
+            std::fs::remove_file(&file).unwrap();
+
+            let (shapes, xyzi) = read_shapes(&data);
+            // the main cube, the cubes of the 2 frames, then the shared empty model
+            assert_eq!(xyzi, vec![1, 1, 1, 0]);
+            assert_eq!(shapes.len(), 3);
+            assert_eq!(shapes[0], vec![(0, None)]);
+            let f = |i: i32| Some(i.to_string());
+            assert_eq!(shapes[1], vec![(1, f(0)), (3, f(1))]);
+            assert_eq!(shapes[2], vec![(3, f(0)), (2, f(1))]);
+        }
+
+        #[test]
+        fn test_remove_voxel() {
+            let mut vox = VoxWriter::create_empty();
+            vox.add_voxel(0, 0, 0, 1);
+            vox.add_voxel(1, 0, 0, 2);
+            vox.add_voxel(2, 0, 0, 3);
+            assert_eq!(vox.remove_voxel(0, 0, 0), Some(1));
+            assert_eq!(vox.remove_voxel(0, 0, 0), None);
+            // the last voxel moved in the place of the removed one
+            assert_eq!(vox.get_voxel(2, 0, 0), Some(3));
+            assert_eq!(vox.get_voxel(1, 0, 0), Some(2));
+            assert_eq!(vox.count_voxels(), 2);
+            vox.add_voxel(0, 0, 0, 4);
+            assert_eq!(vox.get_voxel(0, 0, 0), Some(4));
+
+            // the empty cubes are dropped and the bounds follow the voxels left
+            vox.add_voxel(500, 0, 0, 5);
+            assert_eq!(vox.cubes.len(), 2);
+            assert_eq!(vox.remove_voxel(500, 0, 0), Some(5));
+            assert_eq!(vox.cubes.len(), 1);
+            assert!(vox.bounds_dirty);
+            assert_eq!(vox.get_max_volume().upper_bound.x, 2.0);
+            vox.add_voxel(-300, 0, 0, 6);
+            assert_eq!(vox.get_voxel(-300, 0, 0), Some(6));
+            assert_eq!(vox.get_voxel(2, 0, 0), Some(3));
+
+            // the bounds follow the removals and the adds until they are made again
+            assert_eq!(vox.remove_voxel(2, 0, 0), Some(3));
+            assert_eq!(vox.remove_voxel(1, 0, 0), Some(2));
+            let volume = vox.get_max_volume();
+            assert_eq!((volume.lower_bound.x, volume.upper_bound.x), (-300.0, 0.0));
+            vox.update_bounds();
+            assert!(!vox.bounds_dirty);
+            assert_eq!(vox.max_volume.upper_bound.x, 0.0);
+        }
     }
     ///////////////////////////////////////////////////////////////////////////////////////////////////
 
@@ -503,7 +752,7 @@ mod vox_writer {
             }
         }
 
-        fn write(&mut self, mut fp: File) -> std::io::Result<()> {
+        fn write(&mut self, mut fp: &File) -> std::io::Result<()> {
             // chunk header
             let id = get_id_char('L', 'A', 'Y', 'R') as i32;
             fp.write(&id.to_le_bytes())?;
@@ -514,7 +763,7 @@ mod vox_writer {
 
             // datas's
             fp.write(&self.node_id.to_le_bytes())?;
-            self.node_attribs.write(&fp)?;
+            self.node_attribs.write(fp)?;
             fp.write(&self.reserved_id.to_le_bytes())?;
             Ok(())
         }
@@ -686,6 +935,8 @@ mod vox_writer {
         fn get(&self, a: &A, b: &B, c: &C) -> Option<&i32>;
 
         fn set(&mut self, a: A, b: B, c: C, v: i32);
+
+        fn remove(&mut self, a: &A, b: &B, c: &C) -> Option<i32>;
     }
 
     struct Table<A: Eq + Hash, B: Eq + Hash, C: Eq + Hash> {
@@ -710,10 +961,45 @@ mod vox_writer {
             let inner_b = inner_a.entry(b).or_insert(HashMap::new());
             inner_b.insert(c, v);
         }
+
+        fn remove(&mut self, a: &A, b: &B, c: &C) -> Option<i32> {
+            let inner_a = self.table.get_mut(a)?;
+            let inner_b = inner_a.get_mut(b)?;
+            let v = inner_b.remove(c);
+            if inner_b.is_empty() {
+                inner_a.remove(b);
+                if inner_a.is_empty() {
+                    self.table.remove(a);
+                }
+            }
+            v
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////////////////////////////////////
+
+    #[doc = "a named volume saved as its own group of models, translated by offset"]
+    pub struct VoxNode {
+        pub name: String,
+        pub offset: [i32; 3],
+        pub layer_id: i32,
+        pub hidden: bool,
+        pub volume: VoxWriter,      // the frame 0 of an animated node
+        pub frames: Vec<VoxWriter>, // the next frames of an animated node
+    }
+
+    #[doc = "a named layer of the scene"]
+    #[derive(Clone, Debug)]
+    pub struct VoxLayer {
+        pub name: String,
+        pub hidden: bool,
     }
 
     ///////////////////////////////////////////////////////////////////////////////////////////////////
 
+    /// the grid pos of a shape of a node, with its (frame, model id)
+    type ShapeModels = ([i32; 3], Vec<(usize, i32)>);
+
     #[doc = "the Vox file format writer"]
     pub struct VoxWriter {
         mv_version: i32,
@@ -723,6 +1009,7 @@ mod vox_writer {
         max_voxel_per_cube_y: i32,
         max_voxel_per_cube_z: i32,
         max_volume: AABBCC,
+        bounds_dirty: bool, // a voxel on max_volume was removed, it is made again when used
         colors: Vec<i32>,
         cubes: Vec<VoxCube>,
         max_cube_id: i32,
@@ -731,6 +1018,8 @@ mod vox_writer {
         min_cube_z: i32,
         cube_id: Table<i32, i32, i32>,
         voxel_id: Table<i32, i32, i32>, // voxel of a cube
+        nodes: Vec<VoxNode>,
+        layers: Vec<VoxLayer>,
     }
 
     #[allow(dead_code)]
@@ -749,10 +1038,13 @@ mod vox_writer {
                 max_voxel_per_cube_y: i32::clamp(limity, 0, 126),
                 max_voxel_per_cube_z: i32::clamp(limitz, 0, 126),
                 max_volume: AABBCC::create(1e7, 0.0),
+                bounds_dirty: false,
                 cube_id: Table::new(),
                 voxel_id: Table::new(),
                 colors: Default::default(),
                 cubes: Default::default(),
+                nodes: Default::default(),
+                layers: Default::default(),
             }
         }
 
@@ -764,6 +1056,22 @@ mod vox_writer {
         #[doc = "will clear the voxels of all cubes"]
         pub fn clear_voxels(&mut self) {
             self.cubes.clear();
+            self.cube_id = Table::new();
+            self.voxel_id = Table::new();
+            self.max_cube_id = 0;
+            self.min_cube_x = 1e7 as i32;
+            self.min_cube_y = 1e7 as i32;
+            self.min_cube_z = 1e7 as i32;
+            self.max_volume = AABBCC::create(1e7, 0.0);
+            self.bounds_dirty = false;
+        }
+
+        /// replace the voxels, the cubes and the bounds are made again
+        fn rebuild(&mut self, voxels: &[([i32; 3], u8)]) {
+            self.clear_voxels();
+            for (p, color) in voxels {
+                self.add_voxel(p[0], p[1], p[2], *color as i32);
+            }
         }
 
         #[doc = "will clear the colors of all voxels of all cubes"]
@@ -793,6 +1101,195 @@ mod vox_writer {
             self.merge_voxel_in_cube(v_x, v_y, v_z, v_color_index as u8, ox, oy, oz);
         }
 
+        #[doc = "remove the voxel at pos x,y,z, return its color index if there was one"]
+        pub fn remove_voxel(&mut self, v_x: i32, v_y: i32, v_z: i32) -> Option<i32> {
+            let cid = *self.voxel_id.get(&v_x, &v_y, &v_z)? as usize;
+            let ox = v_x.div_euclid(self.max_voxel_per_cube_x);
+            let oy = v_y.div_euclid(self.max_voxel_per_cube_y);
+            let oz = v_z.div_euclid(self.max_voxel_per_cube_z);
+            let cube_id = *self.cube_id.get(&ox, &oy, &oz)? as usize;
+            self.voxel_id.remove(&v_x, &v_y, &v_z);
+            let c = self.cubes.get_mut(cube_id)?;
+            let color = c.xyzi.voxels[cid - 1] as i32;
+            // the last voxel of the cube takes the place of the removed one
+            let last = c.xyzi.voxels.len() - 4;
+            if cid - 4 != last {
+                let moved: Vec<u8> = c.xyzi.voxels[last..].to_vec();
+                c.xyzi.voxels[cid - 4..cid].copy_from_slice(&moved);
+                let x = ox * self.max_voxel_per_cube_x + moved[0] as i32;
+                let y = oy * self.max_voxel_per_cube_y + moved[1] as i32;
+                let z = oz * self.max_voxel_per_cube_z + moved[2] as i32;
+                self.voxel_id.set(x, y, z, cid as i32);
+            }
+            c.xyzi.voxels.truncate(last);
+            let empty = c.xyzi.voxels.is_empty();
+            // the bounds only change when the voxel was on them
+            let b = &self.max_volume;
+            let on_bounds = [
+                (v_x as f64, b.lower_bound.x, b.upper_bound.x),
+                (v_y as f64, b.lower_bound.y, b.upper_bound.y),
+                (v_z as f64, b.lower_bound.z, b.upper_bound.z),
+            ]
+            .iter()
+            .any(|(v, low, up)| v == low || v == up);
+            // the bounds are made again when used, so peeling a volume stays linear
+            if empty {
+                self.compact_cubes();
+            } else if on_bounds {
+                self.bounds_dirty = true;
+            }
+            Some(color)
+        }
+
+        /// change the color index of an existing voxel, return false if there is no voxel
+        fn set_voxel_color(&mut self, v_x: i32, v_y: i32, v_z: i32, v_color_index: i32) -> bool {
+            let cid = match self.voxel_id.get(&v_x, &v_y, &v_z) {
+                Some(cid) => *cid as usize,
+                None => return false,
+            };
+            let ox = v_x.div_euclid(self.max_voxel_per_cube_x);
+            let oy = v_y.div_euclid(self.max_voxel_per_cube_y);
+            let oz = v_z.div_euclid(self.max_voxel_per_cube_z);
+            match self.cube_id.get(&ox, &oy, &oz) {
+                Some(cube_id) => {
+                    self.cubes[*cube_id as usize].xyzi.voxels[cid - 1] = v_color_index as u8;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        /// keep the voxels for which f(pos, &mut color_index) is true, the color can be changed
+        fn retain_voxels<F: FnMut([i32; 3], &mut u8) -> bool>(&mut self, mut f: F) {
+            for c in self.cubes.iter_mut() {
+                let bx = c.tx * self.max_voxel_per_cube_x;
+                let by = c.ty * self.max_voxel_per_cube_y;
+                let bz = c.tz * self.max_voxel_per_cube_z;
+                let mut kept = Vec::with_capacity(c.xyzi.voxels.len());
+                for v in c.xyzi.voxels.chunks_exact(4) {
+                    let p = [bx + v[0] as i32, by + v[1] as i32, bz + v[2] as i32];
+                    let mut color = v[3];
+                    if f(p, &mut color) {
+                        kept.extend_from_slice(&[v[0], v[1], v[2], color]);
+                        self.voxel_id.set(p[0], p[1], p[2], kept.len() as i32);
+                    } else {
+                        self.voxel_id.remove(&p[0], &p[1], &p[2]);
+                    }
+                }
+                c.xyzi.voxels = kept;
+            }
+            self.compact_cubes();
+        }
+
+        /// drop the cubes without voxels, the bounds are made again from the voxels left when used
+        fn compact_cubes(&mut self) {
+            if self.cubes.iter().any(|c| c.xyzi.voxels.is_empty()) {
+                self.cubes.retain(|c| !c.xyzi.voxels.is_empty());
+                self.cube_id = Table::new();
+                for (i, c) in self.cubes.iter_mut().enumerate() {
+                    c.cube_id = i as i32;
+                    self.cube_id.set(c.tx, c.ty, c.tz, i as i32);
+                }
+                self.max_cube_id = self.cubes.len() as i32;
+            }
+            self.min_cube_x = 1e7 as i32;
+            self.min_cube_y = 1e7 as i32;
+            self.min_cube_z = 1e7 as i32;
+            for c in &self.cubes {
+                self.min_cube_x = i32::min(self.min_cube_x, c.tx);
+                self.min_cube_y = i32::min(self.min_cube_y, c.ty);
+                self.min_cube_z = i32::min(self.min_cube_z, c.tz);
+            }
+            self.bounds_dirty = true;
+        }
+
+        /// the bounds of the voxels, made from the voxels if a removal changed them
+        fn get_max_volume(&self) -> AABBCC {
+            if !self.bounds_dirty {
+                return self.max_volume.clone();
+            }
+            let mut volume = AABBCC::create(1e7, 0.0);
+            if let Some((min, max)) = self.get_bounds() {
+                for p in [min, max] {
+                    volume.combine(Point3::<f64>::create3(
+                        p[0] as f64,
+                        p[1] as f64,
+                        p[2] as f64,
+                    ));
+                }
+            }
+            volume
+        }
+
+        /// make max_volume again if a removal changed it
+        fn update_bounds(&mut self) {
+            self.max_volume = self.get_max_volume();
+            self.bounds_dirty = false;
+        }
+
+        #[doc = "add a layer and return its id. the layer 0 is kept for the voxels added with \
+                 add_voxel, it is added as main with the first layer. the next ids follow the order of the calls"]
+        pub fn add_layer(&mut self, name: String, hidden: bool) -> i32 {
+            if self.layers.is_empty() {
+                self.layers.push(VoxLayer {
+                    name: "main".to_string(),
+                    hidden: false,
+                });
+            }
+            self.layers.push(VoxLayer { name, hidden });
+            self.layers.len() as i32 - 1
+        }
+
+        #[doc = "get the layers"]
+        pub fn get_layers(&self) -> &[VoxLayer] {
+            &self.layers
+        }
+
+        #[doc = "add a named volume translated by offset, saved as its own group of models. \
+                 the palette of this writer is used for the voxels of all the nodes. return the node index"]
+        pub fn add_node(
+            &mut self,
+            name: String,
+            volume: VoxWriter,
+            offset: [i32; 3],
+            layer_id: i32,
+        ) -> usize {
+            self.nodes.push(VoxNode {
+                name,
+                offset,
+                layer_id,
+                hidden: false,
+                volume,
+                frames: vec![],
+            });
+            self.nodes.len() - 1
+        }
+
+        #[doc = "add a named animation translated by offset, each volume is a frame. see add_node"]
+        pub fn add_animated_node(
+            &mut self,
+            name: String,
+            frames: Vec<VoxWriter>,
+            offset: [i32; 3],
+            layer_id: i32,
+        ) -> usize {
+            let mut frames = frames.into_iter();
+            let volume = frames.next().unwrap_or_else(VoxWriter::create_empty);
+            let index = self.add_node(name, volume, offset, layer_id);
+            self.nodes[index].frames = frames.collect();
+            index
+        }
+
+        #[doc = "get the nodes"]
+        pub fn get_nodes(&self) -> &[VoxNode] {
+            &self.nodes
+        }
+
+        #[doc = "get a node to edit it"]
+        pub fn get_node_mut(&mut self, index: usize) -> Option<&mut VoxNode> {
+            self.nodes.get_mut(index)
+        }
+
         fn get_file_pos(&self, mut v_fp: &File) -> u64 {
             v_fp.seek(SeekFrom::Current(0)).unwrap()
         }
@@ -836,9 +1333,9 @@ mod vox_writer {
 
         fn mod_value(&self, vx: i32, vy: i32, vz: i32) -> Point3<u8> {
             Point3::<u8>::create3(
-                (vx % self.max_voxel_per_cube_x) as u8,
-                (vy % self.max_voxel_per_cube_y) as u8,
-                (vz % self.max_voxel_per_cube_z) as u8,
+                vx.rem_euclid(self.max_voxel_per_cube_x) as u8,
+                vy.rem_euclid(self.max_voxel_per_cube_y) as u8,
+                vz.rem_euclid(self.max_voxel_per_cube_z) as u8,
             )
         }
 
@@ -877,6 +1374,7 @@ mod vox_writer {
 
         #[doc = "save the file tp file_path_name"]
         pub fn save_to_file(&mut self, file_path_name: String) -> std::io::Result<()> {
+            self.update_bounds();
             let mut file = File::create(file_path_name)?;
 
             let zero: i32 = 0;
@@ -892,6 +1390,7 @@ mod vox_writer {
             let header_size = self.get_file_pos(&file);
 
             let count_cubes = self.cubes.len();
+            let count_nodes = self.nodes.len();
 
             let mut node_ids = 0;
             let mut root_transform = Ntrn::create(1);
@@ -899,9 +1398,25 @@ mod vox_writer {
             node_ids += 1;
             root_transform.child_node_id = node_ids;
 
-            let mut root_group = Ngrp::create(count_cubes as i32);
+            let mut root_group = Ngrp::create((count_cubes + count_nodes) as i32);
             root_group.node_id = node_ids; //
-            root_group.node_children_nodes = count_cubes as i32;
+            root_group.node_children_nodes = (count_cubes + count_nodes) as i32;
+
+            // the main volume is centered in x and y and starts at z 0, the nodes are moved the same
+            // way so they keep their place around its voxels
+            let shift = if count_cubes == 0 {
+                [0.0; 3]
+            } else {
+                [
+                    self.min_cube_x as f64 * self.max_voxel_per_cube_x as f64
+                        + self.max_volume.lower_bound.x
+                        + self.max_volume.size().x * 0.5,
+                    self.min_cube_y as f64 * self.max_voxel_per_cube_y as f64
+                        + self.max_volume.lower_bound.y
+                        + self.max_volume.size().y * 0.5,
+                    self.min_cube_z as f64 * self.max_voxel_per_cube_z as f64,
+                ]
+            };
 
             let mut shapes: Vec<Nshp> = vec![];
             let mut shape_transforms: Vec<Ntrn> = vec![];
@@ -918,23 +1433,20 @@ mod vox_writer {
                 trans.child_node_id = node_ids;
                 trans.layer_id = 0;
 
-                c.tx = f64::floor(
-                    (c.tx as f64 - self.min_cube_x as f64 + 0.5) * self.max_voxel_per_cube_x as f64
-                        - self.max_volume.lower_bound.x
-                        - self.max_volume.size().x * 0.5,
-                ) as i32;
-                c.ty = f64::floor(
-                    (c.ty as f64 - self.min_cube_y as f64 + 0.5) * self.max_voxel_per_cube_y as f64
-                        - self.max_volume.lower_bound.y
-                        - self.max_volume.size().y * 0.5,
-                ) as i32;
-                c.tz = f64::floor(
-                    (c.tz as f64 - self.min_cube_z as f64 + 0.5) * self.max_voxel_per_cube_z as f64,
-                ) as i32;
+                // the cube keep its grid pos, so the writer can be saved again or read back
+                let tx =
+                    f64::floor((c.tx as f64 + 0.5) * self.max_voxel_per_cube_x as f64 - shift[0])
+                        as i32;
+                let ty =
+                    f64::floor((c.ty as f64 + 0.5) * self.max_voxel_per_cube_y as f64 - shift[1])
+                        as i32;
+                let tz =
+                    f64::floor((c.tz as f64 + 0.5) * self.max_voxel_per_cube_z as f64 - shift[2])
+                        as i32;
 
                 // not an animation in my case so only first frame frames[0]
 
-                let str = CString::new(format!("{} {} {}", c.tx, c.ty, c.tz)).unwrap();
+                let str = CString::new(format!("{} {} {}", tx, ty, tz)).unwrap();
 
                 trans.frames[0].add(
                     CString::new("_t").expect("Fail to create CString::new"),
@@ -949,6 +1461,118 @@ mod vox_writer {
                 shapes.push(shape);
             }
 
+            // the models of the nodes follow the ones of the main volume. the cubes at the same
+            // grid pos in the frames of a node are the models of a same shape
+            let mut model_id = count_cubes as i32;
+            let mut node_shapes: Vec<Vec<ShapeModels>> = vec![];
+            for node in self.nodes.iter_mut() {
+                let mut grid: Vec<ShapeModels> = vec![];
+                let frames = std::iter::once(&mut node.volume).chain(node.frames.iter_mut());
+                for (f, vol) in frames.enumerate() {
+                    let size = [
+                        vol.max_voxel_per_cube_x,
+                        vol.max_voxel_per_cube_y,
+                        vol.max_voxel_per_cube_z,
+                    ];
+                    for c in vol.cubes.iter_mut() {
+                        c.write(&file)?;
+                        // the models are centered on their pivot, so the voxels keep their pos in the node
+                        let t = [
+                            c.tx * size[0] + size[0] / 2,
+                            c.ty * size[1] + size[1] / 2,
+                            c.tz * size[2] + size[2] / 2,
+                        ];
+                        match grid.iter_mut().find(|g| g.0 == t) {
+                            Some(g) => g.1.push((f, model_id)),
+                            None => grid.push((t, vec![(f, model_id)])),
+                        }
+                        model_id += 1;
+                    }
+                }
+                node_shapes.push(grid);
+            }
+
+            // an empty model shared by the cells of the animated nodes without cube in a frame,
+            // else magicavoxel keeps showing the model of the previous frame
+            let empty_model_id = model_id;
+            let need_empty_model = self.nodes.iter().zip(&node_shapes).any(|(node, grid)| {
+                grid.iter()
+                    .any(|(_, models)| models.len() < node.frames.len() + 1)
+            });
+            if need_empty_model {
+                let mut empty = VoxCube::create_empty();
+                empty.size.size_x = 1;
+                empty.size.size_y = 1;
+                empty.size.size_z = 1;
+                empty.write(&file)?;
+            }
+
+            // node : trn -> grp -> (trn -> shp) per cube
+            let mut node_groups: Vec<(Ntrn, Ngrp)> = vec![];
+            for (n, node) in self.nodes.iter().enumerate() {
+                let mut trans = Ntrn::create(1);
+                node_ids += 1;
+                trans.node_id = node_ids;
+                root_group.child_nodes[count_cubes + n] = node_ids;
+                node_ids += 1;
+                trans.child_node_id = node_ids;
+                trans.layer_id = node.layer_id;
+                trans.node_attribs.add(
+                    CString::new("_name").expect("Fail to create CString::new"),
+                    CString::new(node.name.replace('\0', "")).unwrap_or_default(),
+                );
+                if node.hidden {
+                    trans.node_attribs.add(
+                        CString::new("_hidden").expect("Fail to create CString::new"),
+                        CString::new("1").expect("Fail to create CString::new"),
+                    );
+                }
+                let t = [0, 1, 2].map(|i| f64::floor(node.offset[i] as f64 - shift[i]) as i32);
+                let str = CString::new(format!("{} {} {}", t[0], t[1], t[2])).unwrap();
+                trans.frames[0].add(
+                    CString::new("_t").expect("Fail to create CString::new"),
+                    str,
+                );
+
+                let count_frames = node.frames.len() + 1;
+                let mut group = Ngrp::create(node_shapes[n].len() as i32);
+                group.node_id = node_ids;
+                for (i, (t, models)) in node_shapes[n].iter().enumerate() {
+                    let mut cube_trans = Ntrn::create(1);
+                    node_ids += 1;
+                    cube_trans.node_id = node_ids;
+                    group.child_nodes[i] = node_ids;
+                    node_ids += 1;
+                    cube_trans.child_node_id = node_ids;
+                    cube_trans.layer_id = node.layer_id;
+                    let str = CString::new(format!("{} {} {}", t[0], t[1], t[2])).unwrap();
+                    cube_trans.frames[0].add(
+                        CString::new("_t").expect("Fail to create CString::new"),
+                        str,
+                    );
+                    shape_transforms.push(cube_trans);
+
+                    // one model per frame, the frame index is in the _f of the model
+                    // every frame has a model, so the cell is empty in the frames without cube
+                    let mut shape = Nshp::create(count_frames as i32);
+                    shape.node_id = node_ids;
+                    for f in 0..count_frames {
+                        shape.models[f].model_id = match models.iter().find(|m| m.0 == f) {
+                            Some(m) => m.1,
+                            None => empty_model_id,
+                        };
+                        if count_frames > 1 {
+                            shape.models[f].model_attribs.add(
+                                CString::new("_f").expect("Fail to create CString::new"),
+                                CString::new(f.to_string()).unwrap(),
+                            );
+                        }
+                    }
+                    shapes.push(shape);
+                }
+                node_groups.push((trans, group));
+            }
+
             root_transform.write(&file)?;
             root_group.write(&file)?;
 
@@ -958,16 +1582,34 @@ mod vox_writer {
                 shapes[i].write(&file)?;
             }
 
-            // no layr in my cases
+            let mut next_shape = count_cubes;
+            for (n, (trans, group)) in node_groups.iter_mut().enumerate() {
+                trans.write(&file)?;
+                group.write(&file)?;
+                for _ in 0..node_shapes[n].len() {
+                    shape_transforms[next_shape].write(&file)?;
+                    shapes[next_shape].write(&file)?;
+                    next_shape += 1;
+                }
+            }
 
-            // layr
-            /*for (int i = 0; i < 8; i++)
-            {
-                LAYR layr;
-                layr.node_id = i;
-                layr.node_attribs.Add("_name", ct::toStr(i));
-                layr.write(m_File);
-            }*/
+            // layr, only if some layers were added
+            for (i, layer) in self.layers.iter().enumerate() {
+                let mut layr = LAYR::create_empty();
+                layr.node_id = i as i32;
+                layr.node_attribs.add(
+                    CString::new("_name").expect("Fail to create CString::new"),
+                    CString::new(layer.name.replace('\0', "")).unwrap_or_default(),
+                );
+                if layer.hidden {
+                    layr.node_attribs.add(
+                        CString::new("_hidden").expect("Fail to create CString::new"),
+                        CString::new("1").expect("Fail to create CString::new"),
+                    );
+                }
+                layr.reserved_id = -1;
+                layr.write(&file)?;
+            }
 
             // RGBA Palette
             if self.colors.len() > 0 {
@@ -993,16 +1635,73 @@ mod vox_writer {
             Ok(())
         }
 
+        #[doc = "get the color index of the voxel at pos x,y,z, if any"]
+        pub fn get_voxel(&self, v_x: i32, v_y: i32, v_z: i32) -> Option<i32> {
+            let cid = *self.voxel_id.get(&v_x, &v_y, &v_z)? as usize;
+            let ox = v_x.div_euclid(self.max_voxel_per_cube_x);
+            let oy = v_y.div_euclid(self.max_voxel_per_cube_y);
+            let oz = v_z.div_euclid(self.max_voxel_per_cube_z);
+            let cube_id = *self.cube_id.get(&ox, &oy, &oz)? as usize;
+            // cid is the len of the voxels of the cube after the push of this voxel
+            let c = self.cubes.get(cube_id)?;
+            Some(*c.xyzi.voxels.get(cid - 1)? as i32)
+        }
+
+        #[doc = "call f(x, y, z, color_index) for each voxel"]
+        pub fn for_each_voxel<F: FnMut(i32, i32, i32, i32)>(&self, mut f: F) {
+            for c in &self.cubes {
+                let bx = c.tx * self.max_voxel_per_cube_x;
+                let by = c.ty * self.max_voxel_per_cube_y;
+                let bz = c.tz * self.max_voxel_per_cube_z;
+                for v in c.xyzi.voxels.chunks_exact(4) {
+                    f(
+                        bx + v[0] as i32,
+                        by + v[1] as i32,
+                        bz + v[2] as i32,
+                        v[3] as i32,
+                    );
+                }
+            }
+        }
+
+        #[doc = "get the count of voxels of all cubes"]
+        pub fn count_voxels(&self) -> u64 {
+            self.cubes
+                .iter()
+                .map(|c| c.xyzi.get_num_voxels() as u64)
+                .sum()
+        }
+
+        #[doc = "get the min and max pos of the voxels, if any"]
+        pub fn get_bounds(&self) -> Option<([i32; 3], [i32; 3])> {
+            let mut bounds: Option<([i32; 3], [i32; 3])> = None;
+            self.for_each_voxel(|x, y, z, _| {
+                let p = [x, y, z];
+                bounds = Some(match bounds {
+                    None => (p, p),
+                    Some((mut min, mut max)) => {
+                        for i in 0..3 {
+                            min[i] = i32::min(min[i], p[i]);
+                            max[i] = i32::max(max[i], p[i]);
+                        }
+                        (min, max)
+                    }
+                });
+            });
+            bounds
+        }
+
         #[doc = "print some stats"]
         pub fn print_stats(&self) {
             println!("---- Stats -----");
             let count_cubes = self.cubes.len();
             println!("count cubes : {}", count_cubes);
+            let volume = self.get_max_volume();
             println!(
                 "Volume : {} x {} x {}",
-                self.max_volume.size().x,
-                self.max_volume.size().y,
-                self.max_volume.size().z
+                volume.size().x,
+                volume.size().y,
+                volume.size().z
             );
             let mut count_voxels: u64 = 0;
             for i in 0..count_cubes {
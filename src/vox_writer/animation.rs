@@ -0,0 +1,144 @@
+use super::gif::decode_gif;
+use super::png::decode_png;
+use super::sprite::{sprite_voxels, SpriteOptions};
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "add an animated node of the extruded rgba frames, all of width x height pixels. \
+             the colors of all the frames are mapped to the palette at once. return the node index"]
+    pub fn add_sprite_animation(
+        &mut self,
+        name: String,
+        width: usize,
+        height: usize,
+        frames: &[Vec<u8>],
+        offset: [i32; 3],
+        options: &SpriteOptions,
+    ) -> usize {
+        let voxels: Vec<Vec<([i32; 3], [u8; 3])>> = frames
+            .iter()
+            .map(|rgba| sprite_voxels(width, height, rgba, options))
+            .collect();
+        let colors: Vec<[u8; 3]> = voxels.iter().flatten().map(|v| v.1).collect();
+        let indices = self.map_colors_rgb(&colors);
+        let mut next = 0;
+        let mut volumes = vec![];
+        for frame in &voxels {
+            let mut volume = VoxWriter::create_empty();
+            for (p, _) in frame {
+                volume.add_voxel(p[0], p[1], p[2], indices[next]);
+                next += 1;
+            }
+            volumes.push(volume);
+        }
+        self.add_animated_node(name, volumes, offset, 0)
+    }
+
+    #[doc = "add an animated node of the extruded frames of a gif, see add_sprite_animation. \
+             the delays of the frames are not kept, magicavoxel plays all the frames at the same speed"]
+    pub fn add_gif_animation(
+        &mut self,
+        name: String,
+        gif_data: &[u8],
+        offset: [i32; 3],
+        options: &SpriteOptions,
+    ) -> std::io::Result<usize> {
+        let gif = decode_gif(gif_data)?;
+        if gif.frames.is_empty() {
+            return Err(invalid_data("gif: no frame"));
+        }
+        Ok(self.add_sprite_animation(name, gif.width, gif.height, &gif.frames, offset, options))
+    }
+
+    #[doc = "add an animated node of the extruded frames of a png sprite sheet, see add_sprite_animation. \
+             the frames are read from left to right, then from top to bottom. \
+             frame_size is the width and height of a frame, frame_count limits the count of frames, \
+             0 for all the cells of the sheet"]
+    pub fn add_sprite_sheet_animation(
+        &mut self,
+        name: String,
+        png_data: &[u8],
+        frame_size: [usize; 2],
+        frame_count: usize,
+        offset: [i32; 3],
+        options: &SpriteOptions,
+    ) -> std::io::Result<usize> {
+        let img = decode_png(png_data)?;
+        let [frame_width, frame_height] = frame_size;
+        if frame_width == 0 || frame_height == 0 {
+            return Err(invalid_data("sprite sheet: bad frame size"));
+        }
+        let columns = img.width / frame_width;
+        let rows = img.height / frame_height;
+        let mut count = columns * rows;
+        if frame_count > 0 {
+            count = usize::min(count, frame_count);
+        }
+        if count == 0 {
+            return Err(invalid_data("sprite sheet: no frame"));
+        }
+        let mut frames = vec![];
+        for f in 0..count {
+            let x0 = (f % columns) * frame_width;
+            let y0 = (f / columns) * frame_height;
+            let mut rgba = Vec::with_capacity(frame_width * frame_height * 4);
+            for y in y0..y0 + frame_height {
+                for x in x0..x0 + frame_width {
+                    rgba.extend_from_slice(&img.get_rgba(x, y));
+                }
+            }
+            frames.push(rgba);
+        }
+        Ok(self.add_sprite_animation(name, frame_width, frame_height, &frames, offset, options))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::gif::tests::encode_test_gif;
+    use super::super::png::encode_png_rgba;
+    use super::*;
+
+    #[test]
+    fn test_gif_animation() {
+        let colors = [[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        let data = encode_test_gif(
+            2,
+            2,
+            &colors,
+            &[(0, 0, 2, 2, vec![1, 0, 2, 3]), (1, 0, 1, 1, vec![3])],
+        );
+        let mut vox = VoxWriter::create_empty();
+        let index = vox
+            .add_gif_animation("anim".to_string(), &data, [0; 3], &SpriteOptions::create(2))
+            .unwrap();
+        let node = &vox.get_nodes()[index];
+        assert_eq!(node.volume.count_voxels(), 3 * 2);
+        assert_eq!(node.frames.len(), 1);
+        assert_eq!(node.frames[0].count_voxels(), 4 * 2);
+        // the top right pixel of the second frame
+        let c = node.frames[0].get_voxel(1, 1, 1).unwrap();
+        assert_eq!(vox.get_palette_color(c as u8), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_sprite_sheet_animation() {
+        // 3 frames of 1x1 pixel, the last one is transparent
+        let rgba = [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 0, 0];
+        let png = encode_png_rgba(3, 1, &rgba);
+        let mut vox = VoxWriter::create_empty();
+        let options = SpriteOptions::create(1);
+        let index = vox
+            .add_sprite_sheet_animation("sheet".to_string(), &png, [1, 1], 0, [0; 3], &options)
+            .unwrap();
+        let node = &vox.get_nodes()[index];
+        assert_eq!(node.frames.len(), 2);
+        assert_eq!(node.frames[1].count_voxels(), 0);
+        let c = node.frames[0].get_voxel(0, 0, 0).unwrap();
+        assert_eq!(vox.get_palette_color(c as u8), [0, 255, 0, 255]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use super::flood::{BitGrid, NEIGHBOURS_6};
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "the light baked in the colors of the voxels"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BakeLighting {
+    // the empty part of the half spheres of this radius in front of the visible faces
+    AmbientOcclusion(i32),
+    // the voxels with another voxel toward the light, up to the distance, are in the shadow
+    Directional([f64; 3], i32),
+}
+
+#[doc = "the options of the light baking"]
+#[derive(Clone, Copy, Debug)]
+pub struct BakeOptions {
+    pub lighting: BakeLighting,
+    pub levels: i32, // count of shades per color, the last one is the color itself
+    pub min_brightness: f64, // brightness of the darkest shade, in [0:1]
+}
+
+impl BakeOptions {
+    #[doc = "4 shades per color, down to the half of the brightness"]
+    pub fn create(lighting: BakeLighting) -> Self {
+        Self {
+            lighting,
+            levels: 4,
+            min_brightness: 0.5,
+        }
+    }
+}
+
+/// the light of a voxel in [0:1], hemispheres are the offsets of the sphere in front of each face
+fn get_light(
+    solid: &BitGrid,
+    p: [i32; 3],
+    lighting: BakeLighting,
+    hemispheres: &[Vec<[i32; 3]>],
+) -> f64 {
+    let get = |d: &[i32; 3]| solid.get([p[0] + d[0], p[1] + d[1], p[2] + d[2]]);
+    match lighting {
+        BakeLighting::AmbientOcclusion(_) => {
+            // the mean of the empty part of the hemispheres of the visible faces
+            let mut sum = 0.0;
+            let mut faces = 0;
+            for (n, hemisphere) in NEIGHBOURS_6.iter().zip(hemispheres) {
+                if get(n) || hemisphere.is_empty() {
+                    continue;
+                }
+                let empty = hemisphere.iter().filter(|d| !get(d)).count();
+                sum += empty as f64 / hemisphere.len() as f64;
+                faces += 1;
+            }
+            if faces == 0 {
+                1.0
+            } else {
+                sum / faces as f64
+            }
+        }
+        BakeLighting::Directional(dir, distance) => {
+            let len = f64::sqrt(dir[0] * dir[0] + dir[1] * dir[1] + dir[2] * dir[2]);
+            if len == 0.0 {
+                return 1.0;
+            }
+            // steps of less than a voxel along the ray toward the light
+            let step = dir.map(|v| v / len * 0.5);
+            let mut last = p;
+            for k in 1..=2 * distance {
+                let q = [0, 1, 2].map(|i| p[i] + f64::round(step[i] * k as f64) as i32);
+                if q != last && solid.get(q) {
+                    return 0.0;
+                }
+                last = q;
+            }
+            1.0
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "darken the colors of the voxels of the main volume by their light, with levels shades per \
+             color. the shades are added to the palette, or quantized to the nearest colors if there \
+             is not enough free slots. return the count of voxels changed"]
+    pub fn bake_lighting(&mut self, options: &BakeOptions) -> usize {
+        let levels = options.levels.max(2);
+        let radius = match options.lighting {
+            BakeLighting::AmbientOcclusion(r) => r.max(1),
+            BakeLighting::Directional(_, d) => d.max(1),
+        };
+        let (solid, _, _) = match self.get_occupancy(radius) {
+            Some(g) => g,
+            None => return 0,
+        };
+        let mut hemispheres = vec![vec![]; 6];
+        if let BakeLighting::AmbientOcclusion(_) = options.lighting {
+            for z in -radius..=radius {
+                for y in -radius..=radius {
+                    for x in -radius..=radius {
+                        if x * x + y * y + z * z > radius * radius {
+                            continue;
+                        }
+                        for (n, hemisphere) in NEIGHBOURS_6.iter().zip(hemispheres.iter_mut()) {
+                            if x * n[0] + y * n[1] + z * n[2] > 0 {
+                                hemisphere.push([x, y, z]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // the shade level of the visible voxels
+        let mut shades: HashMap<[i32; 3], i32> = HashMap::new();
+        self.for_each_voxel(|x, y, z, _| {
+            let p = [x, y, z];
+            let visible = NEIGHBOURS_6
+                .iter()
+                .any(|d| !solid.get([p[0] + d[0], p[1] + d[1], p[2] + d[2]]));
+            if !visible {
+                return;
+            }
+            let light = get_light(&solid, p, options.lighting, &hemispheres);
+            let level = f64::round(light * (levels - 1) as f64) as i32;
+            if level < levels - 1 {
+                shades.insert(p, level);
+            }
+        });
+
+        // one palette color per shaded color
+        self.copy_default_palette();
+        let mut keys: Vec<(u8, i32)> = vec![];
+        for (p, level) in &shades {
+            if let Some(c) = self.get_voxel(p[0], p[1], p[2]) {
+                keys.push((c as u8, *level));
+            }
+        }
+        keys.sort();
+        keys.dedup();
+        let rgb: Vec<[u8; 3]> = keys
+            .iter()
+            .map(|(c, level)| {
+                let f = options.min_brightness
+                    + (1.0 - options.min_brightness) * *level as f64 / (levels - 1) as f64;
+                let c = self.get_palette_color(*c);
+                [0, 1, 2].map(|i| f64::round(c[i] as f64 * f.clamp(0.0, 1.0)) as u8)
+            })
+            .collect();
+        let indices = self.map_colors_rgb(&rgb);
+        let mapping: HashMap<(u8, i32), i32> = keys.into_iter().zip(indices).collect();
+
+        let mut count = 0;
+        self.retain_voxels(|p, color| {
+            if let Some(level) = shades.get(&p) {
+                if let Some(c) = mapping.get(&(*color, *level)) {
+                    if *c as u8 != *color {
+                        *color = *c as u8;
+                        count += 1;
+                    }
+                }
+            }
+            true
+        });
+        count
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::ShapeFill;
+    use super::*;
+
+    #[test]
+    fn test_bake_ambient_occlusion() {
+        let mut vox = VoxWriter::create_empty();
+        vox.add_color(200, 100, 40, 255, 0);
+        // a floor with a wall, the voxels in the corner get darker
+        vox.draw_box([0, 0, -1], [9, 9, 0], 1, ShapeFill::Solid);
+        vox.draw_box([0, 0, 1], [0, 9, 5], 1, ShapeFill::Solid);
+        let options = BakeOptions::create(BakeLighting::AmbientOcclusion(2));
+        assert!(vox.bake_lighting(&options) > 0);
+        assert_eq!(vox.get_voxel(5, 5, 0), Some(1));
+        let corner = vox.get_voxel(1, 5, 0).unwrap();
+        assert_ne!(corner, 1);
+        let c = vox.get_palette_color(corner as u8);
+        assert!(c[0] < 200 && c[0] >= 100);
+        assert_eq!(c[0] as i32 * 10 / c[1] as i32, 20);
+    }
+
+    #[test]
+    fn test_bake_directional() {
+        let mut vox = VoxWriter::create_empty();
+        // a roof over a floor, with the default palette
+        vox.draw_box([0, 0, 0], [9, 9, 0], 10, ShapeFill::Solid);
+        vox.draw_box([0, 0, 5], [4, 9, 5], 10, ShapeFill::Solid);
+        let mut options = BakeOptions::create(BakeLighting::Directional([0.0, 0.0, 1.0], 20));
+        options.levels = 2;
+        vox.bake_lighting(&options);
+        assert_eq!(vox.get_voxel(7, 5, 0), Some(10));
+        let shadow = vox.get_voxel(2, 5, 0).unwrap();
+        assert_ne!(shadow, 10);
+        // the default colors are kept
+        let light = vox.get_palette_color(10);
+        let dark = vox.get_palette_color(shadow as u8);
+        assert_eq!(
+            [dark[0], dark[1], dark[2]],
+            [0, 1, 2].map(|i| f64::round(light[i] as f64 * 0.5) as u8)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a binvox occupancy grid, y is up. the world pos of a voxel is translate + (pos + 0.5) / dim * scale"]
+#[derive(Clone, Debug)]
+pub struct Binvox {
+    pub dims: [usize; 3],
+    pub translate: [f64; 3],
+    pub scale: f64,
+    pub voxels: Vec<bool>, // (x * dim z + z) * dim y + y, the y run first
+}
+
+impl Binvox {
+    #[doc = "create an empty grid of unit voxels"]
+    pub fn create(dims: [usize; 3]) -> Self {
+        Self {
+            dims,
+            translate: [0.0; 3],
+            scale: dims.iter().copied().max().unwrap_or(0) as f64,
+            voxels: vec![false; dims[0] * dims[1] * dims[2]],
+        }
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (x * self.dims[2] + z) * self.dims[1] + y
+    }
+
+    #[doc = "get the occupancy of the voxel x,y,z"]
+    pub fn get(&self, x: usize, y: usize, z: usize) -> bool {
+        self.voxels[self.index(x, y, z)]
+    }
+
+    #[doc = "set the occupancy of the voxel x,y,z"]
+    pub fn set(&mut self, x: usize, y: usize, z: usize, filled: bool) {
+        let i = self.index(x, y, z);
+        self.voxels[i] = filled;
+    }
+
+    #[doc = "load a .binvox file"]
+    pub fn load_from_file(file_path_name: String) -> std::io::Result<Self> {
+        let file = File::open(file_path_name)?;
+        Self::read(BufReader::new(file))
+    }
+
+    #[doc = "read a binvox grid"]
+    pub fn read<R: BufRead>(mut reader: R) -> std::io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#binvox") {
+            return Err(invalid_data("binvox: bad magic"));
+        }
+        let mut dims: Option<[usize; 3]> = None;
+        let mut translate = [0.0; 3];
+        let mut scale = 1.0;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("binvox: no data"));
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let parse = |i: usize| -> std::io::Result<f64> {
+                words
+                    .get(i)
+                    .and_then(|w| w.parse::<f64>().ok())
+                    .ok_or_else(|| invalid_data("binvox: bad header value"))
+            };
+            match words.first().copied() {
+                Some("dim") => {
+                    let d = [parse(1)?, parse(2)?, parse(3)?];
+                    if d.iter().any(|v| *v < 0.0 || *v > 4096.0) {
+                        return Err(invalid_data("binvox: bad dims"));
+                    }
+                    dims = Some(d.map(|v| v as usize));
+                }
+                Some("translate") => translate = [parse(1)?, parse(2)?, parse(3)?],
+                Some("scale") => scale = parse(1)?,
+                Some("data") => break,
+                _ => {}
+            }
+        }
+        let dims = dims.ok_or_else(|| invalid_data("binvox: no dims"))?;
+        let mut data: Vec<u8> = vec![];
+        reader.read_to_end(&mut data)?;
+        let count = dims[0] * dims[1] * dims[2];
+        // the runs give at most 255 voxels per pair, the dims are not trusted for the allocation
+        if count > data.len() / 2 * 255 {
+            return Err(invalid_data("binvox: not enough data"));
+        }
+        let mut voxels = Vec::with_capacity(count);
+        for pair in data.chunks_exact(2) {
+            if voxels.len() + pair[1] as usize > count {
+                return Err(invalid_data("binvox: too much data"));
+            }
+            voxels.extend(std::iter::repeat(pair[0] != 0).take(pair[1] as usize));
+            if voxels.len() == count {
+                break;
+            }
+        }
+        if voxels.len() < count {
+            return Err(invalid_data("binvox: not enough data"));
+        }
+        Ok(Self {
+            dims,
+            translate,
+            scale,
+            voxels,
+        })
+    }
+
+    #[doc = "save the grid to a .binvox file"]
+    pub fn save_to_file(&self, file_path_name: String) -> std::io::Result<()> {
+        let file = File::create(file_path_name)?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    #[doc = "write the grid in the binvox format"]
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write!(
+            writer,
+            "#binvox 1\ndim {} {} {}\ntranslate {} {} {}\nscale {}\ndata\n",
+            self.dims[0],
+            self.dims[1],
+            self.dims[2],
+            self.translate[0],
+            self.translate[1],
+            self.translate[2],
+            self.scale
+        )?;
+        let mut data: Vec<u8> = vec![];
+        let mut i = 0;
+        while i < self.voxels.len() {
+            let v = self.voxels[i];
+            let mut run = 1;
+            while run < 255 && i + run < self.voxels.len() && self.voxels[i + run] == v {
+                run += 1;
+            }
+            data.push(v as u8);
+            data.push(run as u8);
+            i += run;
+        }
+        writer.write_all(&data)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "add the filled voxels of a binvox grid with a color index. the y up of binvox become \
+             the z up of magicavoxel, and the z of binvox the -y. the grid is moved by translate, \
+             counted in voxels of scale / the longest dim. return the count of voxels added"]
+    pub fn add_binvox(&mut self, binvox: &Binvox, color_index: i32) -> usize {
+        let longest = binvox.dims.iter().copied().max().unwrap_or(0) as f64;
+        let unit = binvox.scale / longest;
+        let offset = binvox.translate.map(|t| {
+            let v = (t / unit).round();
+            if v.is_finite() {
+                v as i32
+            } else {
+                0
+            }
+        });
+        let mut count = 0;
+        for x in 0..binvox.dims[0] {
+            for z in 0..binvox.dims[2] {
+                for y in 0..binvox.dims[1] {
+                    if binvox.get(x, y, z) {
+                        // the voxel from z to z + 1 of binvox is from -z - 1 to -z in y
+                        let vy = -(offset[2] + z as i32 + 1);
+                        self.add_voxel(offset[0] + x as i32, vy, offset[1] + y as i32, color_index);
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    #[doc = "get a cubic binvox grid of the voxels, with a scale of one unit per voxel and the min \
+             corner in translate. the grid is a cube of the longest side of the bounds, so it takes \
+             one byte per voxel of that cube, whatever the other sides"]
+    pub fn to_binvox(&self) -> Binvox {
+        let (min, max) = match self.get_bounds() {
+            Some(b) => b,
+            None => return Binvox::create([0; 3]),
+        };
+        let dim = (0..3).map(|i| max[i] - min[i] + 1).max().unwrap() as usize;
+        let mut binvox = Binvox::create([dim; 3]);
+        binvox.translate = [min[0] as f64, min[2] as f64, -(min[1] + dim as i32) as f64];
+        // the grid is cubic, so the vox y is mapped from the end of the z axis
+        self.for_each_voxel(|x, y, z, _| {
+            binvox.set(
+                (x - min[0]) as usize,
+                (z - min[2]) as usize,
+                dim - 1 - (y - min[1]) as usize,
+                true,
+            );
+        });
+        binvox
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_binvox_round_trip() {
+        let mut vox = VoxWriter::create_empty();
+        for x in 0..300 {
+            vox.add_voxel(x, 0, 0, 1);
+        }
+        vox.add_voxel(0, 2, 1, 1);
+        let binvox = vox.to_binvox();
+        assert_eq!(binvox.dims, [300; 3]);
+        assert!(binvox.get(0, 1, 297));
+
+        let mut data: Vec<u8> = vec![];
+        binvox.write(&mut data).unwrap();
+        assert!(data.starts_with(b"#binvox 1\ndim 300 300 300\n"));
+        let read = Binvox::read(Cursor::new(data)).unwrap();
+        assert_eq!(read.voxels, binvox.voxels);
+
+        let mut copy = VoxWriter::create_empty();
+        assert_eq!(copy.add_binvox(&read, 5), 301);
+        assert_eq!(copy.get_voxel(299, 0, 0), Some(5));
+        assert_eq!(copy.get_voxel(0, 2, 1), Some(5));
+
+        // the position is kept by translate
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(-7, 12, 3, 1);
+        vox.add_voxel(-5, 13, 3, 1);
+        let mut data: Vec<u8> = vec![];
+        vox.to_binvox().write(&mut data).unwrap();
+        let mut copy = VoxWriter::create_empty();
+        copy.add_binvox(&Binvox::read(Cursor::new(data)).unwrap(), 1);
+        assert_eq!(copy.get_bounds(), Some(([-7, 12, 3], [-5, 13, 3])));
+        assert_eq!(copy.get_voxel(-5, 13, 3), Some(1));
+        assert_eq!(copy.count_voxels(), 2);
+    }
+
+    #[test]
+    fn test_read_binvox_bad_runs() {
+        // the dims are far over the data
+        let data = b"#binvox 1\ndim 4096 4096 4096\ndata\n\x01\x01".to_vec();
+        assert!(Binvox::read(Cursor::new(data)).is_err());
+        // the runs go over the count, or stop before it
+        let data = b"#binvox 1\ndim 2 2 2\ndata\n\x01\x05\x00\x04".to_vec();
+        assert!(Binvox::read(Cursor::new(data)).is_err());
+        let data = b"#binvox 1\ndim 2 2 2\ndata\n\x01\x05\x00\x02".to_vec();
+        assert!(Binvox::read(Cursor::new(data)).is_err());
+        let data = b"#binvox 1\ndim 2 2 2\ndata\n\x01\x05\x00\x03".to_vec();
+        assert_eq!(Binvox::read(Cursor::new(data)).unwrap().voxels.len(), 8);
+    }
+}
@@ -0,0 +1,181 @@
+use std::cmp::Reverse;
+use std::collections::{HashSet, VecDeque};
+
+use super::flood::BitGrid;
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "the neighbours of a voxel"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Connectivity {
+    Faces,   // the 6 voxels sharing a face
+    Edges,   // the 18 voxels sharing a face or an edge
+    Corners, // the 26 voxels sharing a face, an edge or a corner
+}
+
+impl Connectivity {
+    /// the offsets of the neighbours
+    pub(crate) fn get_offsets(self) -> Vec<[i32; 3]> {
+        let max = match self {
+            Connectivity::Faces => 1,
+            Connectivity::Edges => 2,
+            Connectivity::Corners => 3,
+        };
+        let mut offsets = vec![];
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let n = i32::abs(x) + i32::abs(y) + i32::abs(z);
+                    if n > 0 && n <= max {
+                        offsets.push([x, y, z]);
+                    }
+                }
+            }
+        }
+        offsets
+    }
+}
+
+#[doc = "a set of connected voxels"]
+#[derive(Clone, Debug)]
+pub struct VoxComponent {
+    pub min: [i32; 3],
+    pub max: [i32; 3],
+    pub voxels: Vec<[i32; 3]>,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "get the connected sets of voxels of the main volume, from the biggest to the smallest"]
+    pub fn get_components(&self, connectivity: Connectivity) -> Vec<VoxComponent> {
+        let (solid, min, max) = match self.get_occupancy(0) {
+            Some(g) => g,
+            None => return vec![],
+        };
+        let offsets = connectivity.get_offsets();
+        let mut visited = BitGrid::create(min, max);
+        let mut components = vec![];
+        self.for_each_voxel(|x, y, z, _| {
+            let start = [x, y, z];
+            if visited.get(start) {
+                return;
+            }
+            visited.set(start, true);
+            let mut component = VoxComponent {
+                min: start,
+                max: start,
+                voxels: vec![],
+            };
+            let mut queue = VecDeque::from([start]);
+            while let Some(p) = queue.pop_front() {
+                for (i, v) in p.iter().enumerate() {
+                    component.min[i] = i32::min(component.min[i], *v);
+                    component.max[i] = i32::max(component.max[i], *v);
+                }
+                component.voxels.push(p);
+                for d in &offsets {
+                    let n = [p[0] + d[0], p[1] + d[1], p[2] + d[2]];
+                    if solid.get(n) && !visited.get(n) {
+                        visited.set(n, true);
+                        queue.push_back(n);
+                    }
+                }
+            }
+            components.push(component);
+        });
+        components.sort_by_key(|c| Reverse(c.voxels.len()));
+        components
+    }
+
+    #[doc = "remove the components of less than min_size voxels. return the count of voxels removed"]
+    pub fn remove_small_components(
+        &mut self,
+        connectivity: Connectivity,
+        min_size: usize,
+    ) -> usize {
+        let removed: HashSet<[i32; 3]> = self
+            .get_components(connectivity)
+            .into_iter()
+            .filter(|c| c.voxels.len() < min_size)
+            .flat_map(|c| c.voxels)
+            .collect();
+        if !removed.is_empty() {
+            self.retain_voxels(|p, _| !removed.contains(&p));
+        }
+        removed.len()
+    }
+
+    #[doc = "move each component of at least min_size voxels of the main volume into its own node, \
+             named prefix followed by its rank by size and translated to its min corner. \
+             the components of less than min_size voxels are deleted, the main volume ends empty. \
+             return the node indices"]
+    pub fn split_components(
+        &mut self,
+        connectivity: Connectivity,
+        min_size: usize,
+        prefix: &str,
+    ) -> Vec<usize> {
+        let components = self.get_components(connectivity);
+        let mut indices = vec![];
+        for (rank, c) in components.iter().enumerate() {
+            if c.voxels.len() < min_size {
+                break;
+            }
+            let mut volume = VoxWriter::create(
+                self.max_voxel_per_cube_x,
+                self.max_voxel_per_cube_y,
+                self.max_voxel_per_cube_z,
+            );
+            for p in &c.voxels {
+                if let Some(color) = self.get_voxel(p[0], p[1], p[2]) {
+                    volume.add_voxel(p[0] - c.min[0], p[1] - c.min[1], p[2] - c.min[2], color);
+                }
+            }
+            indices.push(self.add_node(format!("{}{}", prefix, rank), volume, c.min, 0));
+        }
+        self.clear_voxels();
+        indices
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_components() {
+        let mut vox = VoxWriter::create_empty();
+        // two voxels touching by an edge, one by a corner, and an isolated line
+        vox.add_voxel(0, 0, 0, 1);
+        vox.add_voxel(1, 1, 0, 1);
+        vox.add_voxel(2, 2, 1, 1);
+        vox.draw_line([10, 0, 0], [10, 0, 3], 2);
+        let sizes = |vox: &VoxWriter, c| {
+            vox.get_components(c)
+                .iter()
+                .map(|c| c.voxels.len())
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(sizes(&vox, Connectivity::Faces), vec![4, 1, 1, 1]);
+        assert_eq!(sizes(&vox, Connectivity::Edges), vec![4, 2, 1]);
+        assert_eq!(sizes(&vox, Connectivity::Corners), vec![4, 3]);
+        let c = &vox.get_components(Connectivity::Corners)[1];
+        assert_eq!((c.min, c.max), ([0, 0, 0], [2, 2, 1]));
+
+        let indices = vox.split_components(Connectivity::Edges, 2, "part");
+        assert_eq!(indices.len(), 2);
+        assert_eq!(vox.count_voxels(), 0);
+        assert_eq!(vox.cubes.len(), 0);
+        let node = &vox.get_nodes()[indices[1]];
+        assert_eq!(node.name, "part1");
+        assert_eq!(node.offset, [0, 0, 0]);
+        assert_eq!(node.volume.get_voxel(1, 1, 0), Some(1));
+        let node = &vox.get_nodes()[indices[0]];
+        assert_eq!(node.offset, [10, 0, 0]);
+        assert_eq!(node.volume.get_voxel(0, 0, 3), Some(2));
+    }
+}
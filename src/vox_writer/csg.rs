@@ -0,0 +1,232 @@
+use std::collections::HashSet;
+
+use super::{Memory, Point3, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a boolean operation between two volumes"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CsgOperation {
+    Union,        // the voxels of both volumes
+    Difference,   // the voxels of this volume not in the other one
+    Intersection, // the voxels in both volumes
+    Xor,          // the voxels in only one of the volumes
+}
+
+#[doc = "the volume giving its color to the voxels in both volumes"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CsgPrecedence {
+    This,
+    Other,
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    /// the voxels of the cubes, with the offset
+    pub(crate) fn get_raw_voxels(&self, offset: [i32; 3]) -> Vec<([i32; 3], u8)> {
+        let mut voxels = Vec::with_capacity(self.count_voxels() as usize);
+        for c in &self.cubes {
+            let bx = c.tx * self.max_voxel_per_cube_x + offset[0];
+            let by = c.ty * self.max_voxel_per_cube_y + offset[1];
+            let bz = c.tz * self.max_voxel_per_cube_z + offset[2];
+            for v in c.xyzi.voxels.chunks_exact(4) {
+                voxels.push(([bx + v[0] as i32, by + v[1] as i32, bz + v[2] as i32], v[3]));
+            }
+        }
+        voxels
+    }
+
+    /// add the voxels of other moved by offset straight in the cubes. for the voxels already
+    /// there, on_both is called and the color of other is taken with the precedence Other
+    fn merge_cubes<F: FnMut([i32; 3])>(
+        &mut self,
+        other: &VoxWriter,
+        offset: [i32; 3],
+        precedence: CsgPrecedence,
+        mut on_both: F,
+    ) {
+        let size = [
+            self.max_voxel_per_cube_x,
+            self.max_voxel_per_cube_y,
+            self.max_voxel_per_cube_z,
+        ];
+        // the voxels of a cube of other mostly go in the same cube
+        let mut last: Option<([i32; 3], usize)> = None;
+        for c in &other.cubes {
+            let bx = c.tx * other.max_voxel_per_cube_x + offset[0];
+            let by = c.ty * other.max_voxel_per_cube_y + offset[1];
+            let bz = c.tz * other.max_voxel_per_cube_z + offset[2];
+            for v in c.xyzi.voxels.chunks_exact(4) {
+                let p = [bx + v[0] as i32, by + v[1] as i32, bz + v[2] as i32];
+                let t = [0, 1, 2].map(|i| p[i].div_euclid(size[i]));
+                let index = match last {
+                    Some((k, i)) if k == t => i,
+                    _ => {
+                        self.get_cube(t[0], t[1], t[2]);
+                        let i = self.get_cube_id(t[0], t[1], t[2]) as usize;
+                        self.min_cube_x = i32::min(self.min_cube_x, t[0]);
+                        self.min_cube_y = i32::min(self.min_cube_y, t[1]);
+                        self.min_cube_z = i32::min(self.min_cube_z, t[2]);
+                        last = Some((t, i));
+                        i
+                    }
+                };
+                let voxels = &mut self.cubes[index].xyzi.voxels;
+                match self.voxel_id.get(&p[0], &p[1], &p[2]) {
+                    Some(cid) => {
+                        if precedence == CsgPrecedence::Other {
+                            voxels[*cid as usize - 1] = v[3];
+                        }
+                        on_both(p);
+                    }
+                    None => {
+                        let l = [0, 1, 2].map(|i| p[i].rem_euclid(size[i]) as u8);
+                        voxels.extend_from_slice(&[l[0], l[1], l[2], v[3]]);
+                        self.voxel_id.set(p[0], p[1], p[2], voxels.len() as i32);
+                        self.max_volume.combine(Point3::<f64>::create3(
+                            p[0] as f64,
+                            p[1] as f64,
+                            p[2] as f64,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    #[doc = "apply a boolean operation with the main volume of other, moved by offset. \
+             the color indices of other are kept as is, the volumes must share the palette"]
+    pub fn csg(
+        &mut self,
+        operation: CsgOperation,
+        other: &VoxWriter,
+        offset: [i32; 3],
+        precedence: CsgPrecedence,
+    ) {
+        match operation {
+            CsgOperation::Union => self.merge_cubes(other, offset, precedence, |_| {}),
+            CsgOperation::Difference => self.retain_voxels(|p, _| {
+                other
+                    .voxel_id
+                    .get(
+                        &(p[0] - offset[0]),
+                        &(p[1] - offset[1]),
+                        &(p[2] - offset[2]),
+                    )
+                    .is_none()
+            }),
+            CsgOperation::Intersection => self.retain_voxels(|p, color| {
+                match other.get_voxel(p[0] - offset[0], p[1] - offset[1], p[2] - offset[2]) {
+                    Some(c) => {
+                        if precedence == CsgPrecedence::Other {
+                            *color = c as u8;
+                        }
+                        true
+                    }
+                    None => false,
+                }
+            }),
+            CsgOperation::Xor => {
+                // the voxels of other are added, then the ones in both volumes are removed
+                let mut both: HashSet<[i32; 3]> = HashSet::new();
+                self.merge_cubes(other, offset, precedence, |p| {
+                    both.insert(p);
+                });
+                if !both.is_empty() {
+                    self.retain_voxels(|p, _| !both.contains(&p));
+                }
+            }
+        }
+    }
+
+    #[doc = "add the voxels of other moved by offset, see csg"]
+    pub fn union(&mut self, other: &VoxWriter, offset: [i32; 3], precedence: CsgPrecedence) {
+        self.csg(CsgOperation::Union, other, offset, precedence);
+    }
+
+    #[doc = "remove the voxels of other moved by offset, see csg"]
+    pub fn difference(&mut self, other: &VoxWriter, offset: [i32; 3]) {
+        self.csg(CsgOperation::Difference, other, offset, CsgPrecedence::This);
+    }
+
+    #[doc = "keep only the voxels also in other moved by offset, see csg"]
+    pub fn intersection(&mut self, other: &VoxWriter, offset: [i32; 3], precedence: CsgPrecedence) {
+        self.csg(CsgOperation::Intersection, other, offset, precedence);
+    }
+
+    #[doc = "keep the voxels in only one of the volumes, other moved by offset, see csg"]
+    pub fn xor(&mut self, other: &VoxWriter, offset: [i32; 3]) {
+        self.csg(CsgOperation::Xor, other, offset, CsgPrecedence::This);
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::ShapeFill;
+    use super::*;
+
+    fn boxes() -> (VoxWriter, VoxWriter) {
+        // two 4x4x4 boxes overlapping on 2x4x4 voxels, across the cubes of 126 voxels
+        let mut a = VoxWriter::create_empty();
+        a.draw_box([122, 0, 0], [125, 3, 3], 1, ShapeFill::Solid);
+        let mut b = VoxWriter::create_empty();
+        b.draw_box([0, 0, 0], [3, 3, 3], 2, ShapeFill::Solid);
+        (a, b)
+    }
+
+    #[test]
+    fn test_csg() {
+        let offset = [124, 0, 0];
+        let (mut a, b) = boxes();
+        a.union(&b, offset, CsgPrecedence::Other);
+        assert_eq!(a.count_voxels(), 96);
+        assert_eq!(a.get_voxel(124, 0, 0), Some(2));
+        assert_eq!(a.get_voxel(122, 0, 0), Some(1));
+
+        let (mut a, b) = boxes();
+        a.difference(&b, offset);
+        assert_eq!(a.count_voxels(), 32);
+        assert_eq!(a.get_voxel(124, 0, 0), None);
+        assert_eq!(a.get_voxel(123, 3, 3), Some(1));
+
+        let (mut a, b) = boxes();
+        a.intersection(&b, offset, CsgPrecedence::This);
+        assert_eq!(a.count_voxels(), 32);
+        assert_eq!(a.get_voxel(125, 3, 3), Some(1));
+        assert_eq!(a.get_voxel(123, 3, 3), None);
+
+        let (mut a, b) = boxes();
+        a.xor(&b, offset);
+        assert_eq!(a.count_voxels(), 64);
+        assert_eq!(a.get_voxel(125, 0, 0), None);
+        assert_eq!(a.get_voxel(127, 0, 0), Some(2));
+        let mut count = 0;
+        a.for_each_voxel(|x, y, z, c| {
+            assert_eq!(a.get_voxel(x, y, z), Some(c));
+            count += 1;
+        });
+        assert_eq!(count, 64);
+
+        // the cube emptied by the difference is dropped
+        let (mut a, b) = boxes();
+        a.union(&b, offset, CsgPrecedence::This);
+        assert_eq!(a.cubes.len(), 2);
+        a.difference(&b, offset);
+        assert_eq!(a.cubes.len(), 1);
+        assert_eq!(a.get_max_volume().upper_bound.x, 123.0);
+
+        // the other volume with smaller cubes, the overlap takes its colors
+        let (mut a, _) = boxes();
+        let mut b = VoxWriter::create(2, 2, 2);
+        b.draw_box([0, 0, 0], [3, 3, 3], 2, ShapeFill::Solid);
+        a.union(&b, offset, CsgPrecedence::Other);
+        assert_eq!(a.count_voxels(), 96);
+        assert_eq!(a.cubes.len(), 2);
+        assert_eq!(a.get_voxel(125, 3, 3), Some(2));
+        assert_eq!(a.get_voxel(127, 3, 3), Some(2));
+        assert_eq!(a.get_bounds(), Some(([122, 0, 0], [127, 3, 3])));
+    }
+}
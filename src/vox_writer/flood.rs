@@ -0,0 +1,217 @@
+use std::collections::{HashSet, VecDeque};
+
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) const NEIGHBOURS_6: [[i32; 3]; 6] = [
+    [-1, 0, 0],
+    [1, 0, 0],
+    [0, -1, 0],
+    [0, 1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+];
+
+/// a dense grid of bits from min to max, both included. the cells outside are false
+pub(crate) struct BitGrid {
+    min: [i32; 3],
+    size: [usize; 3],
+    bits: Vec<u64>,
+}
+
+impl BitGrid {
+    pub(crate) fn create(min: [i32; 3], max: [i32; 3]) -> Self {
+        let size = [0, 1, 2].map(|i| (max[i] - min[i] + 1).max(0) as usize);
+        let count = size[0] * size[1] * size[2];
+        Self {
+            min,
+            size,
+            bits: vec![0; count.div_ceil(64)],
+        }
+    }
+
+    fn index(&self, p: [i32; 3]) -> Option<usize> {
+        let mut q = [0usize; 3];
+        for i in 0..3 {
+            let v = p[i] - self.min[i];
+            if v < 0 || v as usize >= self.size[i] {
+                return None;
+            }
+            q[i] = v as usize;
+        }
+        Some((q[2] * self.size[1] + q[1]) * self.size[0] + q[0])
+    }
+
+    pub(crate) fn contains(&self, p: [i32; 3]) -> bool {
+        self.index(p).is_some()
+    }
+
+    pub(crate) fn get(&self, p: [i32; 3]) -> bool {
+        self.index(p)
+            .is_some_and(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    pub(crate) fn set(&mut self, p: [i32; 3], v: bool) {
+        if let Some(i) = self.index(p) {
+            if v {
+                self.bits[i / 64] |= 1 << (i % 64);
+            } else {
+                self.bits[i / 64] &= !(1 << (i % 64));
+            }
+        }
+    }
+}
+
+fn add(p: [i32; 3], d: [i32; 3]) -> [i32; 3] {
+    [p[0] + d[0], p[1] + d[1], p[2] + d[2]]
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    /// the voxels in a grid of the bounds grown by margin, with the bounds of the grid
+    pub(crate) fn get_occupancy(&self, margin: i32) -> Option<(BitGrid, [i32; 3], [i32; 3])> {
+        let (min, max) = self.get_bounds()?;
+        let min = min.map(|v| v - margin);
+        let max = max.map(|v| v + margin);
+        let mut grid = BitGrid::create(min, max);
+        self.for_each_voxel(|x, y, z, _| grid.set([x, y, z], true));
+        Some((grid, min, max))
+    }
+
+    #[doc = "remove the voxels deeper than thickness (at least 1) from the outside. the outside is the \
+             empty space reached by a 6-connected flood from around the volume, so the closed cavities \
+             are removed with the voxels around them. use 3 bits per voxel of the bounds. \
+             return the count of voxels removed"]
+    pub fn hollow(&mut self, thickness: i32) -> usize {
+        let (solid, min, max) = match self.get_occupancy(1) {
+            Some(g) => g,
+            None => return 0,
+        };
+        // the flood of the outside, from a corner of the margin
+        let mut outside = BitGrid::create(min, max);
+        let mut queue = VecDeque::from([min]);
+        outside.set(min, true);
+        while let Some(p) = queue.pop_front() {
+            for d in NEIGHBOURS_6 {
+                let n = add(p, d);
+                if solid.contains(n) && !solid.get(n) && !outside.get(n) {
+                    outside.set(n, true);
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        // the voxels touching the outside, then the next layers inward
+        let mut front = vec![];
+        self.for_each_voxel(|x, y, z, _| {
+            let p = [x, y, z];
+            if NEIGHBOURS_6.iter().any(|d| outside.get(add(p, *d))) {
+                front.push(p);
+            }
+        });
+        let mut kept = BitGrid::create(min, max);
+        for p in &front {
+            kept.set(*p, true);
+        }
+        for _ in 1..thickness {
+            let mut next = vec![];
+            for p in front {
+                for d in NEIGHBOURS_6 {
+                    let n = add(p, d);
+                    if solid.get(n) && !kept.get(n) {
+                        kept.set(n, true);
+                        next.push(n);
+                    }
+                }
+            }
+            front = next;
+        }
+
+        let before = self.count_voxels();
+        self.retain_voxels(|p, _| kept.get(p));
+        (before - self.count_voxels()) as usize
+    }
+
+    #[doc = "recolor the 6-connected voxels from start with color_index. with same_color_only, \
+             the region stops at the voxels of another color than the start one. \
+             return the count of voxels recolored"]
+    pub fn flood_fill(
+        &mut self,
+        start: [i32; 3],
+        color_index: i32,
+        same_color_only: bool,
+    ) -> usize {
+        let start_color = match self.get_voxel(start[0], start[1], start[2]) {
+            Some(c) => c,
+            None => return 0,
+        };
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(p) = queue.pop_front() {
+            for d in NEIGHBOURS_6 {
+                let n = add(p, d);
+                if visited.contains(&n) {
+                    continue;
+                }
+                let c = self.get_voxel(n[0], n[1], n[2]);
+                if c.is_some_and(|c| !same_color_only || c == start_color) {
+                    visited.insert(n);
+                    queue.push_back(n);
+                }
+            }
+        }
+        for p in &visited {
+            self.set_voxel_color(p[0], p[1], p[2], color_index);
+        }
+        visited.len()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::ShapeFill;
+    use super::*;
+
+    #[test]
+    fn test_hollow() {
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_box([0; 3], [6; 3], 1, ShapeFill::Solid);
+        // the center of the box is a closed cavity
+        vox.remove_voxel(3, 3, 3);
+        assert_eq!(vox.hollow(2), 27 - 1);
+        assert_eq!(vox.count_voxels(), 343 - 27);
+        assert_eq!(vox.get_voxel(1, 1, 1), Some(1));
+        assert_eq!(vox.get_voxel(2, 2, 2), None);
+        // a tunnel opens the inside to the outside
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_box([0; 3], [4; 3], 1, ShapeFill::Solid);
+        for z in 0..=4 {
+            vox.remove_voxel(2, 2, z);
+        }
+        // the 12 voxels in the corners of the tunnel are at the depth 2
+        assert_eq!(vox.hollow(1), 12);
+        // the cube in the middle of the box is emptied and dropped
+        let mut vox = VoxWriter::create(4, 4, 4);
+        vox.draw_box([0; 3], [11; 3], 1, ShapeFill::Solid);
+        assert_eq!(vox.cubes.len(), 27);
+        assert_eq!(vox.hollow(2), 8 * 8 * 8);
+        assert_eq!(vox.cubes.len(), 26);
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_line([0, 0, 0], [9, 0, 0], 1);
+        vox.draw_line([5, 1, 0], [5, 5, 0], 2);
+        vox.add_voxel(0, 0, 2, 1);
+        assert_eq!(vox.flood_fill([0, 0, 0], 3, true), 10);
+        assert_eq!(vox.get_voxel(9, 0, 0), Some(3));
+        assert_eq!(vox.get_voxel(5, 1, 0), Some(2));
+        assert_eq!(vox.flood_fill([5, 5, 0], 4, false), 15);
+        assert_eq!(vox.get_voxel(0, 0, 2), Some(1));
+    }
+}
@@ -0,0 +1,437 @@
+use super::invalid_data;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+const MAX_CODES: usize = 4096;
+const MAX_DECODED_SIZE: usize = 1 << 30; // the most bytes of all the frames
+
+/// the frames of a gif, composed on the full canvas
+pub(crate) struct GifImage {
+    pub width: usize,
+    pub height: usize,
+    pub frames: Vec<Vec<u8>>, // rgba, rows from the top
+}
+
+/// read the sub blocks following pos, return the data and the pos after the terminator
+fn read_sub_blocks(data: &[u8], mut pos: usize) -> std::io::Result<(Vec<u8>, usize)> {
+    let mut out = vec![];
+    loop {
+        let len = *data
+            .get(pos)
+            .ok_or_else(|| invalid_data("gif: truncated data"))? as usize;
+        pos += 1;
+        if len == 0 {
+            return Ok((out, pos));
+        }
+        let block = data
+            .get(pos..pos + len)
+            .ok_or_else(|| invalid_data("gif: truncated data"))?;
+        out.extend_from_slice(block);
+        pos += len;
+    }
+}
+
+fn read_color_table(data: &[u8], pos: usize, count: usize) -> std::io::Result<Vec<[u8; 3]>> {
+    let table = data
+        .get(pos..pos + count * 3)
+        .ok_or_else(|| invalid_data("gif: truncated color table"))?;
+    Ok(table.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect())
+}
+
+/// decode the lzw codes of an image, the codes are packed from the low bits
+fn lzw_decode(data: &[u8], min_code_size: u8, pixel_count: usize) -> std::io::Result<Vec<u8>> {
+    if !(1..=11).contains(&min_code_size) {
+        return Err(invalid_data("gif: bad lzw code size"));
+    }
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+    let mut prefix = vec![0u16; MAX_CODES];
+    let mut suffix = vec![0u8; MAX_CODES];
+    let mut first = vec![0u8; MAX_CODES];
+    for i in 0..clear {
+        suffix[i] = i as u8;
+        first[i] = i as u8;
+    }
+    let mut code_size = min_code_size as usize + 1;
+    let mut next = clear + 2;
+    let mut prev: Option<usize> = None;
+    // the pixel count comes from the file, the allocation is bounded by the data
+    let mut out = Vec::with_capacity(pixel_count.min(data.len() * 8));
+    let mut stack: Vec<u8> = vec![];
+    let mut bit_buf = 0u32;
+    let mut bit_count = 0;
+    let mut bytes = data.iter();
+    while out.len() < pixel_count {
+        while bit_count < code_size {
+            match bytes.next() {
+                Some(b) => {
+                    bit_buf |= (*b as u32) << bit_count;
+                    bit_count += 8;
+                }
+                None => return Ok(out), // some encoders stop without the end code
+            }
+        }
+        let code = (bit_buf & ((1 << code_size) - 1)) as usize;
+        bit_buf >>= code_size;
+        bit_count -= code_size;
+        if code == clear {
+            code_size = min_code_size as usize + 1;
+            next = clear + 2;
+            prev = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+        let p = match prev {
+            None => {
+                if code >= clear {
+                    return Err(invalid_data("gif: bad lzw code"));
+                }
+                out.push(code as u8);
+                prev = Some(code);
+                continue;
+            }
+            Some(p) => p,
+        };
+        let head = if code < next {
+            first[code]
+        } else if code == next {
+            first[p]
+        } else {
+            return Err(invalid_data("gif: bad lzw code"));
+        };
+        if next < MAX_CODES {
+            prefix[next] = p as u16;
+            suffix[next] = head;
+            first[next] = first[p];
+            next += 1;
+            if next == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        // the string of a code is read backward
+        let mut c = code;
+        stack.clear();
+        while c >= clear {
+            stack.push(suffix[c]);
+            c = prefix[c] as usize;
+        }
+        stack.push(suffix[c]);
+        out.extend(stack.iter().rev());
+        prev = Some(code);
+    }
+    out.truncate(pixel_count);
+    Ok(out)
+}
+
+/// decode all the frames of a gif, with their disposal. the background is transparent
+pub(crate) fn decode_gif(data: &[u8]) -> std::io::Result<GifImage> {
+    if data.len() < 13 || (&data[0..6] != b"GIF87a" && &data[0..6] != b"GIF89a") {
+        return Err(invalid_data("gif: bad signature"));
+    }
+    let width = u16::from_le_bytes([data[6], data[7]]) as usize;
+    let height = u16::from_le_bytes([data[8], data[9]]) as usize;
+    let flags = data[10];
+    let mut pos = 13;
+    let mut global_table: Vec<[u8; 3]> = vec![];
+    if flags & 0x80 != 0 {
+        let count = 2 << (flags & 7);
+        global_table = read_color_table(data, pos, count)?;
+        pos += count * 3;
+    }
+
+    let canvas_size = width * height * 4;
+    if canvas_size > MAX_DECODED_SIZE {
+        return Err(invalid_data("gif: image too big"));
+    }
+    let mut canvas = vec![0u8; canvas_size];
+    let mut frames: Vec<Vec<u8>> = vec![];
+    let mut transparent: Option<u8> = None;
+    let mut disposal = 0;
+    while pos < data.len() {
+        match data[pos] {
+            0x21 => {
+                let label = *data
+                    .get(pos + 1)
+                    .ok_or_else(|| invalid_data("gif: truncated extension"))?;
+                let (block, next) = read_sub_blocks(data, pos + 2)?;
+                if label == 0xf9 && block.len() >= 4 {
+                    // graphic control, for the next image
+                    disposal = (block[0] >> 2) & 7;
+                    transparent = (block[0] & 1 != 0).then_some(block[3]);
+                }
+                pos = next;
+            }
+            0x2c => {
+                let d = data
+                    .get(pos + 1..pos + 10)
+                    .ok_or_else(|| invalid_data("gif: truncated image"))?;
+                let left = u16::from_le_bytes([d[0], d[1]]) as usize;
+                let top = u16::from_le_bytes([d[2], d[3]]) as usize;
+                let w = u16::from_le_bytes([d[4], d[5]]) as usize;
+                let h = u16::from_le_bytes([d[6], d[7]]) as usize;
+                let image_flags = d[8];
+                pos += 10;
+                let mut table = &global_table;
+                let local_table;
+                if image_flags & 0x80 != 0 {
+                    let count = 2 << (image_flags & 7);
+                    local_table = read_color_table(data, pos, count)?;
+                    table = &local_table;
+                    pos += count * 3;
+                }
+                let min_code_size = *data
+                    .get(pos)
+                    .ok_or_else(|| invalid_data("gif: truncated image"))?;
+                let (codes, next) = read_sub_blocks(data, pos + 1)?;
+                pos = next;
+                if canvas_size * (frames.len() + 2) > MAX_DECODED_SIZE {
+                    return Err(invalid_data("gif: image too big"));
+                }
+                let indices = lzw_decode(&codes, min_code_size, w * h)?;
+
+                // the interlaced rows are in 4 passes
+                let rows: Vec<usize> = if image_flags & 0x40 != 0 {
+                    [(0, 8), (4, 8), (2, 4), (1, 2)]
+                        .iter()
+                        .flat_map(|(start, step)| (*start..h).step_by(*step))
+                        .collect()
+                } else {
+                    (0..h).collect()
+                };
+                let previous = canvas.clone();
+                for (i, index) in indices.iter().enumerate() {
+                    let (x, y) = (left + i % w, top + rows[i / w]);
+                    if x >= width || y >= height || Some(*index) == transparent {
+                        continue;
+                    }
+                    let c = table.get(*index as usize).copied().unwrap_or([0; 3]);
+                    let p = (y * width + x) * 4;
+                    canvas[p..p + 4].copy_from_slice(&[c[0], c[1], c[2], 255]);
+                }
+                frames.push(canvas.clone());
+                match disposal {
+                    2 => {
+                        // restore the area of the image to the background
+                        for y in top..usize::min(top + h, height) {
+                            for x in left..usize::min(left + w, width) {
+                                let p = (y * width + x) * 4;
+                                canvas[p..p + 4].copy_from_slice(&[0; 4]);
+                            }
+                        }
+                    }
+                    3 => canvas = previous,
+                    _ => {}
+                }
+                transparent = None;
+                disposal = 0;
+            }
+            0x3b => break,
+            _ => return Err(invalid_data("gif: bad block")),
+        }
+    }
+    Ok(GifImage {
+        width,
+        height,
+        frames,
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::super::zlib::tests::mutations;
+    use super::*;
+    use gif::{DisposalMethod, Encoder, Frame};
+
+    /// encode a gif of 4 colors, each code is a clear and a pixel so the codes stay 3 bits
+    pub(crate) fn encode_test_gif(
+        width: u16,
+        height: u16,
+        colors: &[[u8; 3]; 4],
+        frames: &[(u16, u16, u16, u16, Vec<u8>)], // left, top, width, height, indices
+    ) -> Vec<u8> {
+        let mut out = b"GIF89a".to_vec();
+        out.extend_from_slice(&width.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        out.extend_from_slice(&[0x81, 0, 0]);
+        for c in colors {
+            out.extend_from_slice(c);
+        }
+        for (left, top, w, h, indices) in frames {
+            // transparent index 0, no disposal
+            out.extend_from_slice(&[0x21, 0xf9, 4, 1, 0, 0, 0, 0]);
+            out.push(0x2c);
+            for v in [left, top, w, h] {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            out.push(0);
+            out.push(2);
+            let mut codes: Vec<u8> = vec![];
+            let (mut bit_buf, mut bit_count) = (0u32, 0);
+            for code in indices.iter().flat_map(|i| [4u32, *i as u32]).chain([5]) {
+                bit_buf |= code << bit_count;
+                bit_count += 3;
+                while bit_count >= 8 {
+                    codes.push(bit_buf as u8);
+                    bit_buf >>= 8;
+                    bit_count -= 8;
+                }
+            }
+            if bit_count > 0 {
+                codes.push(bit_buf as u8);
+            }
+            for block in codes.chunks(255) {
+                out.push(block.len() as u8);
+                out.extend_from_slice(block);
+            }
+            out.push(0);
+        }
+        out.push(0x3b);
+        out
+    }
+
+    #[test]
+    fn test_lzw_decode() {
+        // "a a a a" with the codes 0, 6, 0 : the code 6 is defined by itself
+        let codes = [4u32, 0, 6, 0, 5];
+        let mut data = vec![0u8; 2];
+        for (i, c) in codes.iter().enumerate() {
+            let bits = c << (i * 3);
+            data[0] |= bits as u8;
+            data[1] |= (bits >> 8) as u8;
+        }
+        assert_eq!(lzw_decode(&data, 2, 4).unwrap(), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_gif() {
+        let colors = [[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        let data = encode_test_gif(
+            2,
+            2,
+            &colors,
+            &[(0, 0, 2, 2, vec![1, 0, 2, 3]), (1, 0, 1, 1, vec![3])],
+        );
+        let gif = decode_gif(&data).unwrap();
+        assert_eq!(gif.frames.len(), 2);
+        assert_eq!(&gif.frames[0][0..8], &[255, 0, 0, 255, 0, 0, 0, 0]);
+        // the second frame is drawn over the first one
+        assert_eq!(&gif.frames[1][4..8], &[0, 0, 255, 255]);
+        assert_eq!(&gif.frames[1][12..16], &[0, 0, 255, 255]);
+    }
+
+    /// a 256 colors palette, as rgb bytes
+    fn palette(f: impl Fn(u8) -> [u8; 3]) -> Vec<u8> {
+        (0..=255u8).flat_map(f).collect()
+    }
+
+    /// some noisy indices, enough for the codes to reach 12 bits and the table to be cleared
+    fn noise(count: usize, modulo: u32) -> Vec<u8> {
+        (0..count as u32)
+            .map(|i| ((i.wrapping_mul(2654435761) >> 13) % modulo) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_gif_crate() {
+        let (width, height) = (100usize, 80usize);
+        let global = palette(|i| [i, 255 - i, i / 2]);
+        let local = palette(|i| [255 - i, i, 7]);
+        let mut data = vec![];
+        let mut encoder = Encoder::new(&mut data, width as u16, height as u16, &global).unwrap();
+        // a full frame of noise
+        let background = noise(width * height, 256);
+        let frame = Frame::from_indexed_pixels(100, 80, background.clone(), None);
+        encoder.write_frame(&frame).unwrap();
+        // an interlaced part with its own palette and a transparent index, then cleared
+        let part = noise(30 * 20, 7);
+        let rows: Vec<usize> = [(0, 8), (4, 8), (2, 4), (1, 2)]
+            .iter()
+            .flat_map(|(start, step)| (*start..20).step_by(*step))
+            .collect();
+        let interlaced: Vec<u8> = rows
+            .iter()
+            .flat_map(|y| part[y * 30..(y + 1) * 30].to_vec())
+            .collect();
+        let mut frame = Frame::from_palette_pixels(30, 20, interlaced, local.clone(), Some(0));
+        frame.palette = Some(local.clone());
+        (frame.left, frame.top, frame.interlaced) = (10, 5, true);
+        frame.dispose = DisposalMethod::Background;
+        encoder.write_frame(&frame).unwrap();
+        // a pixel restored after its frame, then a last pixel
+        let mut frame = Frame::from_indexed_pixels(1, 1, vec![3], None);
+        frame.dispose = DisposalMethod::Previous;
+        encoder.write_frame(&frame).unwrap();
+        let mut frame = Frame::from_indexed_pixels(1, 1, vec![4], None);
+        (frame.left, frame.top) = (99, 79);
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+
+        let gif = decode_gif(&data).unwrap();
+        assert_eq!(
+            (gif.width, gif.height, gif.frames.len()),
+            (width, height, 4)
+        );
+        let rgba = |table: &[u8], i: u8| {
+            let i = i as usize * 3;
+            [table[i], table[i + 1], table[i + 2], 255]
+        };
+        let pixel = |frame: &Vec<u8>, x: usize, y: usize| {
+            let p = (y * width + x) * 4;
+            [frame[p], frame[p + 1], frame[p + 2], frame[p + 3]]
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let first = rgba(&global, background[y * width + x]);
+                assert_eq!(pixel(&gif.frames[0], x, y), first);
+                let in_part = (10..40).contains(&x) && (5..25).contains(&y);
+                let second = match in_part {
+                    true => match part[(y - 5) * 30 + x - 10] {
+                        0 => first,
+                        i => rgba(&local, i),
+                    },
+                    false => first,
+                };
+                assert_eq!(pixel(&gif.frames[1], x, y), second);
+                let cleared = if in_part { [0; 4] } else { first };
+                let third = if (x, y) == (0, 0) {
+                    rgba(&global, 3)
+                } else {
+                    cleared
+                };
+                assert_eq!(pixel(&gif.frames[2], x, y), third);
+                let last = if (x, y) == (99, 79) {
+                    rgba(&global, 4)
+                } else {
+                    cleared
+                };
+                assert_eq!(pixel(&gif.frames[3], x, y), last);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_gif_mutations() {
+        // the damaged files give an error or some frames, never a panic
+        let mut data = vec![];
+        let mut encoder = Encoder::new(&mut data, 24, 16, &palette(|i| [i, i, i])).unwrap();
+        let mut frame = Frame::from_indexed_pixels(24, 16, noise(24 * 16, 256), Some(1));
+        frame.interlaced = true;
+        encoder.write_frame(&frame).unwrap();
+        let mut frame =
+            Frame::from_palette_pixels(8, 8, noise(64, 4), palette(|i| [i, 0, 0]), None);
+        (frame.left, frame.top) = (20, 10);
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+        for m in mutations(&data, 3000) {
+            let _ = decode_gif(&m);
+        }
+        // a canvas of 65535 x 65535 is refused before its allocation
+        let mut huge = data.clone();
+        huge[6..10].copy_from_slice(&[255; 4]);
+        assert!(decode_gif(&huge).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use super::png::decode_png;
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+const BLOCK_SIZE: i32 = 16;
+
+#[doc = "a material of a goxel file, its color tints the voxels of the layers using it"]
+#[derive(Clone, Debug)]
+pub struct GoxMaterial {
+    pub name: String,
+    pub color: [f32; 4],
+}
+
+#[doc = "a layer of a goxel file, z is up like magicavoxel"]
+#[derive(Clone, Debug)]
+pub struct GoxLayer {
+    pub name: String,
+    pub visible: bool,
+    pub material: Option<usize>,
+    pub offset: [i32; 3],                 // the translation of the layer matrix
+    pub voxels: Vec<([i32; 3], [u8; 4])>, // pos, rgba
+}
+
+type Dict = HashMap<String, Vec<u8>>;
+
+/// the key values of a chunk dict, a key size of 0 or the end of the chunk stop it
+fn read_dict(data: &[u8]) -> Dict {
+    let mut dict = HashMap::new();
+    let mut pos = 0;
+    let read_len = |pos: usize| -> Option<usize> {
+        let b = data.get(pos..pos + 4)?;
+        let v = i32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        (v > 0).then_some(v as usize)
+    };
+    while let Some(key_len) = read_len(pos) {
+        let key = match data.get(pos + 4..pos + 4 + key_len) {
+            Some(k) => String::from_utf8_lossy(k).to_string(),
+            None => break,
+        };
+        pos += 4 + key_len;
+        let value_len = read_len(pos).unwrap_or(0);
+        let value = match data.get(pos + 4..pos + 4 + value_len) {
+            Some(v) => v.to_vec(),
+            None => break,
+        };
+        pos += 4 + value_len;
+        dict.insert(key, value);
+    }
+    dict
+}
+
+fn dict_i32(dict: &Dict, key: &str) -> Option<i32> {
+    let v = dict.get(key)?.get(0..4)?;
+    Some(i32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+}
+
+fn dict_f32s(dict: &Dict, key: &str) -> Vec<f32> {
+    dict.get(key).map_or(vec![], |v| {
+        v.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    })
+}
+
+fn dict_string(dict: &Dict, key: &str) -> String {
+    dict.get(key).map_or(String::new(), |v| {
+        String::from_utf8_lossy(v)
+            .trim_end_matches('\0')
+            .to_string()
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "the layers and materials of a goxel (.gox) file"]
+#[derive(Clone, Debug)]
+pub struct GoxFile {
+    pub layers: Vec<GoxLayer>,
+    pub materials: Vec<GoxMaterial>,
+}
+
+impl GoxFile {
+    #[doc = "load a .gox file"]
+    pub fn load_from_file(file_path_name: String) -> std::io::Result<Self> {
+        let file = File::open(file_path_name)?;
+        Self::read(BufReader::new(file))
+    }
+
+    #[doc = "read a goxel file. the blocks are 16^3 voxels stored as 64x64 png images"]
+    pub fn read<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut data: Vec<u8> = vec![];
+        reader.read_to_end(&mut data)?;
+        if data.len() < 8 || &data[0..4] != b"GOX " {
+            return Err(invalid_data("gox: bad magic"));
+        }
+        let mut blocks: Vec<Vec<u8>> = vec![]; // rgba of 16^3 voxels, x + y * 16 + z * 256
+        let mut materials: Vec<GoxMaterial> = vec![];
+        // dict, blocks (index, origin)
+        let mut raw_layers = vec![];
+        let mut pos = 8;
+        while pos + 8 <= data.len() {
+            let kind = &data[pos..pos + 4];
+            let len =
+                i32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]);
+            let start = pos + 8;
+            if len < 0 || start + len as usize > data.len() {
+                return Err(invalid_data("gox: truncated chunk"));
+            }
+            let chunk = &data[start..start + len as usize];
+            match kind {
+                b"BL16" => {
+                    let img = decode_png(chunk)?;
+                    if img.width * img.height != 4096 {
+                        return Err(invalid_data("gox: bad block size"));
+                    }
+                    let mut rgba = Vec::with_capacity(4096 * 4);
+                    for y in 0..img.height {
+                        for x in 0..img.width {
+                            rgba.extend_from_slice(&img.get_rgba(x, y));
+                        }
+                    }
+                    blocks.push(rgba);
+                }
+                b"MATE" => {
+                    let dict = read_dict(chunk);
+                    let c = dict_f32s(&dict, "color");
+                    materials.push(GoxMaterial {
+                        name: dict_string(&dict, "name"),
+                        color: if c.len() >= 4 {
+                            [c[0], c[1], c[2], c[3]]
+                        } else {
+                            [1.0; 4]
+                        },
+                    });
+                }
+                b"LAYR" => {
+                    let int = |p: usize| -> std::io::Result<i32> {
+                        chunk
+                            .get(p..p + 4)
+                            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                            .ok_or_else(|| invalid_data("gox: truncated layer"))
+                    };
+                    let count = int(0)?;
+                    if count < 0 || count as usize * 20 + 4 > chunk.len() {
+                        return Err(invalid_data("gox: bad block count"));
+                    }
+                    let mut layer_blocks = vec![];
+                    for i in 0..count as usize {
+                        let p = 4 + i * 20;
+                        layer_blocks
+                            .push((int(p)? as usize, [int(p + 4)?, int(p + 8)?, int(p + 12)?]));
+                    }
+                    let dict = read_dict(&chunk[4 + count as usize * 20..]);
+                    raw_layers.push((dict, layer_blocks));
+                }
+                _ => {} // IMG, PREV, CAMR, LIGH
+            }
+            pos = start + len as usize + 4; // the crc is not checked
+        }
+
+        let ids: Vec<Option<i32>> = raw_layers.iter().map(|l| dict_i32(&l.0, "id")).collect();
+        let mut layers = vec![];
+        for (dict, layer_blocks) in &raw_layers {
+            // a clone layer use the blocks of its base layer
+            let base = dict_i32(dict, "base_id")
+                .filter(|id| *id != 0)
+                .and_then(|id| ids.iter().position(|i| *i == Some(id)));
+            let layer_blocks = match base {
+                Some(b) if layer_blocks.is_empty() => &raw_layers[b].1,
+                _ => layer_blocks,
+            };
+            let mut voxels = vec![];
+            for (index, origin) in layer_blocks {
+                let block = blocks
+                    .get(*index)
+                    .ok_or_else(|| invalid_data("gox: bad block index"))?;
+                for (i, c) in block.chunks_exact(4).enumerate() {
+                    if c[3] == 0 {
+                        continue;
+                    }
+                    let i = i as i32;
+                    let p = [
+                        origin[0] + i % BLOCK_SIZE,
+                        origin[1] + i / BLOCK_SIZE % BLOCK_SIZE,
+                        origin[2] + i / (BLOCK_SIZE * BLOCK_SIZE),
+                    ];
+                    voxels.push((p, [c[0], c[1], c[2], c[3]]));
+                }
+            }
+            let mat = dict_f32s(dict, "mat");
+            let offset = if mat.len() == 16 {
+                [mat[12], mat[13], mat[14]].map(|v| v.round() as i32)
+            } else {
+                [0; 3]
+            };
+            layers.push(GoxLayer {
+                name: dict_string(dict, "name"),
+                visible: dict.get("visible").map_or(true, |v| v.first() != Some(&0)),
+                material: dict_i32(dict, "material")
+                    .filter(|m| *m >= 0 && (*m as usize) < materials.len())
+                    .map(|m| m as usize),
+                offset,
+                voxels,
+            });
+        }
+        Ok(Self { layers, materials })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "add each layer of a goxel file as a layer with a node. the colors are tinted by the \
+             material of the layer and mapped to the palette, quantized if there is not enough free colors"]
+    pub fn add_gox(&mut self, gox: &GoxFile) {
+        let mut colors: Vec<[u8; 3]> = vec![];
+        for layer in &gox.layers {
+            let tint = layer.material.map_or([1.0; 4], |m| gox.materials[m].color);
+            for (_, c) in &layer.voxels {
+                colors
+                    .push([0, 1, 2].map(|i| (c[i] as f32 * tint[i].clamp(0.0, 1.0)).round() as u8));
+            }
+        }
+        let indices = self.map_colors_rgb(&colors);
+        let mut next = 0;
+        for layer in &gox.layers {
+            let mut volume = VoxWriter::create_empty();
+            for (p, _) in &layer.voxels {
+                volume.add_voxel(p[0], p[1], p[2], indices[next]);
+                next += 1;
+            }
+            let layer_id = self.add_layer(layer.name.clone(), !layer.visible);
+            self.add_node(layer.name.clone(), volume, layer.offset, layer_id);
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::png::encode_png_rgba;
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        out.extend_from_slice(data);
+        out.extend_from_slice(&0i32.to_le_bytes());
+    }
+
+    fn dict_value(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+        out.extend_from_slice(&(key.len() as i32).to_le_bytes());
+        out.extend_from_slice(key.as_bytes());
+        out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        out.extend_from_slice(value);
+    }
+
+    #[test]
+    fn test_read_gox() {
+        let mut rgba = vec![0u8; 4096 * 4];
+        // x 1, y 2, z 3
+        let i = (1 + 2 * 16 + 3 * 256) * 4;
+        rgba[i..i + 4].copy_from_slice(&[200, 100, 50, 255]);
+        let mut data = b"GOX ".to_vec();
+        data.extend_from_slice(&2i32.to_le_bytes());
+        chunk(&mut data, b"BL16", &encode_png_rgba(64, 64, &rgba));
+
+        let mut mate = vec![];
+        dict_value(&mut mate, "name", b"half");
+        let color: Vec<u8> = [0.5f32, 1.0, 1.0, 1.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        dict_value(&mut mate, "color", &color);
+        chunk(&mut data, b"MATE", &mate);
+
+        for (name, visible, id, base_id, blocks) in
+            [("a", 1u8, 1i32, 0i32, 1usize), ("b", 0, 2, 1, 0)]
+        {
+            let mut layr = vec![];
+            layr.extend_from_slice(&(blocks as i32).to_le_bytes());
+            for v in [0i32, -16, 0, 16, 0].iter().take(blocks * 5) {
+                layr.extend_from_slice(&v.to_le_bytes());
+            }
+            dict_value(&mut layr, "name", name.as_bytes());
+            dict_value(&mut layr, "visible", &[visible]);
+            dict_value(&mut layr, "id", &id.to_le_bytes());
+            dict_value(&mut layr, "base_id", &base_id.to_le_bytes());
+            dict_value(&mut layr, "material", &0i32.to_le_bytes());
+            chunk(&mut data, b"LAYR", &layr);
+        }
+
+        let gox = GoxFile::read(Cursor::new(data)).unwrap();
+        assert_eq!(gox.layers.len(), 2);
+        assert_eq!(
+            gox.layers[0].voxels,
+            vec![([-15, 2, 19], [200, 100, 50, 255])]
+        );
+        // the clone layer use the blocks of the layer 1
+        assert_eq!(gox.layers[1].voxels.len(), 1);
+
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(0, 0, 0, 1);
+        vox.add_gox(&gox);
+        // the layer 0 stays the one of the main volume
+        let layers = vox.get_layers();
+        assert_eq!((layers[0].name.as_str(), layers[0].hidden), ("main", false));
+        assert_eq!((layers[1].name.as_str(), layers[1].hidden), ("a", false));
+        assert_eq!((layers[2].name.as_str(), layers[2].hidden), ("b", true));
+        assert_eq!(vox.get_nodes()[1].layer_id, 2);
+        let node = &vox.get_nodes()[0];
+        let c = node.volume.get_voxel(-15, 2, 19).unwrap();
+        assert_eq!(vox.get_palette_color(c as u8), [100, 100, 50, 255]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use super::png::decode_png;
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "how the columns of a heightmap are filled"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HeightmapFill {
+    Solid, // from the ground to the top
+    Shell, // only the visible part, down to the lowest neighbour column
+}
+
+#[doc = "how the voxels of a heightmap are colored"]
+#[derive(Clone, PartialEq, Debug)]
+pub enum HeightmapColoring {
+    Single(i32),
+    // (max height in [0:1], color index), sorted by height. a voxel get the first band above its height
+    HeightBands(Vec<(f64, i32)>),
+    // (max slope in degrees, color index), sorted by slope. a column get the first band above its slope
+    SlopeBands(Vec<(f64, i32)>),
+}
+
+#[doc = "the options of the terrain generation from a heightmap"]
+#[derive(Clone, Debug)]
+pub struct HeightmapOptions {
+    pub vertical_scale: f64, // height in voxels of the white pixels
+    pub fill: HeightmapFill,
+    pub coloring: HeightmapColoring,
+}
+
+impl HeightmapOptions {
+    #[doc = "solid columns of one color, vertical_scale voxels high for the white"]
+    pub fn create(vertical_scale: f64, color_index: i32) -> Self {
+        Self {
+            vertical_scale,
+            fill: HeightmapFill::Solid,
+            coloring: HeightmapColoring::Single(color_index),
+        }
+    }
+}
+
+fn band_color(bands: &[(f64, i32)], v: f64) -> i32 {
+    for (max, color) in bands {
+        if v <= *max {
+            return *color;
+        }
+    }
+    bands.last().map_or(1, |b| b.1)
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a grid of heights in [0:1], the row 0 is the top of the image"]
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    pub heights: Vec<f64>,
+}
+
+impl Heightmap {
+    #[doc = "load a png (8 or 16 bits, the color images are converted to gray) or a pgm (P2 or P5) heightmap"]
+    pub fn load_from_file(file_path_name: String) -> std::io::Result<Self> {
+        let file = File::open(&file_path_name)?;
+        let reader = BufReader::new(file);
+        if file_path_name.to_lowercase().ends_with(".png") {
+            Self::read_png(reader)
+        } else {
+            Self::read_pgm(reader)
+        }
+    }
+
+    #[doc = "load a headerless heightmap of width x height samples of 8 or 16 bits"]
+    pub fn load_raw(
+        file_path_name: String,
+        width: usize,
+        height: usize,
+        sixteen_bits: bool,
+        big_endian: bool,
+    ) -> std::io::Result<Self> {
+        let file = File::open(file_path_name)?;
+        Self::read_raw(
+            BufReader::new(file),
+            width,
+            height,
+            sixteen_bits,
+            big_endian,
+        )
+    }
+
+    #[doc = "read a png heightmap"]
+    pub fn read_png<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut data: Vec<u8> = vec![];
+        reader.read_to_end(&mut data)?;
+        let img = decode_png(&data)?;
+        let mut heights = Vec::with_capacity(img.width * img.height);
+        for y in 0..img.height {
+            for x in 0..img.width {
+                heights.push(img.get_gray(x, y));
+            }
+        }
+        Ok(Self {
+            width: img.width,
+            height: img.height,
+            heights,
+        })
+    }
+
+    #[doc = "read an ascii (P2) or binary (P5) pgm heightmap, of 8 or 16 bits"]
+    pub fn read_pgm<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut data: Vec<u8> = vec![];
+        reader.read_to_end(&mut data)?;
+        // header : magic, width, height, maxval, with comments
+        let mut pos = 0;
+        let mut tokens: Vec<String> = vec![];
+        while tokens.len() < 4 {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid_data("pgm: truncated header"));
+            }
+            tokens.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+        }
+        let parse = |s: &str| -> std::io::Result<usize> {
+            s.parse::<usize>()
+                .map_err(|_| invalid_data("pgm: bad header value"))
+        };
+        let width = parse(&tokens[1])?;
+        let height = parse(&tokens[2])?;
+        let max_value = parse(&tokens[3])?.max(1);
+        let count = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid_data("pgm: bad size"))?;
+        let heights: Vec<f64> = match tokens[0].as_str() {
+            "P5" => {
+                pos += 1; // single whitespace after maxval
+                let sixteen_bits = max_value > 255;
+                let sample_size = if sixteen_bits { 2 } else { 1 };
+                if count
+                    .checked_mul(sample_size)
+                    .map_or(true, |size| data.len() < pos + size)
+                {
+                    return Err(invalid_data("pgm: not enough data"));
+                }
+                (0..count)
+                    .map(|i| {
+                        let v = if sixteen_bits {
+                            u16::from_be_bytes([data[pos + i * 2], data[pos + i * 2 + 1]]) as f64
+                        } else {
+                            data[pos + i] as f64
+                        };
+                        v / max_value as f64
+                    })
+                    .collect()
+            }
+            "P2" => {
+                let values: Vec<f64> = String::from_utf8_lossy(&data[pos..])
+                    .split_whitespace()
+                    .take(count)
+                    .map(|s| s.parse::<f64>().map(|v| v / max_value as f64))
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid_data("pgm: bad value"))?;
+                if values.len() < count {
+                    return Err(invalid_data("pgm: not enough data"));
+                }
+                values
+            }
+            _ => return Err(invalid_data("pgm: bad magic")),
+        };
+        Ok(Self {
+            width,
+            height,
+            heights,
+        })
+    }
+
+    #[doc = "read a headerless heightmap of width x height samples of 8 or 16 bits"]
+    pub fn read_raw<R: Read>(
+        mut reader: R,
+        width: usize,
+        height: usize,
+        sixteen_bits: bool,
+        big_endian: bool,
+    ) -> std::io::Result<Self> {
+        let sample_size = if sixteen_bits { 2 } else { 1 };
+        let size = width
+            .checked_mul(height)
+            .and_then(|count| count.checked_mul(sample_size))
+            .ok_or_else(|| invalid_data("raw heightmap: bad size"))?;
+        let mut data = vec![0u8; size];
+        reader.read_exact(&mut data)?;
+        let heights = if sixteen_bits {
+            data.chunks(2)
+                .map(|b| {
+                    let v = if big_endian {
+                        u16::from_be_bytes([b[0], b[1]])
+                    } else {
+                        u16::from_le_bytes([b[0], b[1]])
+                    };
+                    v as f64 / 65535.0
+                })
+                .collect()
+        } else {
+            data.iter().map(|v| *v as f64 / 255.0).collect()
+        };
+        Ok(Self {
+            width,
+            height,
+            heights,
+        })
+    }
+
+    #[doc = "get the height in [0:1] of the pixel x,y"]
+    pub fn get_height(&self, x: usize, y: usize) -> f64 {
+        self.heights[y * self.width + x]
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "fill a column of voxels per pixel of the heightmap, z is up. \
+             the top of the image is at the max y, so the terrain is seen like the image from above"]
+    pub fn add_heightmap(&mut self, heightmap: &Heightmap, options: &HeightmapOptions) {
+        let w = heightmap.width;
+        let h = heightmap.height;
+        let column = |x: usize, y: usize| -> i32 {
+            f64::round(heightmap.get_height(x, y) * options.vertical_scale) as i32
+        };
+        let tops: Vec<i32> = (0..w * h).map(|i| column(i % w, i / w)).collect();
+        let top = |x: i64, y: i64| -> i32 {
+            if x < 0 || y < 0 || x >= w as i64 || y >= h as i64 {
+                -1 // the borders are closed
+            } else {
+                tops[y as usize * w + x as usize]
+            }
+        };
+        let scale = f64::max(options.vertical_scale, 1.0);
+        for y in 0..h as i64 {
+            for x in 0..w as i64 {
+                let z_top = top(x, y);
+                let z_bottom = match options.fill {
+                    HeightmapFill::Solid => 0,
+                    HeightmapFill::Shell => {
+                        let lowest = [top(x - 1, y), top(x + 1, y), top(x, y - 1), top(x, y + 1)]
+                            .into_iter()
+                            .min()
+                            .unwrap();
+                        i32::clamp(lowest + 1, 0, z_top)
+                    }
+                };
+                let slope_color = match &options.coloring {
+                    HeightmapColoring::SlopeBands(bands) => {
+                        // central differences, clamped on the borders
+                        let cx = |x: i64| x.clamp(0, w as i64 - 1);
+                        let cy = |y: i64| y.clamp(0, h as i64 - 1);
+                        let gx = (top(cx(x + 1), y) - top(cx(x - 1), y)) as f64 * 0.5;
+                        let gy = (top(x, cy(y + 1)) - top(x, cy(y - 1))) as f64 * 0.5;
+                        let slope = f64::atan(f64::sqrt(gx * gx + gy * gy)).to_degrees();
+                        band_color(bands, slope)
+                    }
+                    _ => 0,
+                };
+                let vy = (h as i64 - 1 - y) as i32;
+                for z in z_bottom..=z_top {
+                    let color = match &options.coloring {
+                        HeightmapColoring::Single(c) => *c,
+                        HeightmapColoring::HeightBands(bands) => {
+                            band_color(bands, z as f64 / scale)
+                        }
+                        HeightmapColoring::SlopeBands(_) => slope_color,
+                    };
+                    self.add_voxel(x as i32, vy, z, color);
+                }
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_pgm() {
+        let data = b"P2\n# comment\n3 1\n10\n0 5 10\n";
+        let map = Heightmap::read_pgm(Cursor::new(data)).unwrap();
+        assert_eq!((map.width, map.height), (3, 1));
+        assert_eq!(map.heights, vec![0.0, 0.5, 1.0]);
+
+        let mut data = b"P5 2 1 65535\n".to_vec();
+        data.extend_from_slice(&[0, 0, 255, 255]);
+        let map = Heightmap::read_pgm(Cursor::new(data)).unwrap();
+        assert_eq!(map.heights, vec![0.0, 1.0]);
+
+        // the sizes overflow, or the samples overflow the data
+        let data = b"P5 4294967296 4294967297 255\n\0".to_vec();
+        assert!(Heightmap::read_pgm(Cursor::new(data)).is_err());
+        let data = b"P5 4294967296 2147483648 65535\n\0".to_vec();
+        assert!(Heightmap::read_pgm(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn test_add_heightmap_shell() {
+        let pixels = [0u8, 0, 0, 0, 255, 0, 0, 0, 0];
+        let map = Heightmap::read_raw(Cursor::new(pixels), 3, 3, false, false).unwrap();
+        let mut options = HeightmapOptions::create(10.0, 1);
+        let mut vox = VoxWriter::create_empty();
+        vox.add_heightmap(&map, &options);
+        assert_eq!(vox.cubes[0].xyzi.get_num_voxels(), 8 + 11);
+
+        // the middle column stops above its neighbours
+        options.fill = HeightmapFill::Shell;
+        options.coloring = HeightmapColoring::HeightBands(vec![(0.5, 1), (1.0, 2)]);
+        let mut vox = VoxWriter::create_empty();
+        vox.add_heightmap(&map, &options);
+        assert_eq!(vox.cubes[0].xyzi.get_num_voxels(), 8 + 10);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs::File;
+use std::io::Write;
+
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+// the faces of the visibility byte of a slab
+const FACE_LEFT: u8 = 1; // -x
+const FACE_RIGHT: u8 = 2; // +x
+const FACE_BACK: u8 = 4; // -y
+const FACE_FRONT: u8 = 8; // +y
+const FACE_TOP: u8 = 16; // -z
+const FACE_BOTTOM: u8 = 32; // +z
+
+impl VoxWriter {
+    #[doc = "save the voxels of the main volume to a build engine .kvx file, see get_kvx"]
+    pub fn save_kvx(&self, file_path_name: String, pivot: Option<[f64; 3]>) -> std::io::Result<()> {
+        let data = self.get_kvx(pivot)?;
+        let mut file = File::create(file_path_name)?;
+        file.write_all(&data)?;
+        file.sync_all()
+    }
+
+    #[doc = "get the kvx of the main volume, with the mip level 0 only. the z of kvx goes down, so the \
+             volume is turned around x. pivot is in voxels in the kvx axes, by default the center of \
+             the bottom. the color indices are kept, with the palette of colors. \
+             the sizes are limited to 256 voxels"]
+    pub fn get_kvx(&self, pivot: Option<[f64; 3]>) -> std::io::Result<Vec<u8>> {
+        let (min, max) = self.get_bounds().unwrap_or(([0; 3], [0; 3]));
+        let size = [0, 1, 2].map(|i| (max[i] - min[i] + 1) as usize);
+        if size.iter().any(|s| *s > 256) {
+            return Err(invalid_data("kvx: the volume is bigger than 256 voxels"));
+        }
+        let [xsiz, ysiz, zsiz] = size;
+        // dense grid of color indices in the kvx axes, 0 is empty
+        let mut grid = vec![0u8; xsiz * ysiz * zsiz];
+        let index = |x: usize, y: usize, z: usize| (x * ysiz + y) * zsiz + z;
+        self.for_each_voxel(|x, y, z, c| {
+            let kx = (x - min[0]) as usize;
+            let ky = (max[1] - y) as usize;
+            let kz = (max[2] - z) as usize;
+            grid[index(kx, ky, kz)] = c as u8;
+        });
+        let filled = |x: i64, y: i64, z: i64| -> bool {
+            x >= 0
+                && y >= 0
+                && z >= 0
+                && (x as usize) < xsiz
+                && (y as usize) < ysiz
+                && (z as usize) < zsiz
+                && grid[index(x as usize, y as usize, z as usize)] != 0
+        };
+
+        // the slabs of the surface voxels of each column
+        let mut xoffset: Vec<i32> = vec![];
+        let mut xyoffset: Vec<i16> = vec![];
+        let mut voxdata: Vec<u8> = vec![];
+        let header_size = (xsiz + 1) * 4 + xsiz * (ysiz + 1) * 2;
+        for x in 0..xsiz {
+            let x_start = voxdata.len();
+            xoffset.push((header_size + x_start) as i32);
+            for y in 0..ysiz {
+                let column_offset = voxdata.len() - x_start;
+                if column_offset > i16::MAX as usize {
+                    return Err(invalid_data("kvx: too many voxels in a x slice"));
+                }
+                xyoffset.push(column_offset as i16);
+                let (xi, yi) = (x as i64, y as i64);
+                let mut z = 0;
+                while z < zsiz {
+                    let vis = |z: usize| -> u8 {
+                        let zi = z as i64;
+                        let mut v = 0;
+                        for (dx, dy, dz, face) in [
+                            (-1, 0, 0, FACE_LEFT),
+                            (1, 0, 0, FACE_RIGHT),
+                            (0, -1, 0, FACE_BACK),
+                            (0, 1, 0, FACE_FRONT),
+                            (0, 0, -1, FACE_TOP),
+                            (0, 0, 1, FACE_BOTTOM),
+                        ] {
+                            if !filled(xi + dx, yi + dy, zi + dz) {
+                                v |= face;
+                            }
+                        }
+                        v
+                    };
+                    if !filled(xi, yi, z as i64) || vis(z) == 0 {
+                        z += 1;
+                        continue;
+                    }
+                    let ztop = z;
+                    let mut faces = 0;
+                    let mut colors = vec![];
+                    while z < zsiz && filled(xi, yi, z as i64) && vis(z) != 0 && colors.len() < 255
+                    {
+                        faces |= vis(z);
+                        colors.push(grid[index(x, y, z)]);
+                        z += 1;
+                    }
+                    // only the ends of the slab tell if its top and bottom are visible
+                    faces &= !(FACE_TOP | FACE_BOTTOM);
+                    faces |= vis(ztop) & FACE_TOP;
+                    faces |= vis(z - 1) & FACE_BOTTOM;
+                    voxdata.push(ztop as u8);
+                    voxdata.push(colors.len() as u8);
+                    voxdata.push(faces);
+                    voxdata.extend(colors);
+                }
+            }
+            let column_offset = voxdata.len() - x_start;
+            if column_offset > i16::MAX as usize {
+                return Err(invalid_data("kvx: too many voxels in a x slice"));
+            }
+            xyoffset.push(column_offset as i16);
+        }
+        xoffset.push((header_size + voxdata.len()) as i32);
+
+        let pivot = pivot.unwrap_or([xsiz as f64 * 0.5, ysiz as f64 * 0.5, zsiz as f64]);
+        let mut out: Vec<u8> = vec![];
+        let numbytes = 24 + header_size + voxdata.len();
+        out.extend_from_slice(&(numbytes as i32).to_le_bytes());
+        for v in [xsiz, ysiz, zsiz] {
+            out.extend_from_slice(&(v as i32).to_le_bytes());
+        }
+        // the pivot is in 24.8 fixed point
+        for v in pivot {
+            out.extend_from_slice(&((v * 256.0).round() as i32).to_le_bytes());
+        }
+        for v in xoffset {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in xyoffset {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend(voxdata);
+
+        // the palette is at the end of the file, 6 bits per channel
+        for i in 0..256 {
+            let c = self.get_palette_color(i as u8);
+            out.extend_from_slice(&[c[0] >> 2, c[1] >> 2, c[2] >> 2]);
+        }
+        Ok(out)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_i32(data: &[u8], pos: usize) -> i32 {
+        i32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+    }
+
+    #[test]
+    fn test_get_kvx() {
+        let mut vox = VoxWriter::create_empty();
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    vox.add_voxel(x, y, z, 1 + z);
+                }
+            }
+        }
+        vox.add_color(255, 0, 0, 255, 0);
+        let data = vox.get_kvx(None).unwrap();
+        let numbytes = read_i32(&data, 0) as usize;
+        assert_eq!(data.len(), 4 + numbytes + 768);
+        assert_eq!(
+            [read_i32(&data, 4), read_i32(&data, 8), read_i32(&data, 12)],
+            [3, 3, 3]
+        );
+        assert_eq!(read_i32(&data, 24), 3 * 256);
+
+        // the center column is split by the hidden center voxel
+        let xoffset = |x: usize| read_i32(&data, 28 + x * 4) as usize;
+        let xyoffset = 28 + 4 * 4;
+        let column = |x: usize, y: usize| {
+            let p = xyoffset + (x * 4 + y) * 2;
+            i16::from_le_bytes([data[p], data[p + 1]]) as usize
+        };
+        let start = 28 + xoffset(1) + column(1, 1);
+        assert_eq!(column(1, 2) - column(1, 1), 2 * 4);
+        // the top of the volume (z 2 in vox) is the z 0 of kvx, with the color index 3
+        assert_eq!(&data[start..start + 4], &[0, 1, FACE_TOP, 3]);
+        assert_eq!(&data[start + 4..start + 8], &[2, 1, FACE_BOTTOM, 1]);
+
+        // the color index 1 is the first color
+        let palette = data.len() - 768;
+        assert_eq!(&data[palette + 3..palette + 6], &[63, 0, 0]);
+    }
+}
@@ -0,0 +1,355 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use super::{invalid_data, PointCloud, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+fn read_u16(b: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([b[pos], b[pos + 1]])
+}
+
+fn read_u32(b: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([b[pos], b[pos + 1], b[pos + 2], b[pos + 3]])
+}
+
+fn read_i32(b: &[u8], pos: usize) -> i32 {
+    read_u32(b, pos) as i32
+}
+
+fn read_u64(b: &[u8], pos: usize) -> u64 {
+    let mut v = [0u8; 8];
+    v.copy_from_slice(&b[pos..pos + 8]);
+    u64::from_le_bytes(v)
+}
+
+fn read_f64(b: &[u8], pos: usize) -> f64 {
+    f64::from_bits(read_u64(b, pos))
+}
+
+/// the color of the asprs standard classes
+fn classification_color(classification: u8) -> [u8; 3] {
+    match classification {
+        0 | 1 => [160, 160, 160], // created, unclassified
+        2 => [150, 110, 70],      // ground
+        3 => [140, 200, 90],      // low vegetation
+        4 => [80, 170, 60],       // medium vegetation
+        5 => [30, 120, 40],       // high vegetation
+        6 => [200, 80, 60],       // building
+        7 => [255, 0, 255],       // low point (noise)
+        8 => [255, 255, 0],       // model key-point
+        9 => [50, 110, 220],      // water
+        10 => [120, 80, 120],     // rail
+        11 => [90, 90, 90],       // road surface
+        12 => [200, 200, 120],    // overlap
+        13 => [230, 200, 50],     // wire guard
+        14 => [230, 170, 30],     // wire conductor
+        15 => [180, 180, 200],    // transmission tower
+        16 => [200, 150, 50],     // wire connector
+        17 => [170, 140, 110],    // bridge deck
+        18 => [255, 0, 128],      // high noise
+        c => {
+            // user defined classes, a stable color per code
+            let h = (c as u32).wrapping_mul(2654435761);
+            [(h >> 8) as u8, (h >> 16) as u8, (h >> 24) as u8]
+        }
+    }
+}
+
+/// blue for the low points to white for the high points
+fn elevation_color(t: f64) -> [u8; 3] {
+    const RAMP: [[f64; 3]; 6] = [
+        [0.0, 0.0, 180.0],
+        [0.0, 200.0, 200.0],
+        [40.0, 180.0, 40.0],
+        [230.0, 220.0, 60.0],
+        [200.0, 60.0, 30.0],
+        [255.0, 255.0, 255.0],
+    ];
+    let t = t.clamp(0.0, 1.0) * (RAMP.len() - 1) as f64;
+    let i = usize::min(t as usize, RAMP.len() - 2);
+    let f = t - i as f64;
+    let a = RAMP[i];
+    let b = RAMP[i + 1];
+    [
+        (a[0] + (b[0] - a[0]) * f).round() as u8,
+        (a[1] + (b[1] - a[1]) * f).round() as u8,
+        (a[2] + (b[2] - a[2]) * f).round() as u8,
+    ]
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "how the voxels of a las file are colored"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LasColorMode {
+    Classification, // asprs standard class colors
+    Rgb,            // the point colors, for the point formats with rgb
+    Elevation,      // a ramp from the lowest to the highest point
+}
+
+#[doc = "the voxelization options of a las file"]
+#[derive(Clone, Debug)]
+pub struct LasOptions {
+    pub voxel_size: f64,
+    pub color_mode: LasColorMode,
+    pub classifications: Vec<u8>, // keep only these classes, all if empty
+    pub default_color: [u8; 3],   // used in rgb mode when the points have no color
+}
+
+impl LasOptions {
+    #[doc = "rgb colored voxels of voxel_size, without class filter"]
+    pub fn create(voxel_size: f64) -> Self {
+        Self {
+            voxel_size,
+            color_mode: LasColorMode::Rgb,
+            classifications: vec![],
+            default_color: [200, 200, 200],
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a point record of a las file, scaled and offseted"]
+#[derive(Clone, Copy, Debug)]
+pub struct LasPoint {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub classification: u8,
+    pub rgb: Option<[u16; 3]>,
+}
+
+#[doc = "the points of a las 1.0 to 1.4 file"]
+pub struct LasFile {
+    pub version_major: u8,
+    pub version_minor: u8,
+    pub point_format: u8,
+    pub points: Vec<LasPoint>,
+}
+
+impl LasFile {
+    #[doc = "load a las file"]
+    pub fn load_from_file(file_path_name: String) -> std::io::Result<Self> {
+        let file = File::open(file_path_name)?;
+        Self::read(BufReader::new(file))
+    }
+
+    #[doc = "read a las file. point formats 0 to 10 are supported, laz compressed files are not"]
+    pub fn read<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut b: Vec<u8> = vec![];
+        reader.read_to_end(&mut b)?;
+        if b.len() < 227 || &b[0..4] != b"LASF" {
+            return Err(invalid_data("las: bad magic"));
+        }
+        let version_major = b[24];
+        let version_minor = b[25];
+        let offset_to_points = read_u32(&b, 96) as usize;
+        let format_byte = b[104];
+        if format_byte & 0x80 != 0 {
+            return Err(invalid_data("las: laz compressed points are not supported"));
+        }
+        let point_format = format_byte & 0x3f;
+        let record_length = read_u16(&b, 105) as usize;
+        let mut count = read_u32(&b, 107) as u64;
+        if count == 0 && version_minor >= 4 && b.len() >= 255 {
+            count = read_u64(&b, 247);
+        }
+        let scale = [read_f64(&b, 131), read_f64(&b, 139), read_f64(&b, 147)];
+        let offset = [read_f64(&b, 155), read_f64(&b, 163), read_f64(&b, 171)];
+
+        let (class_pos, rgb_pos) = match point_format {
+            0 | 1 | 4 => (15, None),
+            2 => (15, Some(20)),
+            3 | 5 => (15, Some(28)),
+            6 | 9 => (16, None),
+            7 | 8 | 10 => (16, Some(30)),
+            _ => return Err(invalid_data("las: unknown point format")),
+        };
+        let min_length = rgb_pos.map_or(class_pos + 1, |p| p + 6);
+        if record_length < min_length {
+            return Err(invalid_data("las: point record too small"));
+        }
+        // a bad header can give a count too big for the size of the file
+        let end = usize::try_from(count)
+            .ok()
+            .and_then(|c| c.checked_mul(record_length))
+            .and_then(|n| n.checked_add(offset_to_points));
+        if end.map_or(true, |end| end > b.len()) {
+            return Err(invalid_data("las: unexpected end of file"));
+        }
+
+        let mut points = Vec::with_capacity(count as usize);
+        for i in 0..count as usize {
+            let p = offset_to_points + i * record_length;
+            let rec = &b[p..p + record_length];
+            let classification = if point_format < 6 {
+                rec[class_pos] & 0x1f
+            } else {
+                rec[class_pos]
+            };
+            points.push(LasPoint {
+                x: read_i32(rec, 0) as f64 * scale[0] + offset[0],
+                y: read_i32(rec, 4) as f64 * scale[1] + offset[1],
+                z: read_i32(rec, 8) as f64 * scale[2] + offset[2],
+                classification,
+                rgb: rgb_pos
+                    .map(|p| [read_u16(rec, p), read_u16(rec, p + 2), read_u16(rec, p + 4)]),
+            });
+        }
+
+        Ok(Self {
+            version_major,
+            version_minor,
+            point_format,
+            points,
+        })
+    }
+
+    #[doc = "get the filtered and colored points as a point cloud"]
+    pub fn to_point_cloud(&self, options: &LasOptions) -> PointCloud {
+        let points: Vec<&LasPoint> = self
+            .points
+            .iter()
+            .filter(|p| {
+                options.classifications.is_empty()
+                    || options.classifications.contains(&p.classification)
+            })
+            .collect();
+
+        // rgb are 16 bits, but many writers put 8 bits values in them
+        let is_16_bits = points
+            .iter()
+            .filter_map(|p| p.rgb)
+            .any(|c| c.iter().any(|v| *v > 255));
+        let min_z = points.iter().map(|p| p.z).fold(f64::MAX, f64::min);
+        let max_z = points.iter().map(|p| p.z).fold(f64::MIN, f64::max);
+        let range_z = if max_z > min_z { max_z - min_z } else { 1.0 };
+
+        let mut cloud = PointCloud::create_empty();
+        for p in points {
+            cloud.points.push([p.x, p.y, p.z]);
+            cloud.colors.push(match options.color_mode {
+                LasColorMode::Classification => classification_color(p.classification),
+                LasColorMode::Elevation => elevation_color((p.z - min_z) / range_z),
+                LasColorMode::Rgb => match p.rgb {
+                    Some(c) if is_16_bits => c.map(|v| (v >> 8) as u8),
+                    Some(c) => c.map(|v| v as u8),
+                    None => options.default_color,
+                },
+            });
+        }
+        cloud
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "voxelize the points of a las file, return the count of voxels added"]
+    pub fn add_las(&mut self, las: &LasFile, options: &LasOptions) -> usize {
+        let cloud = las.to_point_cloud(options);
+        self.add_point_cloud(&cloud, options.voxel_size, options.default_color)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn make_las(points: &[([i32; 3], u8, [u16; 3])]) -> Vec<u8> {
+        let mut b = vec![0u8; 227];
+        b[0..4].copy_from_slice(b"LASF");
+        b[24] = 1;
+        b[25] = 2;
+        b[94..96].copy_from_slice(&227u16.to_le_bytes());
+        b[96..100].copy_from_slice(&227u32.to_le_bytes());
+        b[104] = 2;
+        b[105..107].copy_from_slice(&26u16.to_le_bytes());
+        b[107..111].copy_from_slice(&(points.len() as u32).to_le_bytes());
+        for (i, s) in [0.5f64, 0.5, 0.5].iter().enumerate() {
+            b[131 + i * 8..139 + i * 8].copy_from_slice(&s.to_le_bytes());
+        }
+        for (p, class, rgb) in points {
+            let mut rec = vec![0u8; 26];
+            for i in 0..3 {
+                rec[i * 4..i * 4 + 4].copy_from_slice(&p[i].to_le_bytes());
+                rec[20 + i * 2..22 + i * 2].copy_from_slice(&rgb[i].to_le_bytes());
+            }
+            rec[15] = *class;
+            b.extend_from_slice(&rec);
+        }
+        b
+    }
+
+    #[test]
+    fn test_read_las_format_2() {
+        let data = make_las(&[([2, 4, 6], 2, [65535, 0, 0]), ([0, 0, 0], 6, [0, 256, 0])]);
+        let las = LasFile::read(Cursor::new(data)).unwrap();
+        assert_eq!(las.point_format, 2);
+        assert_eq!(las.points.len(), 2);
+        assert_eq!(las.points[0].x, 1.0);
+        assert_eq!(las.points[0].z, 3.0);
+        assert_eq!(las.points[1].classification, 6);
+
+        let mut options = LasOptions::create(1.0);
+        let cloud = las.to_point_cloud(&options);
+        assert_eq!(cloud.colors, vec![[255, 0, 0], [0, 1, 0]]);
+
+        options.classifications = vec![6];
+        options.color_mode = LasColorMode::Classification;
+        let cloud = las.to_point_cloud(&options);
+        assert_eq!(cloud.points, vec![[0.0, 0.0, 0.0]]);
+        assert_eq!(cloud.colors, vec![classification_color(6)]);
+    }
+
+    #[test]
+    fn test_read_las_bad_count() {
+        let mut data = make_las(&[([0, 0, 0], 2, [0; 3]), ([0, 0, 0], 2, [0; 3])]);
+        // a las 1.4 header with a 64 bits count of points
+        data[25] = 4;
+        data[107..111].copy_from_slice(&0u32.to_le_bytes());
+        data[247..255].copy_from_slice(&(u64::MAX / 3).to_le_bytes());
+        assert!(LasFile::read(Cursor::new(data.clone())).is_err());
+        data[247..255].copy_from_slice(&2u64.to_le_bytes());
+        data[96..100].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(LasFile::read(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn test_add_las() {
+        let data = make_las(&[
+            ([0, 0, 0], 2, [0; 3]),
+            ([0, 0, 20], 2, [0; 3]),
+            ([0, 0, 10], 6, [0; 3]),
+        ]);
+        let las = LasFile::read(Cursor::new(data)).unwrap();
+        let mut options = LasOptions::create(1.0);
+        options.color_mode = LasColorMode::Elevation;
+        let mut vox = VoxWriter::create_empty();
+        assert_eq!(vox.add_las(&las, &options), 3);
+        let color =
+            |vox: &VoxWriter, z| vox.get_palette_color(vox.get_voxel(0, 0, z).unwrap() as u8);
+        // the ramp goes from blue at the lowest point to white at the highest one
+        assert_eq!(color(&vox, 0), [0, 0, 180, 255]);
+        assert_eq!(color(&vox, 5), [135, 200, 50, 255]);
+        assert_eq!(color(&vox, 10), [255, 255, 255, 255]);
+
+        // the points of the other classes are dropped
+        options.classifications = vec![2];
+        let mut vox = VoxWriter::create_empty();
+        assert_eq!(vox.add_las(&las, &options), 2);
+        assert_eq!(vox.get_voxel(0, 0, 5), None);
+        assert_eq!(color(&vox, 10), [255, 255, 255, 255]);
+        options.classifications = vec![6];
+        options.color_mode = LasColorMode::Classification;
+        let mut vox = VoxWriter::create_empty();
+        assert_eq!(vox.add_las(&las, &options), 1);
+        let c = classification_color(6);
+        assert_eq!(color(&vox, 0), [c[0], c[1], c[2], 255]);
+    }
+}
@@ -0,0 +1,77 @@
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "get a scene with the main volume at several levels of detail. LOD0 is the full volume, \
+             the next ones are downsampled by the factors, see downsample. each level is a node named \
+             LODn on its own layer named LODn, hidden except LOD0, and moved so its min corner is at the one \
+             of LOD0. the main volume of the scene is empty"]
+    pub fn get_lods(&self, factors: &[i32], min_occupancy: f64) -> VoxWriter {
+        let mut scene = self.create_same();
+        let mut lod0 = self.create_same();
+        lod0.rebuild(&self.get_raw_voxels([0; 3]));
+        let mut levels = vec![(1, lod0)];
+        for factor in factors {
+            levels.push((*factor, self.downsample(*factor, min_occupancy)));
+        }
+        for (i, (factor, volume)) in levels.into_iter().enumerate() {
+            let name = format!("LOD{}", i);
+            let layer_id = scene.add_layer(name.clone(), i > 0);
+            // the min block of the level goes where its first voxel is in LOD0
+            let offset = match volume.get_bounds() {
+                Some((min, _)) => min.map(|v| v.saturating_mul(factor.max(1) - 1)),
+                None => [0; 3],
+            };
+            scene.add_node(name, volume, offset, layer_id);
+        }
+        scene
+    }
+
+    #[doc = "save the main volume at several levels of detail in one file, see get_lods"]
+    pub fn save_lods_to_file(
+        &self,
+        file_path_name: String,
+        factors: &[i32],
+        min_occupancy: f64,
+    ) -> std::io::Result<()> {
+        self.get_lods(factors, min_occupancy)
+            .save_to_file(file_path_name)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::ShapeFill;
+    use super::*;
+
+    #[test]
+    fn test_get_lods() {
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_box([0; 3], [7; 3], 2, ShapeFill::Solid);
+        let scene = vox.get_lods(&[2, 4], 0.5);
+        assert_eq!(scene.count_voxels(), 0);
+        let layers = scene.get_layers();
+        // the layer 0 is the one of the empty main volume
+        assert_eq!(layers.len(), 4);
+        assert_eq!((layers[1].name.as_str(), layers[1].hidden), ("LOD0", false));
+        assert_eq!((layers[3].name.as_str(), layers[3].hidden), ("LOD2", true));
+        let nodes = scene.get_nodes();
+        let counts: Vec<u64> = nodes.iter().map(|n| n.volume.count_voxels()).collect();
+        assert_eq!(counts, vec![512, 64, 8]);
+        assert_eq!(nodes[1].layer_id, 2);
+        assert_eq!(nodes[2].volume.get_voxel(1, 1, 1), Some(2));
+
+        // the levels keep the place of the volume
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_box([100, -40, 8], [107, -33, 15], 2, ShapeFill::Solid);
+        let scene = vox.get_lods(&[2, 4], 0.5);
+        for node in scene.get_nodes() {
+            let (min, _) = node.volume.get_bounds().unwrap();
+            let corner = [0, 1, 2].map(|i| node.offset[i] + min[i]);
+            assert_eq!(corner, [100, -40, 8]);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use super::flood::BitGrid;
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "the neighbourhood of a voxel used by the morphology operators"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StructuringElement {
+    Cross(i32),  // the voxels at a manhattan distance <= r
+    Cube(i32),   // the voxels in the cube of half size r
+    Sphere(i32), // the voxels at an euclidean distance <= r
+}
+
+impl StructuringElement {
+    fn get_radius(self) -> i32 {
+        match self {
+            StructuringElement::Cross(r)
+            | StructuringElement::Cube(r)
+            | StructuringElement::Sphere(r) => r.max(0),
+        }
+    }
+
+    /// the offsets of the element without the center, from the nearest to the farthest
+    fn get_offsets(self) -> Vec<[i32; 3]> {
+        let r = self.get_radius();
+        let mut offsets = vec![];
+        for z in -r..=r {
+            for y in -r..=r {
+                for x in -r..=r {
+                    let inside = match self {
+                        StructuringElement::Cross(_) => x.abs() + y.abs() + z.abs() <= r,
+                        StructuringElement::Cube(_) => true,
+                        StructuringElement::Sphere(_) => x * x + y * y + z * z <= r * r,
+                    };
+                    if inside && [x, y, z] != [0; 3] {
+                        offsets.push([x, y, z]);
+                    }
+                }
+            }
+        }
+        offsets.sort_by_key(|d| d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+        offsets
+    }
+}
+
+#[doc = "the color of the voxels created by a dilation"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MorphologyColor {
+    Nearest,    // the color of the nearest voxel
+    Majority,   // the most common color of the voxels in the element around
+    Fixed(i32), // this color index
+}
+
+fn add(p: [i32; 3], d: [i32; 3]) -> [i32; 3] {
+    [p[0] + d[0], p[1] + d[1], p[2] + d[2]]
+}
+
+/// the empty cells in the element around the cells, each one once
+fn dilate_cells(
+    cells: &[[i32; 3]],
+    grid: &BitGrid,
+    offsets: &[[i32; 3]],
+    seen: &mut BitGrid,
+) -> Vec<[i32; 3]> {
+    let mut added = vec![];
+    for p in cells {
+        for d in offsets {
+            let n = add(*p, *d);
+            if !grid.get(n) && !seen.get(n) {
+                seen.set(n, true);
+                added.push(n);
+            }
+        }
+    }
+    added
+}
+
+/// the cells whose element is full in grid
+fn erode_cells(cells: &[[i32; 3]], grid: &BitGrid, offsets: &[[i32; 3]]) -> Vec<[i32; 3]> {
+    cells
+        .iter()
+        .filter(|p| offsets.iter().all(|d| grid.get(add(**p, *d))))
+        .copied()
+        .collect()
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    fn get_voxel_positions(&self) -> Vec<[i32; 3]> {
+        let mut cells = Vec::with_capacity(self.count_voxels() as usize);
+        self.for_each_voxel(|x, y, z, _| cells.push([x, y, z]));
+        cells
+    }
+
+    /// add the cells with the color rule, from the voxels of solid in the element around them
+    fn add_morphology_voxels(
+        &mut self,
+        cells: &[[i32; 3]],
+        solid: &BitGrid,
+        offsets: &[[i32; 3]],
+        color: MorphologyColor,
+    ) {
+        let mut colors = vec![];
+        for p in cells {
+            let c = match color {
+                MorphologyColor::Fixed(c) => c,
+                MorphologyColor::Nearest => offsets
+                    .iter()
+                    .map(|d| add(*p, *d))
+                    .find(|n| solid.get(*n))
+                    .and_then(|n| self.get_voxel(n[0], n[1], n[2]))
+                    .unwrap_or(1),
+                MorphologyColor::Majority => {
+                    // the ties go to the nearest color
+                    let mut counts = [0u32; 256];
+                    let mut best: Option<i32> = None;
+                    for n in offsets.iter().map(|d| add(*p, *d)) {
+                        if let Some(c) = solid
+                            .get(n)
+                            .then(|| self.get_voxel(n[0], n[1], n[2]))
+                            .flatten()
+                        {
+                            counts[c as u8 as usize] += 1;
+                            if best.map_or(true, |b| {
+                                counts[c as u8 as usize] > counts[b as u8 as usize]
+                            }) {
+                                best = Some(c);
+                            }
+                        }
+                    }
+                    best.unwrap_or(1)
+                }
+            };
+            colors.push(c);
+        }
+        for (p, c) in cells.iter().zip(colors) {
+            self.add_voxel(p[0], p[1], p[2], c);
+        }
+    }
+
+    #[doc = "add the empty voxels in the element around the voxels, colored by the color rule. \
+             return the count of voxels added"]
+    pub fn dilate(&mut self, element: StructuringElement, color: MorphologyColor) -> usize {
+        let (solid, min, max) = match self.get_occupancy(element.get_radius()) {
+            Some(g) => g,
+            None => return 0,
+        };
+        let offsets = element.get_offsets();
+        let mut seen = BitGrid::create(min, max);
+        let added = dilate_cells(&self.get_voxel_positions(), &solid, &offsets, &mut seen);
+        self.add_morphology_voxels(&added, &solid, &offsets, color);
+        added.len()
+    }
+
+    #[doc = "remove the voxels with an empty voxel in the element around them. return the count of voxels removed"]
+    pub fn erode(&mut self, element: StructuringElement) -> usize {
+        let (solid, _, _) = match self.get_occupancy(0) {
+            Some(g) => g,
+            None => return 0,
+        };
+        let offsets = element.get_offsets();
+        let before = self.count_voxels();
+        self.retain_voxels(|p, _| offsets.iter().all(|d| solid.get(add(p, *d))));
+        (before - self.count_voxels()) as usize
+    }
+
+    #[doc = "erode then dilate, it removes the parts thinner than the element. \
+             the voxels kept have their colors. return the count of voxels removed"]
+    pub fn open(&mut self, element: StructuringElement) -> usize {
+        let (solid, min, max) = match self.get_occupancy(element.get_radius()) {
+            Some(g) => g,
+            None => return 0,
+        };
+        let offsets = element.get_offsets();
+        let eroded = erode_cells(&self.get_voxel_positions(), &solid, &offsets);
+        let mut kept = BitGrid::create(min, max);
+        for p in &eroded {
+            kept.set(*p, true);
+        }
+        let mut seen = BitGrid::create(min, max);
+        for p in dilate_cells(&eroded, &kept, &offsets, &mut seen) {
+            kept.set(p, true);
+        }
+        let before = self.count_voxels();
+        self.retain_voxels(|p, _| kept.get(p));
+        (before - self.count_voxels()) as usize
+    }
+
+    #[doc = "dilate then erode, it fills the holes and gaps smaller than the element. \
+             the new voxels are colored by the color rule. return the count of voxels added"]
+    pub fn close(&mut self, element: StructuringElement, color: MorphologyColor) -> usize {
+        let r = element.get_radius();
+        let (solid, min, max) = match self.get_occupancy(2 * r) {
+            Some(g) => g,
+            None => return 0,
+        };
+        let offsets = element.get_offsets();
+        let mut dilated = BitGrid::create(min, max);
+        let added = dilate_cells(&self.get_voxel_positions(), &solid, &offsets, &mut dilated);
+        self.for_each_voxel(|x, y, z, _| dilated.set([x, y, z], true));
+        let closed = erode_cells(&added, &dilated, &offsets);
+        self.add_morphology_voxels(&closed, &solid, &offsets, color);
+        closed.len()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::ShapeFill;
+    use super::*;
+
+    #[test]
+    fn test_dilate_erode() {
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(0, 0, 0, 1);
+        assert_eq!(
+            vox.dilate(StructuringElement::Cross(1), MorphologyColor::Nearest),
+            6
+        );
+        assert_eq!(vox.get_voxel(0, 0, -1), Some(1));
+        assert_eq!(vox.erode(StructuringElement::Cross(1)), 6);
+        assert_eq!(vox.count_voxels(), 1);
+        assert_eq!(
+            vox.dilate(StructuringElement::Cube(1), MorphologyColor::Fixed(2)),
+            26
+        );
+        assert_eq!(vox.get_voxel(1, 1, 1), Some(2));
+
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(0, 0, 0, 1);
+        vox.add_voxel(2, 0, 0, 3);
+        vox.add_voxel(2, 1, 0, 3);
+        vox.dilate(StructuringElement::Sphere(2), MorphologyColor::Majority);
+        assert_eq!(vox.get_voxel(1, 0, 0), Some(3));
+        assert_eq!(vox.get_voxel(-1, 0, 0), Some(1));
+    }
+
+    #[test]
+    fn test_open_close() {
+        // a slab with a hole in its middle
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_box([0, 0, 0], [4, 4, 0], 1, ShapeFill::Solid);
+        vox.remove_voxel(2, 2, 0);
+        let added = vox.close(StructuringElement::Cube(1), MorphologyColor::Fixed(3));
+        assert_eq!(added, 1);
+        assert_eq!(vox.get_voxel(2, 2, 0), Some(3));
+
+        // a box with a thin spike
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_box([0; 3], [4; 3], 1, ShapeFill::Solid);
+        vox.draw_line([5, 2, 2], [8, 2, 2], 2);
+        // the edges of the box are cut by the cross, 125 - 27 - 6 * 9 voxels. the root of the spike
+        // is kept by the center of the face
+        assert_eq!(vox.open(StructuringElement::Cross(1)), 44 + 3);
+        assert_eq!(vox.get_voxel(5, 2, 2), Some(2));
+        assert_eq!(vox.get_voxel(6, 2, 2), None);
+        assert_eq!(vox.get_voxel(1, 0, 0), None);
+        assert_eq!(vox.get_voxel(1, 1, 0), Some(1));
+    }
+}
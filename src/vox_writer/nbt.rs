@@ -0,0 +1,383 @@
+use super::invalid_data;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+pub(crate) const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+const MAX_DEPTH: usize = 512;
+
+/// a minecraft named binary tag. the compounds keep the order of their entries
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(u8, Vec<Nbt>), // type of the elements, elements
+    Compound(Vec<(String, Nbt)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Nbt {
+    fn tag_id(&self) -> u8 {
+        match self {
+            Nbt::Byte(_) => TAG_BYTE,
+            Nbt::Short(_) => TAG_SHORT,
+            Nbt::Int(_) => TAG_INT,
+            Nbt::Long(_) => TAG_LONG,
+            Nbt::Float(_) => TAG_FLOAT,
+            Nbt::Double(_) => TAG_DOUBLE,
+            Nbt::ByteArray(_) => TAG_BYTE_ARRAY,
+            Nbt::String(_) => TAG_STRING,
+            Nbt::List(..) => TAG_LIST,
+            Nbt::Compound(_) => TAG_COMPOUND,
+            Nbt::IntArray(_) => TAG_INT_ARRAY,
+            Nbt::LongArray(_) => TAG_LONG_ARRAY,
+        }
+    }
+
+    /// get an entry of a compound
+    pub fn get(&self, name: &str) -> Option<&Nbt> {
+        match self {
+            Nbt::Compound(entries) => entries.iter().find(|e| e.0 == name).map(|e| &e.1),
+            _ => None,
+        }
+    }
+
+    /// get a byte, short, int or long as an i64
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Nbt::Byte(v) => Some(*v as i64),
+            Nbt::Short(v) => Some(*v as i64),
+            Nbt::Int(v) => Some(*v as i64),
+            Nbt::Long(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Nbt::Byte(v) => out.push(*v as u8),
+            Nbt::Short(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Nbt::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Nbt::Long(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Nbt::Float(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Nbt::Double(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Nbt::ByteArray(v) => {
+                out.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                out.extend_from_slice(v);
+            }
+            Nbt::String(s) => write_string(out, s),
+            Nbt::List(kind, items) => {
+                out.push(if items.is_empty() {
+                    *kind
+                } else {
+                    items[0].tag_id()
+                });
+                out.extend_from_slice(&(items.len() as i32).to_be_bytes());
+                for item in items {
+                    item.write(out);
+                }
+            }
+            Nbt::Compound(entries) => {
+                for (name, value) in entries {
+                    out.push(value.tag_id());
+                    write_string(out, name);
+                    value.write(out);
+                }
+                out.push(TAG_END);
+            }
+            Nbt::IntArray(v) => {
+                out.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                for i in v {
+                    out.extend_from_slice(&i.to_be_bytes());
+                }
+            }
+            Nbt::LongArray(v) => {
+                out.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                for i in v {
+                    out.extend_from_slice(&i.to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    let bytes = &s.as_bytes()[..usize::min(s.len(), u16::MAX as usize)];
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+struct NbtReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl NbtReader<'_> {
+    fn take(&mut self, count: usize) -> std::io::Result<&[u8]> {
+        if self.pos + count > self.data.len() {
+            return Err(invalid_data("nbt: truncated data"));
+        }
+        self.pos += count;
+        Ok(&self.data[self.pos - count..self.pos])
+    }
+
+    fn take_array<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        let mut a = [0u8; N];
+        a.copy_from_slice(self.take(N)?);
+        Ok(a)
+    }
+
+    fn read_len(&mut self) -> std::io::Result<usize> {
+        let len = i32::from_be_bytes(self.take_array()?);
+        if len < 0 || len as usize > self.data.len() {
+            return Err(invalid_data("nbt: bad length"));
+        }
+        Ok(len as usize)
+    }
+
+    fn read_string(&mut self) -> std::io::Result<String> {
+        let len = u16::from_be_bytes(self.take_array()?) as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).to_string())
+    }
+
+    fn read_payload(&mut self, kind: u8, depth: usize) -> std::io::Result<Nbt> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("nbt: too deep"));
+        }
+        Ok(match kind {
+            TAG_BYTE => Nbt::Byte(self.take(1)?[0] as i8),
+            TAG_SHORT => Nbt::Short(i16::from_be_bytes(self.take_array()?)),
+            TAG_INT => Nbt::Int(i32::from_be_bytes(self.take_array()?)),
+            TAG_LONG => Nbt::Long(i64::from_be_bytes(self.take_array()?)),
+            TAG_FLOAT => Nbt::Float(f32::from_be_bytes(self.take_array()?)),
+            TAG_DOUBLE => Nbt::Double(f64::from_be_bytes(self.take_array()?)),
+            TAG_BYTE_ARRAY => {
+                let len = self.read_len()?;
+                Nbt::ByteArray(self.take(len)?.to_vec())
+            }
+            TAG_STRING => Nbt::String(self.read_string()?),
+            TAG_LIST => {
+                let item_kind = self.take(1)?[0];
+                let len = self.read_len()?;
+                let mut items = Vec::with_capacity(usize::min(len, 4096));
+                for _ in 0..len {
+                    items.push(self.read_payload(item_kind, depth + 1)?);
+                }
+                Nbt::List(item_kind, items)
+            }
+            TAG_COMPOUND => {
+                let mut entries = vec![];
+                loop {
+                    let item_kind = self.take(1)?[0];
+                    if item_kind == TAG_END {
+                        break;
+                    }
+                    let name = self.read_string()?;
+                    entries.push((name, self.read_payload(item_kind, depth + 1)?));
+                }
+                Nbt::Compound(entries)
+            }
+            TAG_INT_ARRAY => {
+                let len = self.read_len()?;
+                let bytes = self.take(len * 4)?;
+                Nbt::IntArray(
+                    bytes
+                        .chunks_exact(4)
+                        .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                        .collect(),
+                )
+            }
+            TAG_LONG_ARRAY => {
+                let len = self.read_len()?;
+                let bytes = self.take(len * 8)?;
+                Nbt::LongArray(
+                    bytes
+                        .chunks_exact(8)
+                        .map(|b| {
+                            i64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+                        })
+                        .collect(),
+                )
+            }
+            _ => return Err(invalid_data("nbt: bad tag")),
+        })
+    }
+}
+
+/// read the named root tag of uncompressed nbt data
+pub(crate) fn read_nbt(data: &[u8]) -> std::io::Result<(String, Nbt)> {
+    let mut reader = NbtReader { data, pos: 0 };
+    let kind = reader.take(1)?[0];
+    if kind == TAG_END {
+        return Err(invalid_data("nbt: no root tag"));
+    }
+    let name = reader.read_string()?;
+    let value = reader.read_payload(kind, 0)?;
+    Ok((name, value))
+}
+
+/// write a named root tag as uncompressed nbt data
+pub(crate) fn write_nbt(name: &str, value: &Nbt) -> Vec<u8> {
+    let mut out = vec![value.tag_id()];
+    write_string(&mut out, name);
+    value.write(&mut out);
+    out
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::zlib::tests::mutations;
+    use super::*;
+    use nbt::{Blob, Value};
+
+    /// the same value in the nbt crate
+    fn to_value(value: &Nbt) -> Value {
+        match value {
+            Nbt::Byte(v) => Value::Byte(*v),
+            Nbt::Short(v) => Value::Short(*v),
+            Nbt::Int(v) => Value::Int(*v),
+            Nbt::Long(v) => Value::Long(*v),
+            Nbt::Float(v) => Value::Float(*v),
+            Nbt::Double(v) => Value::Double(*v),
+            Nbt::ByteArray(v) => Value::ByteArray(v.iter().map(|b| *b as i8).collect()),
+            Nbt::String(v) => Value::String(v.clone()),
+            Nbt::List(_, items) => Value::List(items.iter().map(to_value).collect()),
+            Nbt::Compound(entries) => Value::Compound(
+                entries
+                    .iter()
+                    .map(|(k, v)| (k.clone(), to_value(v)))
+                    .collect(),
+            ),
+            Nbt::IntArray(v) => Value::IntArray(v.clone()),
+            Nbt::LongArray(v) => Value::LongArray(v.clone()),
+        }
+    }
+
+    /// a named root compound as a blob of the nbt crate
+    fn to_blob(name: &str, value: &Nbt) -> Blob {
+        let mut blob = Blob::named(name);
+        if let Nbt::Compound(entries) = value {
+            for (k, v) in entries {
+                blob.insert(k.as_str(), to_value(v)).unwrap();
+            }
+        }
+        blob
+    }
+
+    /// a schematic like compound with all the tags
+    fn sample_value() -> Nbt {
+        let block = |name: &str, id: i32| {
+            Nbt::Compound(vec![
+                ("Name".to_string(), Nbt::String(name.to_string())),
+                ("Id".to_string(), Nbt::Int(id)),
+            ])
+        };
+        Nbt::Compound(vec![
+            ("Version".to_string(), Nbt::Int(2)),
+            ("Width".to_string(), Nbt::Short(-300)),
+            ("Flag".to_string(), Nbt::Byte(-1)),
+            ("Seed".to_string(), Nbt::Long(-1 << 40)),
+            ("Scale".to_string(), Nbt::Float(0.25)),
+            ("Ratio".to_string(), Nbt::Double(-1e100)),
+            ("BlockData".to_string(), Nbt::ByteArray((0..=255).collect())),
+            (
+                "Offset".to_string(),
+                Nbt::IntArray(vec![i32::MIN, 0, i32::MAX]),
+            ),
+            ("Bits".to_string(), Nbt::LongArray(vec![i64::MIN, 7])),
+            (
+                "Palette".to_string(),
+                Nbt::List(
+                    TAG_COMPOUND,
+                    vec![block("minecraft:air", 0), block("é ü", 1)],
+                ),
+            ),
+            (
+                "Nested".to_string(),
+                Nbt::List(
+                    TAG_LIST,
+                    vec![
+                        Nbt::List(TAG_SHORT, vec![Nbt::Short(1), Nbt::Short(2)]),
+                        Nbt::List(TAG_DOUBLE, vec![Nbt::Double(0.5)]),
+                    ],
+                ),
+            ),
+            ("Metadata".to_string(), Nbt::Compound(vec![])),
+        ])
+    }
+
+    #[test]
+    fn test_nbt_round_trip() {
+        let value = Nbt::Compound(vec![
+            ("a".to_string(), Nbt::Short(-3)),
+            ("b".to_string(), Nbt::List(TAG_INT, vec![])),
+            (
+                "c".to_string(),
+                Nbt::List(TAG_STRING, vec![Nbt::String("x".to_string())]),
+            ),
+            ("d".to_string(), Nbt::IntArray(vec![1, -1])),
+            ("e".to_string(), Nbt::ByteArray(vec![1, 255])),
+        ]);
+        let data = write_nbt("root", &value);
+        assert_eq!(&data[0..7], &[TAG_COMPOUND, 0, 4, b'r', b'o', b'o', b't']);
+        let (name, read) = read_nbt(&data).unwrap();
+        assert_eq!(name, "root");
+        assert_eq!(read, value);
+        assert_eq!(read.get("a").and_then(|v| v.as_i64()), Some(-3));
+    }
+
+    #[test]
+    fn test_nbt_crate() {
+        let value = sample_value();
+        // the nbt crate reads our data
+        let data = write_nbt("Schematic", &value);
+        let blob = Blob::from_reader(&mut &data[..]).unwrap();
+        assert_eq!(blob, to_blob("Schematic", &value));
+        // and we read its data, its compounds are not ordered
+        let mut data = vec![];
+        to_blob("Schematic", &value).to_writer(&mut data).unwrap();
+        let (name, read) = read_nbt(&data).unwrap();
+        assert_eq!(name, "Schematic");
+        assert_eq!(to_blob(&name, &read), to_blob("Schematic", &value));
+        assert_eq!(read.get("Width"), Some(&Nbt::Short(-300)));
+    }
+
+    #[test]
+    fn test_read_nbt_mutations() {
+        // the damaged data give an error or a value, never a panic
+        let mut data = vec![];
+        to_blob("", &sample_value()).to_writer(&mut data).unwrap();
+        for m in mutations(&data, 3000) {
+            let _ = read_nbt(&m);
+        }
+        // the nested lists stop at the max depth
+        let mut deep = vec![TAG_LIST, 0, 0];
+        for _ in 0..10000 {
+            deep.extend_from_slice(&[TAG_LIST, 0, 0, 0, 1]);
+        }
+        assert!(read_nbt(&deep).is_err());
+    }
+}
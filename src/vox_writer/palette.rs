@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+
+use super::{get_id_u8, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// the palette used by magicavoxel when a file has no RGBA chunk.
+/// entry i is the color of the color index i, packed like get_id_u8 (r | g << 8 | b << 16 | a << 24)
+pub(crate) fn default_palette() -> [u32; 256] {
+    let mut palette = [0u32; 256];
+    let steps = [0xffu8, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let ramp = [0xeeu8, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    let mut idx = 1;
+    // 6x6x6 cube without the black, blue varying first
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                if r == 0 && g == 0 && b == 0 {
+                    continue;
+                }
+                palette[idx] = get_id_u8(r, g, b, 255);
+                idx += 1;
+            }
+        }
+    }
+    // red, green, blue and gray ramps
+    for v in ramp {
+        palette[idx] = get_id_u8(v, 0, 0, 255);
+        idx += 1;
+    }
+    for v in ramp {
+        palette[idx] = get_id_u8(0, v, 0, 255);
+        idx += 1;
+    }
+    for v in ramp {
+        palette[idx] = get_id_u8(0, 0, v, 255);
+        idx += 1;
+    }
+    for v in ramp {
+        palette[idx] = get_id_u8(v, v, v, 255);
+        idx += 1;
+    }
+    palette
+}
+
+pub(crate) fn unpack_color(c: u32) -> [u8; 4] {
+    c.to_le_bytes()
+}
+
+fn color_dist2(a: [u8; 3], b: [u8; 3]) -> i32 {
+    let dr = a[0] as i32 - b[0] as i32;
+    let dg = a[1] as i32 - b[1] as i32;
+    let db = a[2] as i32 - b[2] as i32;
+    dr * dr + dg * dg + db * db
+}
+
+/// the CIELAB color of a srgb color, with the D65 white
+fn rgb_to_lab(rgb: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb.map(|v| {
+        let v = v as f64 / 255.0;
+        if v <= 0.04045 {
+            v / 12.92
+        } else {
+            f64::powf((v + 0.055) / 1.055, 2.4)
+        }
+    });
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let [fx, fy, fz] = [x, y, z].map(|t| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    });
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// the CIE76 color difference
+fn delta_e(a: [f64; 3], b: [f64; 3]) -> f64 {
+    f64::sqrt((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2))
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// reduce a set of weighted colors to at most max_colors colors with a median cut
+pub(crate) fn quantize_colors(colors: &[([u8; 3], u32)], max_colors: usize) -> Vec<[u8; 3]> {
+    if max_colors == 0 || colors.is_empty() {
+        return vec![];
+    }
+    let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![colors.to_vec()];
+    while boxes.len() < max_colors {
+        // split the box with the widest channel range
+        let mut best: Option<(usize, usize, i32)> = None;
+        for (i, b) in boxes.iter().enumerate() {
+            if b.len() < 2 {
+                continue;
+            }
+            for ch in 0..3 {
+                let lo = b.iter().map(|c| c.0[ch]).min().unwrap() as i32;
+                let hi = b.iter().map(|c| c.0[ch]).max().unwrap() as i32;
+                if best.map_or(true, |(_, _, r)| hi - lo > r) {
+                    best = Some((i, ch, hi - lo));
+                }
+            }
+        }
+        let (i, ch, range) = match best {
+            Some(b) => b,
+            None => break,
+        };
+        if range == 0 {
+            break;
+        }
+        let mut b = boxes.swap_remove(i);
+        b.sort_by_key(|c| c.0[ch]);
+        let total: u64 = b.iter().map(|c| c.1 as u64).sum();
+        let mut acc = 0u64;
+        let mut cut = 1;
+        for (k, c) in b.iter().enumerate() {
+            acc += c.1 as u64;
+            if acc * 2 >= total {
+                cut = k + 1;
+                break;
+            }
+        }
+        let cut = cut.clamp(1, b.len() - 1);
+        let upper = b.split_off(cut);
+        boxes.push(b);
+        boxes.push(upper);
+    }
+    boxes
+        .iter()
+        .map(|b| {
+            let total: u64 = b.iter().map(|c| c.1.max(1) as u64).sum();
+            let mut sum = [0u64; 3];
+            for c in b {
+                for (s, v) in sum.iter_mut().zip(c.0) {
+                    *s += v as u64 * c.1.max(1) as u64;
+                }
+            }
+            [
+                ((sum[0] + total / 2) / total) as u8,
+                ((sum[1] + total / 2) / total) as u8,
+                ((sum[2] + total / 2) / total) as u8,
+            ]
+        })
+        .collect()
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "get the rgba color of a color index. the magicavoxel default palette is used if no color was added"]
+    pub fn get_palette_color(&self, color_index: u8) -> [u8; 4] {
+        if color_index == 0 {
+            return [0, 0, 0, 0];
+        }
+        if self.colors.is_empty() {
+            return unpack_color(default_palette()[color_index as usize]);
+        }
+        // colors[i] is written at the RGBA chunk entry i, who is the color index i + 1
+        match self.colors.get(color_index as usize - 1) {
+            Some(c) => unpack_color(*c as u32),
+            None => [0, 0, 0, 0],
+        }
+    }
+
+    /// the color indices used by the main volume, the nodes and their frames
+    pub(crate) fn get_used_color_indices(&self) -> [bool; 256] {
+        let mut used = [false; 256];
+        let mut mark =
+            |vox: &VoxWriter| vox.for_each_voxel(|_, _, _, c| used[c as u8 as usize] = true);
+        mark(self);
+        for node in &self.nodes {
+            mark(&node.volume);
+            node.frames.iter().for_each(&mut mark);
+        }
+        used
+    }
+
+    /// when the default palette is in use, copy its used colors in the palette so the free slots
+    /// can be allocated without changing the colors of the voxels
+    pub(crate) fn copy_default_palette(&mut self) {
+        if !self.colors.is_empty() {
+            return;
+        }
+        let used = self.get_used_color_indices();
+        let palette = default_palette();
+        for i in 1..=255u8 {
+            if used[i as usize] {
+                let c = unpack_color(palette[i as usize]);
+                self.add_color(c[0], c[1], c[2], c[3], i - 1);
+            }
+        }
+    }
+
+    pub(crate) fn is_color_slot_used(&self, color_index: u8) -> bool {
+        match self.colors.get(color_index as usize - 1) {
+            Some(c) => *c != 0,
+            None => false,
+        }
+    }
+
+    fn find_color_exact(&self, rgb: [u8; 3]) -> Option<u8> {
+        (1..=255u8).find(|&i| {
+            let c = self.get_palette_color(i);
+            self.is_color_slot_used(i) && c[0] == rgb[0] && c[1] == rgb[1] && c[2] == rgb[2]
+        })
+    }
+
+    fn find_color_nearest(&self, rgb: [u8; 3]) -> Option<u8> {
+        (1..=255u8)
+            .filter(|&i| self.is_color_slot_used(i))
+            .min_by_key(|&i| {
+                let c = self.get_palette_color(i);
+                color_dist2(rgb, [c[0], c[1], c[2]])
+            })
+    }
+
+    fn allocate_color(&mut self, rgb: [u8; 3]) -> Option<u8> {
+        let slot = (1..=255u8).find(|&i| !self.is_color_slot_used(i))?;
+        self.add_color(rgb[0], rgb[1], rgb[2], 255, slot - 1);
+        Some(slot)
+    }
+
+    #[doc = "get the color index of a rgb color. the color is added to the palette if not present, \
+             or the nearest color is used when the 255 colors of the palette are already taken"]
+    pub fn get_color_index(&mut self, r: u8, g: u8, b: u8) -> i32 {
+        let rgb = [r, g, b];
+        self.copy_default_palette();
+        if let Some(i) = self.find_color_exact(rgb) {
+            return i as i32;
+        }
+        if let Some(i) = self.allocate_color(rgb) {
+            return i as i32;
+        }
+        self.find_color_nearest(rgb).unwrap_or(1) as i32
+    }
+
+    /// map many rgb colors at once. when there are more new colors than free palette slots,
+    /// the new colors are reduced with a median cut before being added to the palette
+    pub(crate) fn map_colors_rgb(&mut self, colors: &[[u8; 3]]) -> Vec<i32> {
+        self.copy_default_palette();
+        let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+        for c in colors {
+            *counts.entry(*c).or_default() += 1;
+        }
+        let mut mapping: HashMap<[u8; 3], i32> = HashMap::new();
+        let mut news: Vec<([u8; 3], u32)> = vec![];
+        for (c, n) in &counts {
+            match self.find_color_exact(*c) {
+                Some(i) => {
+                    mapping.insert(*c, i as i32);
+                }
+                None => news.push((*c, *n)),
+            }
+        }
+        // stable order whatever the hashmap does
+        news.sort();
+        let free = (1..=255u8).filter(|&i| !self.is_color_slot_used(i)).count();
+        if news.len() <= free {
+            for (c, _) in &news {
+                let i = self.allocate_color(*c).unwrap();
+                mapping.insert(*c, i as i32);
+            }
+        } else {
+            for c in quantize_colors(&news, free) {
+                if self.find_color_exact(c).is_none() {
+                    self.allocate_color(c);
+                }
+            }
+            for (c, _) in &news {
+                mapping.insert(*c, self.find_color_nearest(*c).unwrap_or(1) as i32);
+            }
+        }
+        colors.iter().map(|c| mapping[c]).collect()
+    }
+
+    #[doc = "remove the colors used by no voxel of the main volume, the nodes and their frames, \
+             merge the colors closer than max_delta_e (CIE76 on CIELAB, 2.3 is barely visible) \
+             into the most used one, then compact the palette from the index 1 and remap the voxels. \
+             return the count of colors removed from the palette"]
+    pub fn optimize_palette(&mut self, max_delta_e: f64) -> usize {
+        let mut counts = [0u64; 256];
+        let mut count =
+            |vox: &VoxWriter| vox.for_each_voxel(|_, _, _, c| counts[c as u8 as usize] += 1);
+        count(self);
+        for node in &self.nodes {
+            count(&node.volume);
+            node.frames.iter().for_each(&mut count);
+        }
+        let before = if self.colors.is_empty() {
+            255
+        } else {
+            (1..=255u8).filter(|&i| self.is_color_slot_used(i)).count()
+        };
+
+        // the most used colors first, each one is merged into the first kept color close enough
+        let mut used: Vec<u8> = (1..=255u8).filter(|&i| counts[i as usize] > 0).collect();
+        if used.is_empty() {
+            return 0;
+        }
+        used.sort_by_key(|&i| (std::cmp::Reverse(counts[i as usize]), i));
+        let mut kept: Vec<(u8, [f64; 3], u8)> = vec![]; // color index, lab, alpha
+        let mut merged = [0u8; 256];
+        for i in used {
+            let c = self.get_palette_color(i);
+            let lab = rgb_to_lab([c[0], c[1], c[2]]);
+            let target = kept
+                .iter()
+                .find(|k| k.2 == c[3] && delta_e(k.1, lab) <= max_delta_e);
+            merged[i as usize] = match target {
+                Some(k) => k.0,
+                None => {
+                    kept.push((i, lab, c[3]));
+                    i
+                }
+            };
+        }
+
+        // the kept colors keep their order
+        kept.sort_by_key(|k| k.0);
+        let mut mapping = [0u8; 256];
+        let mut colors = vec![];
+        for (n, k) in kept.iter().enumerate() {
+            mapping[k.0 as usize] = n as u8 + 1;
+            let c = self.get_palette_color(k.0);
+            colors.push(get_id_u8(c[0], c[1], c[2], c[3]) as i32);
+        }
+        for i in 1..256 {
+            mapping[i] = mapping[merged[i] as usize];
+        }
+        self.colors = colors;
+
+        let mut remap = |vox: &mut VoxWriter| {
+            vox.retain_voxels(|_, c| {
+                if *c != 0 {
+                    *c = mapping[*c as usize];
+                }
+                true
+            })
+        };
+        remap(self);
+        for node in self.nodes.iter_mut() {
+            remap(&mut node.volume);
+            node.frames.iter_mut().for_each(&mut remap);
+        }
+        before.saturating_sub(kept.len())
+    }
+
+    #[doc = "add a voxel at pos x,y,z with a rgb color, the color index is found or added in the palette"]
+    pub fn add_voxel_rgb(&mut self, v_x: i32, v_y: i32, v_z: i32, r: u8, g: u8, b: u8) {
+        let color_index = self.get_color_index(r, g, b);
+        self.add_voxel(v_x, v_y, v_z, color_index);
+    }
+
+    #[doc = "add many voxels with rgb colors. the palette is built for all the colors at once, \
+             so a median cut keeps the most representative colors if there are more than 255"]
+    pub fn add_voxels_rgb(&mut self, voxels: &[([i32; 3], [u8; 3])]) {
+        let colors: Vec<[u8; 3]> = voxels.iter().map(|v| v.1).collect();
+        let indices = self.map_colors_rgb(&colors);
+        for (v, c) in voxels.iter().zip(indices) {
+            self.add_voxel(v.0[0], v.0[1], v.0[2], c);
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_palette() {
+        let palette = default_palette();
+        assert_eq!(palette[0], 0x00000000);
+        assert_eq!(palette[1], 0xffffffff);
+        assert_eq!(palette[2], 0xffccffff);
+        assert_eq!(palette[7], 0xffffccff);
+        assert_eq!(palette[216], 0xff0000ee);
+        assert_eq!(palette[255], 0xff111111);
+    }
+
+    #[test]
+    fn test_get_color_index() {
+        let mut vox = VoxWriter::create_empty();
+        assert_eq!(vox.get_color_index(10, 20, 30), 1);
+        assert_eq!(vox.get_color_index(40, 50, 60), 2);
+        assert_eq!(vox.get_color_index(10, 20, 30), 1);
+        assert_eq!(vox.get_palette_color(2), [40, 50, 60, 255]);
+    }
+
+    #[test]
+    fn test_add_voxel_rgb_keeps_default_colors() {
+        // the voxels added on the default palette keep their colors
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(0, 0, 0, 5);
+        let default_color = vox.get_palette_color(5);
+        vox.add_voxel_rgb(1, 0, 0, 10, 20, 30);
+        vox.add_voxels_rgb(&[([2, 0, 0], [40, 50, 60])]);
+        assert_eq!(vox.get_palette_color(5), default_color);
+        let c = vox.get_voxel(1, 0, 0).unwrap();
+        assert_ne!(c, 5);
+        assert_eq!(vox.get_palette_color(c as u8), [10, 20, 30, 255]);
+        let c = vox.get_voxel(2, 0, 0).unwrap();
+        assert_eq!(vox.get_palette_color(c as u8), [40, 50, 60, 255]);
+        // a default color in use is found again
+        let d = default_color;
+        assert_eq!(vox.get_color_index(d[0], d[1], d[2]), 5);
+    }
+
+    #[test]
+    fn test_map_colors_rgb_quantize() {
+        let mut vox = VoxWriter::create_empty();
+        let colors: Vec<[u8; 3]> = (0..1000)
+            .map(|i| [(i % 256) as u8, (i / 4) as u8, 7])
+            .collect();
+        let indices = vox.map_colors_rgb(&colors);
+        assert!(indices.iter().all(|&i| (1..=255).contains(&i)));
+        assert!(vox.colors.len() <= 255);
+    }
+
+    #[test]
+    fn test_optimize_palette() {
+        let mut vox = VoxWriter::create_empty();
+        vox.add_color(200, 10, 10, 255, 9);
+        vox.add_color(201, 11, 10, 255, 19);
+        vox.add_color(10, 10, 200, 255, 29);
+        vox.add_color(90, 90, 90, 255, 39);
+        vox.add_voxel(0, 0, 0, 10);
+        vox.add_voxel(1, 0, 0, 10);
+        vox.add_voxel(2, 0, 0, 20);
+        let mut node = VoxWriter::create_empty();
+        node.add_voxel(0, 0, 0, 30);
+        vox.add_node("node".to_string(), node, [0; 3], 0);
+
+        // the color 40 is unused and the color 20 is merged in the color 10
+        assert_eq!(vox.optimize_palette(2.3), 2);
+        assert_eq!(vox.colors.len(), 2);
+        assert_eq!(vox.get_voxel(2, 0, 0), Some(1));
+        assert_eq!(vox.get_palette_color(1), [200, 10, 10, 255]);
+        assert_eq!(vox.get_palette_color(2), [10, 10, 200, 255]);
+        assert_eq!(vox.get_nodes()[0].volume.get_voxel(0, 0, 0), Some(2));
+
+        // the default palette is compacted too
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(0, 0, 0, 255);
+        assert_eq!(vox.optimize_palette(0.0), 254);
+        assert_eq!(vox.get_voxel(0, 0, 0), Some(1));
+        assert_eq!(vox.get_palette_color(1), [0x11, 0x11, 0x11, 255]);
+    }
+}
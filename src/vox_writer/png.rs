@@ -0,0 +1,513 @@
+use super::invalid_data;
+use super::zlib::{crc32, zlib_compress, zlib_decompress};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// a decoded png. the palette images are expanded to rgba 8 bits
+pub(crate) struct PngImage {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize, // 1 gray, 2 gray alpha, 3 rgb, 4 rgba
+    pub bit_depth: u8,   // 8 or 16 after expansion of the low bit depths
+    pub samples: Vec<u16>,
+}
+
+impl PngImage {
+    /// get the rgba 8 bits color of a pixel
+    pub fn get_rgba(&self, x: usize, y: usize) -> [u8; 4] {
+        let p = (y * self.width + x) * self.channels;
+        let s = |i: usize| -> u8 {
+            if self.bit_depth == 16 {
+                (self.samples[p + i] >> 8) as u8
+            } else {
+                self.samples[p + i] as u8
+            }
+        };
+        match self.channels {
+            1 => [s(0), s(0), s(0), 255],
+            2 => [s(0), s(0), s(0), s(1)],
+            3 => [s(0), s(1), s(2), 255],
+            _ => [s(0), s(1), s(2), s(3)],
+        }
+    }
+
+    /// get the luminance of a pixel in [0:1], at the full precision of the image
+    pub fn get_gray(&self, x: usize, y: usize) -> f64 {
+        let p = (y * self.width + x) * self.channels;
+        let max = if self.bit_depth == 16 { 65535.0 } else { 255.0 };
+        let v = |i: usize| self.samples[p + i] as f64 / max;
+        if self.channels >= 3 {
+            0.299 * v(0) + 0.587 * v(1) + 0.114 * v(2)
+        } else {
+            v(0)
+        }
+    }
+}
+
+fn paeth(a: i32, b: i32, c: i32) -> u8 {
+    let p = a + b - c;
+    let pa = (p - a).abs();
+    let pb = (p - b).abs();
+    let pc = (p - c).abs();
+    if pa <= pb && pa <= pc {
+        a as u8
+    } else if pb <= pc {
+        b as u8
+    } else {
+        c as u8
+    }
+}
+
+/// undo the filters of the scanlines of a (sub) image
+fn unfilter(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bits_per_pixel: usize,
+) -> std::io::Result<Vec<u8>> {
+    let bpp = usize::max(1, bits_per_pixel / 8);
+    let stride = (width * bits_per_pixel).div_ceil(8);
+    if data.len() < height * (stride + 1) {
+        return Err(invalid_data("png: not enough image data"));
+    }
+    let mut out = vec![0u8; height * stride];
+    for y in 0..height {
+        let filter = data[y * (stride + 1)];
+        let line = &data[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..stride {
+            let a = if x >= bpp {
+                out[y * stride + x - bpp] as i32
+            } else {
+                0
+            };
+            let b = if y > 0 {
+                out[(y - 1) * stride + x] as i32
+            } else {
+                0
+            };
+            let c = if x >= bpp && y > 0 {
+                out[(y - 1) * stride + x - bpp] as i32
+            } else {
+                0
+            };
+            let v = line[x];
+            out[y * stride + x] = match filter {
+                0 => v,
+                1 => v.wrapping_add(a as u8),
+                2 => v.wrapping_add(b as u8),
+                3 => v.wrapping_add(((a + b) / 2) as u8),
+                4 => v.wrapping_add(paeth(a, b, c)),
+                _ => return Err(invalid_data("png: bad filter")),
+            };
+        }
+    }
+    Ok(out)
+}
+
+/// read the sample n of an unfiltered scanline
+fn get_sample(line: &[u8], n: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([line[n * 2], line[n * 2 + 1]]),
+        8 => line[n] as u16,
+        _ => {
+            let bits = bit_depth as usize;
+            let byte = line[n * bits / 8];
+            let shift = 8 - bits - (n * bits) % 8;
+            ((byte >> shift) as u16) & ((1 << bits) - 1)
+        }
+    }
+}
+
+/// decode a png file in memory
+pub(crate) fn decode_png(data: &[u8]) -> std::io::Result<PngImage> {
+    if data.len() < 8 || data[0..8] != PNG_SIGNATURE {
+        return Err(invalid_data("png: bad signature"));
+    }
+    let mut pos = 8;
+    let mut width = 0;
+    let mut height = 0;
+    let mut bit_depth = 0u8;
+    let mut color_type = 0u8;
+    let mut interlace = 0u8;
+    let mut palette: Vec<[u8; 4]> = vec![];
+    let mut idat: Vec<u8> = vec![];
+    while pos + 8 <= data.len() {
+        let len =
+            u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let start = pos + 8;
+        if start + len + 4 > data.len() {
+            return Err(invalid_data("png: truncated chunk"));
+        }
+        let chunk = &data[start..start + len];
+        let crc = &data[start + len..start + len + 4];
+        if u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]])
+            != crc32(&data[pos + 4..start + len])
+        {
+            return Err(invalid_data("png: bad chunk crc"));
+        }
+        match kind {
+            b"IHDR" => {
+                if len < 13 {
+                    return Err(invalid_data("png: bad IHDR"));
+                }
+                width = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+                height = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+                bit_depth = chunk[8];
+                color_type = chunk[9];
+                interlace = chunk[12];
+            }
+            b"PLTE" => {
+                palette = chunk
+                    .chunks_exact(3)
+                    .map(|c| [c[0], c[1], c[2], 255])
+                    .collect();
+            }
+            b"tRNS" if color_type == 3 => {
+                for (i, a) in chunk.iter().enumerate() {
+                    if let Some(p) = palette.get_mut(i) {
+                        p[3] = *a;
+                    }
+                }
+            }
+            b"IDAT" => idat.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos = start + len + 4;
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid_data("png: bad color type")),
+    };
+    if width == 0 || height == 0 || ![1, 2, 4, 8, 16].contains(&bit_depth) {
+        return Err(invalid_data("png: bad header"));
+    }
+    let bits_per_pixel = channels * bit_depth as usize;
+
+    // the 7 passes of adam7, or a single pass for the non interlaced images
+    const ADAM7: [(usize, usize, usize, usize); 7] = [
+        (0, 0, 8, 8),
+        (4, 0, 8, 8),
+        (0, 4, 4, 8),
+        (2, 0, 4, 4),
+        (0, 2, 2, 4),
+        (1, 0, 2, 2),
+        (0, 1, 1, 2),
+    ];
+    let passes: Vec<(usize, usize, usize, usize)> = if interlace == 1 {
+        ADAM7.to_vec()
+    } else {
+        vec![(0, 0, 1, 1)]
+    };
+    // the size of each pass, the data is decompressed up to their sum
+    let mut sizes = vec![];
+    let mut total = 0usize;
+    for (x0, y0, dx, dy) in &passes {
+        let pw = (width + dx - 1 - x0) / dx;
+        let ph = (height + dy - 1 - y0) / dy;
+        // the empty passes of the small images have no rows
+        let size = if pw == 0 || ph == 0 {
+            Some(0)
+        } else {
+            pw.checked_mul(bits_per_pixel)
+                .map(|bits| bits.div_ceil(8) + 1)
+                .and_then(|row| row.checked_mul(ph))
+        };
+        total = size
+            .and_then(|size| total.checked_add(size))
+            .ok_or_else(|| invalid_data("png: bad image size"))?;
+        sizes.push((pw, ph));
+    }
+    let raw = zlib_decompress(&idat, total)?;
+    if raw.len() < total {
+        return Err(invalid_data("png: not enough image data"));
+    }
+    let mut samples = vec![0u16; width * height * channels];
+    let mut offset = 0;
+    for ((x0, y0, dx, dy), (pw, ph)) in passes.into_iter().zip(sizes) {
+        if pw == 0 || ph == 0 {
+            continue;
+        }
+        let stride = (pw * bits_per_pixel).div_ceil(8);
+        let end = offset + ph * (stride + 1);
+        let pass = unfilter(&raw[offset..end], pw, ph, bits_per_pixel)?;
+        offset = end;
+        for py in 0..ph {
+            let line = &pass[py * stride..(py + 1) * stride];
+            for px in 0..pw {
+                let p = ((y0 + py * dy) * width + x0 + px * dx) * channels;
+                for c in 0..channels {
+                    samples[p + c] = get_sample(line, px * channels + c, bit_depth);
+                }
+            }
+        }
+    }
+
+    if color_type == 3 {
+        // expand the palette indices to rgba
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for s in &samples {
+            let c = palette.get(*s as usize).copied().unwrap_or([0, 0, 0, 255]);
+            rgba.extend(c.iter().map(|v| *v as u16));
+        }
+        return Ok(PngImage {
+            width,
+            height,
+            channels: 4,
+            bit_depth: 8,
+            samples: rgba,
+        });
+    }
+    if bit_depth < 8 {
+        // scale the low bit depths gray to 8 bits
+        let max = (1u16 << bit_depth) - 1;
+        for s in samples.iter_mut() {
+            *s = *s * 255 / max;
+        }
+    }
+    Ok(PngImage {
+        width,
+        height,
+        channels,
+        bit_depth: u8::max(bit_depth, 8),
+        samples,
+    })
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// encode a rgba 8 bits image, rows from the top
+pub(crate) fn encode_png_rgba(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    let mut out = PNG_SIGNATURE.to_vec();
+    let mut ihdr = vec![];
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]); // 8 bits rgba, not interlaced
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    let stride = width * 4;
+    let mut raw = Vec::with_capacity(height * (stride + 1));
+    for y in 0..height {
+        // sub filter, the runs of a same color become runs of zeros
+        raw.push(1);
+        let line = &rgba[y * stride..(y + 1) * stride];
+        for x in 0..stride {
+            let a = if x >= 4 { line[x - 4] } else { 0 };
+            raw.push(line[x].wrapping_sub(a));
+        }
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_compress(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::zlib::tests::mutations;
+    use super::*;
+    use png::{BitDepth, ColorType, Encoder, FilterType};
+
+    /// encode the samples with the png crate, the samples are packed in the rows at depth bits
+    fn encode_with_png(
+        width: usize,
+        height: usize,
+        color: ColorType,
+        depth: BitDepth,
+        filter: FilterType,
+        samples: &[u16],
+    ) -> Vec<u8> {
+        let channels = samples.len() / (width * height);
+        let bits = depth as usize;
+        let stride = (width * channels * bits).div_ceil(8);
+        let mut rows = vec![0u8; stride * height];
+        for y in 0..height {
+            for n in 0..width * channels {
+                let v = samples[y * width * channels + n];
+                let row = &mut rows[y * stride..(y + 1) * stride];
+                match bits {
+                    16 => row[n * 2..n * 2 + 2].copy_from_slice(&v.to_be_bytes()),
+                    8 => row[n] = v as u8,
+                    _ => row[n * bits / 8] |= (v as u8) << (8 - bits - (n * bits) % 8),
+                }
+            }
+        }
+        let mut out = vec![];
+        let mut encoder = Encoder::new(&mut out, width as u32, height as u32);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        encoder.set_filter(filter);
+        if color == ColorType::Indexed {
+            encoder.set_palette((0..16 * 3).map(|i| (i * 5) as u8).collect::<Vec<u8>>());
+            encoder.set_trns(vec![0u8, 128]);
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&rows).unwrap();
+        writer.finish().unwrap();
+        out
+    }
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_png_gray16() {
+        let data = from_hex(
+            "89504e470d0a1a0a0000000d4948445200000002000000021000000000074d8ebb\
+             0000001249444154789c636060f8ff9fa181418001000f1d028f5b31878a\
+             0000000049454e44ae426082",
+        );
+        let img = decode_png(&data).unwrap();
+        assert_eq!(
+            (img.width, img.height, img.channels, img.bit_depth),
+            (2, 2, 1, 16)
+        );
+        assert_eq!(img.samples, vec![0, 65535, 32768, 4096]);
+        assert_eq!(img.get_gray(1, 0), 1.0);
+        assert_eq!(img.get_rgba(0, 1), [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn test_encode_png_round_trip() {
+        let rgba: Vec<u8> = (0..3 * 2 * 4).map(|i| (i * 10) as u8).collect();
+        let img = decode_png(&encode_png_rgba(3, 2, &rgba)).unwrap();
+        assert_eq!((img.width, img.height, img.channels), (3, 2, 4));
+        assert_eq!(img.get_rgba(2, 1), [200, 210, 220, 230]);
+
+        // a damaged chunk, and a 1x1 header over the data of 3x2 pixels
+        let mut data = encode_png_rgba(3, 2, &rgba);
+        data[40] ^= 1;
+        assert!(decode_png(&data).is_err());
+        let mut data = encode_png_rgba(3, 2, &rgba);
+        data[16..24].copy_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1]);
+        let crc = crc32(&data[12..29]).to_be_bytes();
+        data[29..33].copy_from_slice(&crc);
+        assert!(decode_png(&data).is_err());
+    }
+
+    #[test]
+    fn test_decode_png_crate() {
+        let (width, height) = (13, 7);
+        let configs = [
+            (ColorType::Grayscale, BitDepth::One),
+            (ColorType::Grayscale, BitDepth::Two),
+            (ColorType::Grayscale, BitDepth::Four),
+            (ColorType::Grayscale, BitDepth::Eight),
+            (ColorType::Grayscale, BitDepth::Sixteen),
+            (ColorType::GrayscaleAlpha, BitDepth::Eight),
+            (ColorType::Rgb, BitDepth::Eight),
+            (ColorType::Rgb, BitDepth::Sixteen),
+            (ColorType::Rgba, BitDepth::Eight),
+            (ColorType::Rgba, BitDepth::Sixteen),
+            (ColorType::Indexed, BitDepth::Four),
+        ];
+        let filters = [
+            FilterType::NoFilter,
+            FilterType::Sub,
+            FilterType::Up,
+            FilterType::Avg,
+            FilterType::Paeth,
+        ];
+        for (color, depth) in configs {
+            let channels = color.samples();
+            let max = (1u32 << depth as u32) - 1;
+            let samples: Vec<u16> = (0..(width * height * channels) as u32)
+                .map(|i| (i.wrapping_mul(2654435761) >> 7) % (max + 1))
+                .map(|v| v as u16)
+                .collect();
+            for filter in filters {
+                let data = encode_with_png(width, height, color, depth, filter, &samples);
+                let img = decode_png(&data).unwrap();
+                assert_eq!((img.width, img.height), (width, height));
+                if color == ColorType::Indexed {
+                    // the palette colors, with the alpha of the tRNS chunk
+                    for (i, s) in samples.iter().enumerate() {
+                        let v = *s * 15;
+                        let a = [0, 128].get(*s as usize).copied().unwrap_or(255);
+                        assert_eq!(img.samples[i * 4..i * 4 + 4], [v, v + 5, v + 10, a]);
+                    }
+                } else if (depth as u32) < 8 {
+                    let scaled: Vec<u16> = samples.iter().map(|v| v * 255 / max as u16).collect();
+                    assert_eq!(img.samples, scaled);
+                } else {
+                    assert_eq!((img.channels, img.bit_depth), (channels, depth as u8));
+                    assert_eq!(img.samples, samples);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_png_interlaced() {
+        // a 3x3 gray image in adam7, the passes 2 and 3 are empty
+        let mut data = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut data, b"IHDR", &[0, 0, 0, 3, 0, 0, 0, 3, 8, 0, 0, 0, 1]);
+        let raw = [0, 0, 0, 2, 0, 6, 8, 0, 1, 0, 7, 0, 3, 4, 5];
+        write_chunk(&mut data, b"IDAT", &zlib_compress(&raw));
+        write_chunk(&mut data, b"IEND", &[]);
+        let img = decode_png(&data).unwrap();
+        assert_eq!(img.samples, (0..9).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn test_encode_png_crate() {
+        let (width, height) = (5, 3);
+        let rgba: Vec<u8> = (0..width * height * 4).map(|i| (i * 37) as u8).collect();
+        let data = encode_png_rgba(width, height, &rgba);
+        let mut reader = png::Decoder::new(&data[..]).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        assert_eq!((info.width, info.height), (width as u32, height as u32));
+        assert_eq!(
+            (info.color_type, info.bit_depth),
+            (ColorType::Rgba, BitDepth::Eight)
+        );
+        assert_eq!(buf[..info.buffer_size()], rgba[..]);
+    }
+
+    #[test]
+    fn test_decode_png_mutations() {
+        // the damaged files give an error or an image, never a panic
+        let samples: Vec<u16> = (0..9 * 4 * 3).map(|i| (i * 7 % 256) as u16).collect();
+        let data = encode_with_png(
+            9,
+            4,
+            ColorType::Rgb,
+            BitDepth::Eight,
+            FilterType::Paeth,
+            &samples,
+        );
+        for m in mutations(&data, 3000) {
+            let _ = decode_png(&m);
+        }
+        let samples: Vec<u16> = (0..9 * 4).map(|i| i % 16).collect();
+        let data = encode_with_png(
+            9,
+            4,
+            ColorType::Indexed,
+            BitDepth::Four,
+            FilterType::Sub,
+            &samples,
+        );
+        for m in mutations(&data, 3000) {
+            let _ = decode_png(&m);
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(Self::I8),
+            "uchar" | "uint8" => Some(Self::U8),
+            "short" | "int16" => Some(Self::I16),
+            "ushort" | "uint16" => Some(Self::U16),
+            "int" | "int32" => Some(Self::I32),
+            "uint" | "uint32" => Some(Self::U32),
+            "float" | "float32" => Some(Self::F32),
+            "double" | "float64" => Some(Self::F64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, Self::F32 | Self::F64)
+    }
+
+    fn read_binary(&self, bytes: &[u8], big_endian: bool) -> f64 {
+        macro_rules! num {
+            ($t:ty) => {{
+                let mut b = [0u8; std::mem::size_of::<$t>()];
+                b.copy_from_slice(bytes);
+                if big_endian {
+                    <$t>::from_be_bytes(b) as f64
+                } else {
+                    <$t>::from_le_bytes(b) as f64
+                }
+            }};
+        }
+        match self {
+            Self::I8 => num!(i8),
+            Self::U8 => num!(u8),
+            Self::I16 => num!(i16),
+            Self::U16 => num!(u16),
+            Self::I32 => num!(i32),
+            Self::U32 => num!(u32),
+            Self::F32 => num!(f32),
+            Self::F64 => num!(f64),
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    value_type: PlyType,
+    list_count_type: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+#[derive(PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// the most points allocated ahead from the vertex count of a ply header
+const MAX_PREALLOCATED_POINTS: usize = 1 << 20;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "the columns of the lines of a xyz file"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum XyzColumns {
+    Xyz,             // x y z
+    XyzRgb,          // x y z r g b
+    XyzIntensityRgb, // x y z intensity r g b
+}
+
+#[doc = "the reading options of a xyz file"]
+#[derive(Clone, Debug)]
+pub struct XyzOptions {
+    pub columns: XyzColumns,
+    pub unit_colors: bool, // the colors are floats in [0:1], else bytes in [0:255]
+}
+
+impl XyzOptions {
+    #[doc = "x y z r g b lines with the colors as bytes"]
+    pub fn create() -> Self {
+        Self {
+            columns: XyzColumns::XyzRgb,
+            unit_colors: false,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a list of points with optional rgb colors, as read from ply or xyz files"]
+#[derive(Default)]
+pub struct PointCloud {
+    pub points: Vec<[f64; 3]>,
+    pub colors: Vec<[u8; 3]>, // empty or one color per point
+}
+
+impl PointCloud {
+    #[doc = "create an empty point cloud"]
+    pub fn create_empty() -> Self {
+        Self::default()
+    }
+
+    #[doc = "load a .ply (ascii or binary) or a .xyz file, the format is chosen with the file extension. \
+             xyz_options gives the columns of a .xyz file"]
+    pub fn load_from_file(
+        file_path_name: String,
+        xyz_options: &XyzOptions,
+    ) -> std::io::Result<Self> {
+        let file = File::open(&file_path_name)?;
+        let reader = BufReader::new(file);
+        if file_path_name.to_lowercase().ends_with(".ply") {
+            Self::read_ply(reader)
+        } else {
+            Self::read_xyz(reader, xyz_options)
+        }
+    }
+
+    #[doc = "read a whitespace (or comma) separated file of lines with the columns of options. \
+             the columns after them are ignored"]
+    pub fn read_xyz<R: BufRead>(reader: R, options: &XyzOptions) -> std::io::Result<Self> {
+        let (count_columns, first_color) = match options.columns {
+            XyzColumns::Xyz => (3, None),
+            XyzColumns::XyzRgb => (6, Some(3)),
+            XyzColumns::XyzIntensityRgb => (7, Some(4)),
+        };
+        let scale = if options.unit_colors { 255.0 } else { 1.0 };
+        let mut cloud = Self::create_empty();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+            let values: Vec<f64> = line
+                .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid_data("xyz: bad number"))?;
+            if values.len() < 3 {
+                // some exporters write the point count on the first line
+                continue;
+            }
+            if values.len() < count_columns {
+                return Err(invalid_data("xyz: missing columns"));
+            }
+            cloud.points.push([values[0], values[1], values[2]]);
+            if let Some(c) = first_color {
+                let rgb = [values[c], values[c + 1], values[c + 2]];
+                cloud
+                    .colors
+                    .push(rgb.map(|v| (v * scale).round().clamp(0.0, 255.0) as u8));
+            }
+        }
+        Ok(cloud)
+    }
+
+    #[doc = "read an ascii, binary_little_endian or binary_big_endian ply file. only the vertex element is used"]
+    pub fn read_ply<R: BufRead>(mut reader: R) -> std::io::Result<Self> {
+        // header
+        let mut format: Option<PlyFormat> = None;
+        let mut elements: Vec<PlyElement> = vec![];
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim() != "ply" {
+            return Err(invalid_data("ply: bad magic"));
+        }
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("ply: no end_header"));
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["format", f, ..] => {
+                    format = match *f {
+                        "ascii" => Some(PlyFormat::Ascii),
+                        "binary_little_endian" => Some(PlyFormat::BinaryLittleEndian),
+                        "binary_big_endian" => Some(PlyFormat::BinaryBigEndian),
+                        _ => return Err(invalid_data("ply: unknown format")),
+                    }
+                }
+                ["element", name, count] => elements.push(PlyElement {
+                    name: name.to_string(),
+                    count: count
+                        .parse()
+                        .map_err(|_| invalid_data("ply: bad element count"))?,
+                    properties: vec![],
+                }),
+                ["property", "list", count_type, value_type, name] => {
+                    let element = elements
+                        .last_mut()
+                        .ok_or_else(|| invalid_data("ply: property without element"))?;
+                    element.properties.push(PlyProperty {
+                        name: name.to_string(),
+                        value_type: PlyType::parse(value_type)
+                            .ok_or_else(|| invalid_data("ply: bad property type"))?,
+                        list_count_type: Some(
+                            PlyType::parse(count_type)
+                                .ok_or_else(|| invalid_data("ply: bad property type"))?,
+                        ),
+                    });
+                }
+                ["property", value_type, name] => {
+                    let element = elements
+                        .last_mut()
+                        .ok_or_else(|| invalid_data("ply: property without element"))?;
+                    element.properties.push(PlyProperty {
+                        name: name.to_string(),
+                        value_type: PlyType::parse(value_type)
+                            .ok_or_else(|| invalid_data("ply: bad property type"))?,
+                        list_count_type: None,
+                    });
+                }
+                ["end_header"] => break,
+                _ => {} // comment, obj_info
+            }
+        }
+        let format = format.ok_or_else(|| invalid_data("ply: no format"))?;
+
+        let mut cloud = Self::create_empty();
+        for element in &elements {
+            let is_vertex = element.name == "vertex";
+            let find = |names: &[&str]| -> Option<usize> {
+                element
+                    .properties
+                    .iter()
+                    .position(|p| p.list_count_type.is_none() && names.contains(&p.name.as_str()))
+            };
+            let xyz = [find(&["x"]), find(&["y"]), find(&["z"])];
+            let rgb = [
+                find(&["red", "r", "diffuse_red"]),
+                find(&["green", "g", "diffuse_green"]),
+                find(&["blue", "b", "diffuse_blue"]),
+            ];
+            if is_vertex && xyz.iter().any(|p| p.is_none()) {
+                return Err(invalid_data("ply: vertex without x, y or z"));
+            }
+            let has_colors = is_vertex && rgb.iter().all(|p| p.is_some());
+            if is_vertex {
+                // the count of the header is not trusted for the allocation
+                let capacity = element.count.min(MAX_PREALLOCATED_POINTS);
+                cloud.points.reserve(capacity);
+                if has_colors {
+                    cloud.colors.reserve(capacity);
+                }
+            }
+
+            let mut values = vec![0.0f64; element.properties.len()];
+            for _ in 0..element.count {
+                if format == PlyFormat::Ascii {
+                    line.clear();
+                    if reader.read_line(&mut line)? == 0 {
+                        return Err(invalid_data("ply: unexpected end of file"));
+                    }
+                    if !is_vertex {
+                        continue; // one line per element, nothing to parse
+                    }
+                    let mut words = line.split_whitespace();
+                    for (i, p) in element.properties.iter().enumerate() {
+                        let mut next = || -> std::io::Result<f64> {
+                            words
+                                .next()
+                                .and_then(|w| w.parse::<f64>().ok())
+                                .ok_or_else(|| invalid_data("ply: bad value"))
+                        };
+                        if p.list_count_type.is_some() {
+                            let n = next()? as usize;
+                            for _ in 0..n {
+                                next()?;
+                            }
+                        } else {
+                            values[i] = next()?;
+                        }
+                    }
+                } else {
+                    let big_endian = format == PlyFormat::BinaryBigEndian;
+                    let mut buf = [0u8; 8];
+                    for (i, p) in element.properties.iter().enumerate() {
+                        if let Some(count_type) = p.list_count_type {
+                            let b = &mut buf[..count_type.size()];
+                            reader.read_exact(b)?;
+                            let n = count_type.read_binary(b, big_endian) as u64;
+                            let len = n * p.value_type.size() as u64;
+                            let skipped =
+                                std::io::copy(&mut (&mut reader).take(len), &mut std::io::sink())?;
+                            if skipped != len {
+                                return Err(invalid_data("ply: unexpected end of file"));
+                            }
+                        } else {
+                            let b = &mut buf[..p.value_type.size()];
+                            reader.read_exact(b)?;
+                            values[i] = p.value_type.read_binary(b, big_endian);
+                        }
+                    }
+                    if !is_vertex {
+                        continue;
+                    }
+                }
+                cloud.points.push([
+                    values[xyz[0].unwrap()],
+                    values[xyz[1].unwrap()],
+                    values[xyz[2].unwrap()],
+                ]);
+                if has_colors {
+                    cloud.colors.push(rgb.map(|p| {
+                        let p = p.unwrap();
+                        let v = if element.properties[p].value_type.is_float() {
+                            values[p] * 255.0
+                        } else {
+                            values[p]
+                        };
+                        v.round().clamp(0.0, 255.0) as u8
+                    }));
+                }
+            }
+            if is_vertex {
+                // the other elements (faces, edges) are not needed
+                break;
+            }
+        }
+        Ok(cloud)
+    }
+
+    #[doc = "get the min and max of the points"]
+    pub fn get_bounds(&self) -> Option<([f64; 3], [f64; 3])> {
+        let first = *self.points.first()?;
+        let mut min = first;
+        let mut max = first;
+        for p in &self.points {
+            for i in 0..3 {
+                min[i] = f64::min(min[i], p[i]);
+                max[i] = f64::max(max[i], p[i]);
+            }
+        }
+        Some((min, max))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "bin the points in voxels of voxel_size, starting at the min of the cloud. \
+             the colors of the points of a voxel are averaged, default_color is used for clouds without colors. \
+             return the count of voxels added"]
+    pub fn add_point_cloud(
+        &mut self,
+        cloud: &PointCloud,
+        voxel_size: f64,
+        default_color: [u8; 3],
+    ) -> usize {
+        let (min, _) = match cloud.get_bounds() {
+            Some(b) => b,
+            None => return 0,
+        };
+        let voxel_size = if voxel_size > 0.0 { voxel_size } else { 1.0 };
+        let mut bins: HashMap<[i32; 3], [u64; 4]> = HashMap::new();
+        for (i, p) in cloud.points.iter().enumerate() {
+            let key = [
+                f64::floor((p[0] - min[0]) / voxel_size) as i32,
+                f64::floor((p[1] - min[1]) / voxel_size) as i32,
+                f64::floor((p[2] - min[2]) / voxel_size) as i32,
+            ];
+            let c = cloud.colors.get(i).copied().unwrap_or(default_color);
+            let bin = bins.entry(key).or_default();
+            bin[0] += c[0] as u64;
+            bin[1] += c[1] as u64;
+            bin[2] += c[2] as u64;
+            bin[3] += 1;
+        }
+        let mut voxels: Vec<([i32; 3], [u8; 3])> = bins
+            .iter()
+            .map(|(k, s)| {
+                let n = s[3];
+                let avg = [
+                    ((s[0] + n / 2) / n) as u8,
+                    ((s[1] + n / 2) / n) as u8,
+                    ((s[2] + n / 2) / n) as u8,
+                ];
+                (*k, avg)
+            })
+            .collect();
+        voxels.sort();
+        self.add_voxels_rgb(&voxels);
+        voxels.len()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_ply_ascii() {
+        let data = "ply\nformat ascii 1.0\ncomment test\nelement vertex 2\n\
+                    property float x\nproperty float y\nproperty float z\n\
+                    property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                    element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                    0 0 0 255 0 0\n1.5 2 3 0 255 0\n3 0 1 2\n";
+        let cloud = PointCloud::read_ply(Cursor::new(data)).unwrap();
+        assert_eq!(cloud.points, vec![[0.0, 0.0, 0.0], [1.5, 2.0, 3.0]]);
+        assert_eq!(cloud.colors, vec![[255, 0, 0], [0, 255, 0]]);
+    }
+
+    #[test]
+    fn test_read_ply_binary() {
+        let mut data = b"ply\nformat binary_little_endian 1.0\nelement vertex 1\n\
+                         property double x\nproperty double y\nproperty double z\n\
+                         property float red\nproperty float green\nproperty float blue\nend_header\n"
+            .to_vec();
+        for v in [1.0f64, 2.0, 3.0] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [1.0f32, 0.5, 0.0] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        let cloud = PointCloud::read_ply(Cursor::new(data)).unwrap();
+        assert_eq!(cloud.points, vec![[1.0, 2.0, 3.0]]);
+        assert_eq!(cloud.colors, vec![[255, 128, 0]]);
+    }
+
+    #[test]
+    fn test_add_point_cloud_averages_colors() {
+        let data = "0.1 0.1 0.1 100 0 0\n0.2 0.2 0.2 200 0 0\n5 5 5 0 0 50\n";
+        let cloud = PointCloud::read_xyz(Cursor::new(data), &XyzOptions::create()).unwrap();
+        let mut vox = VoxWriter::create_empty();
+        assert_eq!(vox.add_point_cloud(&cloud, 1.0, [255, 255, 255]), 2);
+        assert_eq!(vox.get_palette_color(1), [0, 0, 50, 255]);
+        assert_eq!(vox.get_palette_color(2), [150, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_read_xyz_options() {
+        // the colors in [0:1] are given by the options, not guessed
+        let data = "3\n0 0 0 1 0 0.5 9 9\n1 2 3 0 1 0\n";
+        let options = XyzOptions {
+            columns: XyzColumns::XyzRgb,
+            unit_colors: true,
+        };
+        let cloud = PointCloud::read_xyz(Cursor::new(data), &options).unwrap();
+        assert_eq!(cloud.points, vec![[0.0, 0.0, 0.0], [1.0, 2.0, 3.0]]);
+        assert_eq!(cloud.colors, vec![[255, 0, 128], [0, 255, 0]]);
+        let cloud = PointCloud::read_xyz(Cursor::new(data), &XyzOptions::create()).unwrap();
+        assert_eq!(cloud.colors, vec![[1, 0, 1], [0, 1, 0]]);
+
+        // x y z intensity r g b, and the columns after x y z ignored
+        let data = "0 0 0 0.7 10 20 30\n";
+        let options = XyzOptions {
+            columns: XyzColumns::XyzIntensityRgb,
+            unit_colors: false,
+        };
+        let cloud = PointCloud::read_xyz(Cursor::new(data), &options).unwrap();
+        assert_eq!(cloud.colors, vec![[10, 20, 30]]);
+        let options = XyzOptions {
+            columns: XyzColumns::Xyz,
+            unit_colors: false,
+        };
+        let cloud = PointCloud::read_xyz(Cursor::new(data), &options).unwrap();
+        assert_eq!(cloud.points.len(), 1);
+        assert!(cloud.colors.is_empty());
+
+        // a line without the colors
+        let data = "0 0 0 1 2 3\n1 1 1\n";
+        assert!(PointCloud::read_xyz(Cursor::new(data), &XyzOptions::create()).is_err());
+    }
+
+    #[test]
+    fn test_read_ply_huge_counts() {
+        // a vertex count far over the data, and a list count over the data
+        let data = "ply\nformat ascii 1.0\nelement vertex 4000000000\n\
+                    property float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n";
+        assert!(PointCloud::read_ply(Cursor::new(data)).is_err());
+        let mut data = b"ply\nformat binary_little_endian 1.0\nelement vertex 1\n\
+                         property float x\nproperty float y\nproperty float z\n\
+                         property list uint double normals\nend_header\n"
+            .to_vec();
+        for v in [0.0f32; 3] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(PointCloud::read_ply(Cursor::new(data)).is_err());
+    }
+}
@@ -0,0 +1,452 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+const QB_VERSION: [u8; 4] = [1, 1, 0, 0];
+const CODEFLAG: u32 = 2;
+const NEXTSLICEFLAG: u32 = 6;
+
+#[doc = "the byte order of the colors of a qb file"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum QubicleColorFormat {
+    Rgba,
+    Bgra,
+}
+
+#[doc = "the orientation of the z axis of a qb file"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum QubicleZAxis {
+    LeftHanded,
+    RightHanded,
+}
+
+#[doc = "a named matrix of rgba voxels, y is up. the alpha 0 is an empty voxel"]
+#[derive(Clone, Debug)]
+pub struct QubicleMatrix {
+    pub name: String,
+    pub size: [u32; 3],
+    pub position: [i32; 3],
+    pub voxels: Vec<[u8; 4]>, // x + y * size x + z * size x * size y, left handed
+}
+
+impl QubicleMatrix {
+    #[doc = "create an empty matrix"]
+    pub fn create(name: String, size: [u32; 3], position: [i32; 3]) -> Self {
+        let count = size[0] as usize * size[1] as usize * size[2] as usize;
+        Self {
+            name,
+            size,
+            position,
+            voxels: vec![[0; 4]; count],
+        }
+    }
+
+    fn index(&self, x: u32, y: u32, z: u32) -> usize {
+        (z as usize * self.size[1] as usize + y as usize) * self.size[0] as usize + x as usize
+    }
+
+    #[doc = "get the rgba color of the voxel x,y,z"]
+    pub fn get_voxel(&self, x: u32, y: u32, z: u32) -> [u8; 4] {
+        self.voxels[self.index(x, y, z)]
+    }
+
+    #[doc = "set the rgba color of the voxel x,y,z"]
+    pub fn set_voxel(&mut self, x: u32, y: u32, z: u32, rgba: [u8; 4]) {
+        let i = self.index(x, y, z);
+        self.voxels[i] = rgba;
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut b = [0u8; 4];
+    reader.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+#[doc = "a qubicle binary (.qb) model"]
+#[derive(Clone, Debug)]
+pub struct QubicleModel {
+    pub color_format: QubicleColorFormat,
+    pub z_axis: QubicleZAxis,
+    pub compressed: bool,
+    pub visibility_mask: bool, // the alpha is a mask of the visible faces, not an opacity
+    pub matrices: Vec<QubicleMatrix>,
+}
+
+impl QubicleModel {
+    #[doc = "create an empty model, saved as rgba, left handed and compressed"]
+    pub fn create_empty() -> Self {
+        Self {
+            color_format: QubicleColorFormat::Rgba,
+            z_axis: QubicleZAxis::LeftHanded,
+            compressed: true,
+            visibility_mask: false,
+            matrices: vec![],
+        }
+    }
+
+    #[doc = "load a .qb file"]
+    pub fn load_from_file(file_path_name: String) -> std::io::Result<Self> {
+        let file = File::open(file_path_name)?;
+        Self::read(BufReader::new(file))
+    }
+
+    #[doc = "read a qb model"]
+    pub fn read<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        if version[0] != 1 {
+            return Err(invalid_data("qb: unsupported version"));
+        }
+        let color_format = match read_u32(&mut reader)? {
+            0 => QubicleColorFormat::Rgba,
+            1 => QubicleColorFormat::Bgra,
+            _ => return Err(invalid_data("qb: bad color format")),
+        };
+        let z_axis = match read_u32(&mut reader)? {
+            0 => QubicleZAxis::LeftHanded,
+            1 => QubicleZAxis::RightHanded,
+            _ => return Err(invalid_data("qb: bad z axis orientation")),
+        };
+        let compressed = read_u32(&mut reader)? != 0;
+        let visibility_mask = read_u32(&mut reader)? != 0;
+        let count = read_u32(&mut reader)?;
+        let mut model = Self {
+            color_format,
+            z_axis,
+            compressed,
+            visibility_mask,
+            matrices: vec![],
+        };
+        for _ in 0..count {
+            let mut len = [0u8; 1];
+            reader.read_exact(&mut len)?;
+            let mut name = vec![0u8; len[0] as usize];
+            reader.read_exact(&mut name)?;
+            let mut size = [0u32; 3];
+            for s in size.iter_mut() {
+                *s = read_u32(&mut reader)?;
+            }
+            let mut position = [0i32; 3];
+            for p in position.iter_mut() {
+                *p = read_u32(&mut reader)? as i32;
+            }
+            if size[0] as u64 * size[1] as u64 * size[2] as u64 > 1 << 30 {
+                return Err(invalid_data("qb: matrix too big"));
+            }
+            // the voxels are added as they are read, the size is not trusted for the allocation
+            let slice_size = size[0] as usize * size[1] as usize;
+            let mut voxels: Vec<[u8; 4]> = vec![];
+            for _ in 0..size[2] {
+                let end = voxels.len() + slice_size;
+                let put = |voxels: &mut Vec<[u8; 4]>, count: u32, data: u32| {
+                    if count as usize > end - voxels.len() {
+                        return Err(invalid_data("qb: too many voxels in a slice"));
+                    }
+                    voxels.resize(voxels.len() + count as usize, model.decode_color(data));
+                    Ok(())
+                };
+                if compressed {
+                    loop {
+                        let data = read_u32(&mut reader)?;
+                        if data == NEXTSLICEFLAG {
+                            break;
+                        } else if data == CODEFLAG {
+                            let count = read_u32(&mut reader)?;
+                            let data = read_u32(&mut reader)?;
+                            put(&mut voxels, count, data)?;
+                        } else {
+                            put(&mut voxels, 1, data)?;
+                        }
+                    }
+                    voxels.resize(end, [0; 4]);
+                } else {
+                    for _ in 0..slice_size {
+                        let data = read_u32(&mut reader)?;
+                        put(&mut voxels, 1, data)?;
+                    }
+                }
+            }
+            // the slices of the right handed files are stored from the back
+            if z_axis == QubicleZAxis::RightHanded && slice_size > 0 {
+                voxels.reverse();
+                for slice in voxels.chunks_mut(slice_size) {
+                    slice.reverse();
+                }
+            }
+            let matrix = QubicleMatrix {
+                name: String::from_utf8_lossy(&name).to_string(),
+                size,
+                position,
+                voxels,
+            };
+            model.matrices.push(matrix);
+        }
+        Ok(model)
+    }
+
+    fn decode_color(&self, data: u32) -> [u8; 4] {
+        let b = data.to_le_bytes();
+        let a = if self.visibility_mask && b[3] != 0 {
+            255
+        } else {
+            b[3]
+        };
+        match self.color_format {
+            QubicleColorFormat::Rgba => [b[0], b[1], b[2], a],
+            QubicleColorFormat::Bgra => [b[2], b[1], b[0], a],
+        }
+    }
+
+    fn encode_color(&self, c: [u8; 4]) -> u32 {
+        if c[3] == 0 {
+            return 0;
+        }
+        // with a visibility mask, 255 means all the faces are visible
+        match self.color_format {
+            QubicleColorFormat::Rgba => u32::from_le_bytes([c[0], c[1], c[2], 255]),
+            QubicleColorFormat::Bgra => u32::from_le_bytes([c[2], c[1], c[0], 255]),
+        }
+    }
+
+    #[doc = "save the model to a .qb file"]
+    pub fn save_to_file(&self, file_path_name: String) -> std::io::Result<()> {
+        let file = File::create(file_path_name)?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    #[doc = "write the model in the qb format"]
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writer.write_all(&QB_VERSION)?;
+        let header = [
+            (self.color_format == QubicleColorFormat::Bgra) as u32,
+            (self.z_axis == QubicleZAxis::RightHanded) as u32,
+            self.compressed as u32,
+            self.visibility_mask as u32,
+            self.matrices.len() as u32,
+        ];
+        for v in header {
+            writer.write_all(&v.to_le_bytes())?;
+        }
+        for m in &self.matrices {
+            let name = &m.name.as_bytes()[..usize::min(m.name.len(), 255)];
+            writer.write_all(&[name.len() as u8])?;
+            writer.write_all(name)?;
+            for s in m.size {
+                writer.write_all(&s.to_le_bytes())?;
+            }
+            for p in m.position {
+                writer.write_all(&p.to_le_bytes())?;
+            }
+            for z in 0..m.size[2] {
+                let mz = match self.z_axis {
+                    QubicleZAxis::LeftHanded => z,
+                    QubicleZAxis::RightHanded => m.size[2] - 1 - z,
+                };
+                let mut slice = Vec::with_capacity((m.size[0] * m.size[1]) as usize);
+                for y in 0..m.size[1] {
+                    for x in 0..m.size[0] {
+                        slice.push(self.encode_color(m.get_voxel(x, y, mz)));
+                    }
+                }
+                if !self.compressed {
+                    for v in slice {
+                        writer.write_all(&v.to_le_bytes())?;
+                    }
+                    continue;
+                }
+                let mut i = 0;
+                while i < slice.len() {
+                    let v = slice[i];
+                    let mut run = 1;
+                    while i + run < slice.len() && slice[i + run] == v {
+                        run += 1;
+                    }
+                    // the values equal to the flags must be escaped in a run
+                    if run > 2 || v == CODEFLAG || v == NEXTSLICEFLAG {
+                        writer.write_all(&CODEFLAG.to_le_bytes())?;
+                        writer.write_all(&(run as u32).to_le_bytes())?;
+                        writer.write_all(&v.to_le_bytes())?;
+                    } else {
+                        for _ in 0..run {
+                            writer.write_all(&v.to_le_bytes())?;
+                        }
+                    }
+                    i += run;
+                }
+                writer.write_all(&NEXTSLICEFLAG.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "add each matrix of a qb model as a node. the y up of qubicle become the z up of magicavoxel. \
+             the colors are mapped to the palette, quantized if there is not enough free colors"]
+    pub fn add_qubicle(&mut self, model: &QubicleModel) {
+        let mut colors: Vec<[u8; 3]> = vec![];
+        for m in &model.matrices {
+            for c in &m.voxels {
+                if c[3] != 0 {
+                    colors.push([c[0], c[1], c[2]]);
+                }
+            }
+        }
+        let indices = self.map_colors_rgb(&colors);
+        let mut next = 0;
+        for m in &model.matrices {
+            let mut volume = VoxWriter::create_empty();
+            for z in 0..m.size[2] {
+                for y in 0..m.size[1] {
+                    for x in 0..m.size[0] {
+                        if m.get_voxel(x, y, z)[3] != 0 {
+                            volume.add_voxel(x as i32, z as i32, y as i32, indices[next]);
+                            next += 1;
+                        }
+                    }
+                }
+            }
+            let offset = [m.position[0], m.position[2], m.position[1]];
+            self.add_node(m.name.clone(), volume, offset, 0);
+        }
+    }
+
+    #[doc = "get a qb model with a matrix for the main volume, named main, and one per node"]
+    pub fn to_qubicle(&self) -> QubicleModel {
+        let mut model = QubicleModel::create_empty();
+        let mut add = |name: &str, volume: &VoxWriter, offset: [i32; 3]| {
+            let (min, max) = match volume.get_bounds() {
+                Some(b) => b,
+                None => return,
+            };
+            let size = [
+                (max[0] - min[0] + 1) as u32,
+                (max[2] - min[2] + 1) as u32,
+                (max[1] - min[1] + 1) as u32,
+            ];
+            let position = [offset[0] + min[0], offset[2] + min[2], offset[1] + min[1]];
+            let mut m = QubicleMatrix::create(name.to_string(), size, position);
+            volume.for_each_voxel(|x, y, z, c| {
+                let rgba = self.get_palette_color(c as u8);
+                m.set_voxel(
+                    (x - min[0]) as u32,
+                    (z - min[2]) as u32,
+                    (y - min[1]) as u32,
+                    [rgba[0], rgba[1], rgba[2], 255],
+                );
+            });
+            model.matrices.push(m);
+        };
+        add("main", self, [0, 0, 0]);
+        for node in &self.nodes {
+            add(&node.name, &node.volume, node.offset);
+        }
+        model
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_qubicle_round_trip() {
+        let mut vox = VoxWriter::create_empty();
+        for x in 0..5 {
+            vox.add_voxel_rgb(x, 0, 0, 200, 10, 10);
+        }
+        vox.add_voxel_rgb(1, 2, 3, 10, 10, 200);
+        let mut model = vox.to_qubicle();
+        assert_eq!(model.matrices[0].size, [5, 4, 3]);
+
+        for (format, z_axis, compressed) in [
+            (QubicleColorFormat::Rgba, QubicleZAxis::LeftHanded, true),
+            (QubicleColorFormat::Bgra, QubicleZAxis::RightHanded, true),
+            (QubicleColorFormat::Bgra, QubicleZAxis::LeftHanded, false),
+        ] {
+            model.color_format = format;
+            model.z_axis = z_axis;
+            model.compressed = compressed;
+            let mut data: Vec<u8> = vec![];
+            model.write(&mut data).unwrap();
+            let read = QubicleModel::read(Cursor::new(data)).unwrap();
+            assert_eq!(read.z_axis, z_axis);
+            assert_eq!(read.matrices[0].voxels, model.matrices[0].voxels);
+
+            let mut copy = VoxWriter::create_empty();
+            copy.add_qubicle(&read);
+            let node = &copy.get_nodes()[0];
+            assert_eq!(node.name, "main");
+            assert_eq!(node.volume.count_voxels(), 6);
+            let c = node.volume.get_voxel(1, 2, 3).unwrap();
+            assert_eq!(copy.get_palette_color(c as u8), [10, 10, 200, 255]);
+        }
+
+        // the nodes stay in the layer 0 of the main volume after a hidden layer is added
+        let mut copy = VoxWriter::create_empty();
+        let hidden = copy.add_layer("hidden".to_string(), true);
+        copy.add_qubicle(&model);
+        assert_eq!(hidden, 1);
+        assert_eq!(copy.get_nodes()[0].layer_id, 0);
+        assert_eq!(copy.layers[0].name, "main");
+        assert!(!copy.layers[0].hidden);
+    }
+
+    #[test]
+    fn test_read_qb_escaped_flag() {
+        // a 3x1x1 matrix whose first voxel has the value of CODEFLAG
+        let mut data = vec![1, 1, 0, 0];
+        for v in [0u32, 0, 1, 0, 1] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.extend_from_slice(&[1, b'a']);
+        for v in [
+            3u32, 1, 1, 0, 0, 0, CODEFLAG, 1, CODEFLAG, CODEFLAG, 2, 0xff0000ff,
+        ] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.extend_from_slice(&NEXTSLICEFLAG.to_le_bytes());
+        let model = QubicleModel::read(Cursor::new(data)).unwrap();
+        let m = &model.matrices[0];
+        assert_eq!(m.get_voxel(0, 0, 0), [2, 0, 0, 0]);
+        assert_eq!(m.get_voxel(2, 0, 0), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_read_qb_bad_size() {
+        let header = |compressed: u32, size: [u32; 3]| {
+            let mut data = vec![1, 1, 0, 0];
+            for v in [0u32, 0, compressed, 0, 1] {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+            data.extend_from_slice(&[0]);
+            for v in [size[0], size[1], size[2], 0, 0, 0] {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+            data
+        };
+        // a matrix of 1 << 30 voxels without the data
+        let mut data = header(0, [1024; 3]);
+        data.extend_from_slice(&[0; 16]);
+        assert!(QubicleModel::read(Cursor::new(data)).is_err());
+        // a run over the slice
+        let mut data = header(1, [2, 2, 1]);
+        for v in [CODEFLAG, 5, 0xff0000ff, NEXTSLICEFLAG] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        assert!(QubicleModel::read(Cursor::new(data)).is_err());
+    }
+}
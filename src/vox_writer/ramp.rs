@@ -0,0 +1,224 @@
+use super::flood::BitGrid;
+use super::{Axis, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a gradient of colors on a contiguous range of the palette"]
+#[derive(Clone, Debug)]
+pub struct ColorRamp {
+    pub stops: Vec<(f64, [u8; 3])>, // (position in [0:1], color), sorted by position
+    pub first_index: i32,           // color index of the position 0
+    pub count: i32,                 // count of color indices
+}
+
+impl ColorRamp {
+    #[doc = "get the interpolated color at t in [0:1]"]
+    pub fn get_color(&self, t: f64) -> [u8; 3] {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(f), Some(l)) => (f, l),
+            _ => return [0; 3],
+        };
+        if t <= first.0 {
+            return first.1;
+        }
+        for w in self.stops.windows(2) {
+            let ((t0, c0), (t1, c1)) = (w[0], w[1]);
+            if t <= t1 {
+                let a = if t1 > t0 { (t - t0) / (t1 - t0) } else { 1.0 };
+                return [0, 1, 2]
+                    .map(|i| f64::round(c0[i] as f64 + (c1[i] as f64 - c0[i] as f64) * a) as u8);
+            }
+        }
+        last.1
+    }
+
+    #[doc = "get the color index of t in [0:1]"]
+    pub fn get_index(&self, t: f64) -> i32 {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        self.first_index + f64::round(t * (self.count - 1).max(0) as f64) as i32
+    }
+
+    #[doc = "get the color index of a value in [min:max], like a height or a count of iterations"]
+    pub fn get_index_in(&self, value: f64, min: f64, max: f64) -> i32 {
+        if max > min {
+            self.get_index((value - min) / (max - min))
+        } else {
+            self.get_index(0.0)
+        }
+    }
+}
+
+#[doc = "the scalar of a voxel mapped on a color ramp"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RampSource {
+    Height(Axis), // the position along the axis, from the min to the max of the bounds
+    Distance([f64; 3], f64), // the distance to the point, from 0 to the max distance
+    Normal([f64; 3]), // the facing of the surface toward the direction, from opposite to same
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// the normal of the surface at p, from the empty voxels around it
+fn estimate_normal(solid: &BitGrid, p: [i32; 3]) -> [f64; 3] {
+    let mut n = [0.0; 3];
+    for z in -2..=2 {
+        for y in -2..=2 {
+            for x in -2..=2 {
+                if !solid.get([p[0] + x, p[1] + y, p[2] + z]) {
+                    n[0] += x as f64;
+                    n[1] += y as f64;
+                    n[2] += z as f64;
+                }
+            }
+        }
+    }
+    let len = f64::sqrt(n[0] * n[0] + n[1] * n[1] + n[2] * n[2]);
+    if len > 0.0 {
+        n.map(|v| v / len)
+    } else {
+        n
+    }
+}
+
+impl VoxWriter {
+    #[doc = "allocate count contiguous colors of the palette for the gradient of the stops \
+             (position in [0:1], color). return None if there is not enough contiguous free colors"]
+    pub fn add_color_ramp(&mut self, stops: &[(f64, [u8; 3])], count: i32) -> Option<ColorRamp> {
+        if !(1..=255).contains(&count) {
+            return None;
+        }
+        // with the default palette, the colors used by the voxels are taken
+        let used = if self.colors.is_empty() {
+            self.get_used_color_indices()
+        } else {
+            let mut used = [false; 256];
+            for (i, u) in used.iter_mut().enumerate().skip(1) {
+                *u = self.is_color_slot_used(i as u8);
+            }
+            used
+        };
+        let mut first = None;
+        let mut run = 0;
+        for i in 1..=255u8 {
+            if used[i as usize] {
+                run = 0;
+                continue;
+            }
+            run += 1;
+            if run == count {
+                first = Some(i as i32 - count + 1);
+                break;
+            }
+        }
+        let first_index = first?;
+        // the colors of the default palette in use are kept
+        self.copy_default_palette();
+        let mut stops = stops.to_vec();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let ramp = ColorRamp {
+            stops,
+            first_index,
+            count,
+        };
+        for k in 0..count {
+            let c = ramp.get_color(if count > 1 {
+                k as f64 / (count - 1) as f64
+            } else {
+                0.0
+            });
+            self.add_color(c[0], c[1], c[2], 255, (ramp.first_index + k - 1) as u8);
+        }
+        Some(ramp)
+    }
+
+    #[doc = "recolor the voxels of the main volume with the ramp, by the scalar of the source. \
+             return the count of voxels recolored"]
+    pub fn apply_color_ramp(&mut self, ramp: &ColorRamp, source: RampSource) -> usize {
+        let (solid, _, _) = match self.get_occupancy(2) {
+            Some(g) => g,
+            None => return 0,
+        };
+        let (min, max) = self.get_bounds().unwrap_or(([0; 3], [0; 3]));
+        let mut count = 0;
+        self.retain_voxels(|p, color| {
+            let t = match source {
+                RampSource::Height(axis) => {
+                    let i = match axis {
+                        Axis::X => 0,
+                        Axis::Y => 1,
+                        Axis::Z => 2,
+                    };
+                    if max[i] > min[i] {
+                        (p[i] - min[i]) as f64 / (max[i] - min[i]) as f64
+                    } else {
+                        0.0
+                    }
+                }
+                RampSource::Distance(center, max_distance) => {
+                    let d = [0, 1, 2].map(|i| p[i] as f64 - center[i]);
+                    f64::sqrt(d[0] * d[0] + d[1] * d[1] + d[2] * d[2]) / max_distance
+                }
+                RampSource::Normal(dir) => {
+                    let len =
+                        f64::sqrt(dir[0] * dir[0] + dir[1] * dir[1] + dir[2] * dir[2]).max(1e-9);
+                    let n = estimate_normal(&solid, p);
+                    ((n[0] * dir[0] + n[1] * dir[1] + n[2] * dir[2]) / len + 1.0) * 0.5
+                }
+            };
+            let c = ramp.get_index(t) as u8;
+            if c != *color {
+                *color = c;
+                count += 1;
+            }
+            true
+        });
+        count
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::palette::{default_palette, unpack_color};
+    use super::super::ShapeFill;
+    use super::*;
+
+    #[test]
+    fn test_color_ramp() {
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_box([0, 0, 0], [4, 4, 10], 1, ShapeFill::Solid);
+        let stops = [(1.0, [255, 255, 255]), (0.0, [0, 0, 255])];
+        let ramp = vox.add_color_ramp(&stops, 11).unwrap();
+        // the index 1 of the default palette is in use
+        assert_eq!(ramp.first_index, 2);
+        assert_eq!(vox.get_palette_color(1), [255, 255, 255, 255]);
+        assert_eq!(vox.get_palette_color(2), [0, 0, 255, 255]);
+        assert_eq!(vox.get_palette_color(7), [128, 128, 255, 255]);
+        assert_eq!(ramp.get_index_in(5.0, 0.0, 10.0), 7);
+
+        vox.apply_color_ramp(&ramp, RampSource::Height(Axis::Z));
+        assert_eq!(vox.get_voxel(2, 2, 0), Some(2));
+        assert_eq!(vox.get_voxel(2, 2, 10), Some(12));
+        vox.apply_color_ramp(&ramp, RampSource::Normal([0.0, 0.0, 1.0]));
+        assert_eq!(vox.get_voxel(2, 2, 10), Some(12));
+        assert_eq!(vox.get_voxel(2, 2, 0), Some(2));
+        assert_eq!(vox.get_voxel(0, 2, 5), Some(7));
+
+        // no room for a second ramp of 250 colors
+        assert!(vox.add_color_ramp(&stops, 250).is_none());
+
+        // a failed ramp keeps the default palette
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(0, 0, 0, 128);
+        assert!(vox.add_color_ramp(&stops, 200).is_none());
+        assert!(vox.colors.is_empty());
+        let ramp = vox.add_color_ramp(&stops, 127).unwrap();
+        assert_eq!(ramp.first_index, 1);
+        assert_eq!(
+            vox.get_palette_color(128),
+            unpack_color(default_palette()[128])
+        );
+    }
+}
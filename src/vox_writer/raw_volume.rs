@@ -0,0 +1,429 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Component, Path};
+
+use super::zlib::gzip_decompress;
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "the type of the samples of a raw volume"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RawSampleType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl RawSampleType {
+    fn size(&self) -> usize {
+        match self {
+            Self::U8 | Self::I8 => 1,
+            Self::U16 | Self::I16 => 2,
+            Self::U32 | Self::I32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn decode(&self, b: &[u8], big_endian: bool) -> f32 {
+        macro_rules! num {
+            ($t:ty) => {{
+                let mut v = [0u8; std::mem::size_of::<$t>()];
+                v.copy_from_slice(b);
+                if big_endian {
+                    <$t>::from_be_bytes(v) as f32
+                } else {
+                    <$t>::from_le_bytes(v) as f32
+                }
+            }};
+        }
+        match self {
+            Self::U8 => num!(u8),
+            Self::I8 => num!(i8),
+            Self::U16 => num!(u16),
+            Self::I16 => num!(i16),
+            Self::U32 => num!(u32),
+            Self::I32 => num!(i32),
+            Self::F32 => num!(f32),
+            Self::F64 => num!(f64),
+        }
+    }
+
+    /// the type names of the nrrd format
+    fn from_nrrd(name: &str) -> Option<Self> {
+        match name {
+            "uchar" | "unsigned char" | "uint8" | "uint8_t" => Some(Self::U8),
+            "signed char" | "int8" | "int8_t" => Some(Self::I8),
+            "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+                Some(Self::U16)
+            }
+            "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+                Some(Self::I16)
+            }
+            "uint" | "unsigned int" | "uint32" | "uint32_t" => Some(Self::U32),
+            "int" | "signed int" | "int32" | "int32_t" => Some(Self::I32),
+            "float" => Some(Self::F32),
+            "double" => Some(Self::F64),
+            _ => None,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "map the scalar values of a volume to color indices. \
+         the values in no range are empty, the first range containing a value wins"]
+#[derive(Clone, Default, Debug)]
+pub struct TransferFunction {
+    ranges: Vec<(f32, f32, i32)>,
+}
+
+impl TransferFunction {
+    #[doc = "create a transfer function without range, so everything is empty"]
+    pub fn create_empty() -> Self {
+        Self::default()
+    }
+
+    #[doc = "the values in [min:max] get color_index"]
+    pub fn add_range(&mut self, min: f32, max: f32, color_index: i32) {
+        self.ranges.push((min, max, color_index));
+    }
+
+    #[doc = "get the color index of a value, None if empty"]
+    pub fn get_color(&self, value: f32) -> Option<i32> {
+        self.ranges
+            .iter()
+            .find(|r| value >= r.0 && value <= r.1)
+            .map(|r| r.2)
+    }
+}
+
+/// the count of samples and of bytes of a volume, none on overflow
+fn get_sizes(dims: [usize; 3], sample_type: RawSampleType) -> Option<(usize, usize)> {
+    let count = dims[0].checked_mul(dims[1])?.checked_mul(dims[2])?;
+    Some((count, count.checked_mul(sample_type.size())?))
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a dense scalar volume, x varying first then y then z"]
+pub struct RawVolume {
+    pub dims: [usize; 3],
+    pub values: Vec<f32>,
+}
+
+impl RawVolume {
+    #[doc = "load a headerless volume of dims samples"]
+    pub fn load_raw(
+        file_path_name: String,
+        dims: [usize; 3],
+        sample_type: RawSampleType,
+        big_endian: bool,
+    ) -> std::io::Result<Self> {
+        let file = File::open(file_path_name)?;
+        Self::read_raw(BufReader::new(file), dims, sample_type, big_endian)
+    }
+
+    #[doc = "read a headerless volume of dims samples"]
+    pub fn read_raw<R: Read>(
+        mut reader: R,
+        dims: [usize; 3],
+        sample_type: RawSampleType,
+        big_endian: bool,
+    ) -> std::io::Result<Self> {
+        let (_, size) =
+            get_sizes(dims, sample_type).ok_or_else(|| invalid_data("raw: bad size"))?;
+        let mut data = vec![0u8; size];
+        reader.read_exact(&mut data)?;
+        Ok(Self::from_bytes(&data, dims, sample_type, big_endian))
+    }
+
+    fn from_bytes(
+        data: &[u8],
+        dims: [usize; 3],
+        sample_type: RawSampleType,
+        big_endian: bool,
+    ) -> Self {
+        let values = data
+            .chunks_exact(sample_type.size())
+            .take(dims[0] * dims[1] * dims[2])
+            .map(|b| sample_type.decode(b, big_endian))
+            .collect();
+        Self { dims, values }
+    }
+
+    #[doc = "load a 3d nrrd volume, with its data attached or in a detached data file of the \
+             directory of the header. the raw, gzip and ascii encodings are supported"]
+    pub fn load_nrrd(file_path_name: String) -> std::io::Result<Self> {
+        let data = fs::read(&file_path_name)?;
+        let dir = Path::new(&file_path_name)
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
+        Self::parse_nrrd(&data, |name| fs::read(dir.join(name)))
+    }
+
+    #[doc = "read a 3d nrrd volume with its data attached"]
+    pub fn read_nrrd<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut data: Vec<u8> = vec![];
+        reader.read_to_end(&mut data)?;
+        Self::parse_nrrd(&data, |_| Err(invalid_data("nrrd: detached data file")))
+    }
+
+    fn parse_nrrd<F: Fn(&str) -> std::io::Result<Vec<u8>>>(
+        data: &[u8],
+        read_data_file: F,
+    ) -> std::io::Result<Self> {
+        if !data.starts_with(b"NRRD") {
+            return Err(invalid_data("nrrd: bad magic"));
+        }
+        // the header ends with an empty line
+        let mut pos = 0;
+        let mut sample_type: Option<RawSampleType> = None;
+        let mut dims: Option<[usize; 3]> = None;
+        let mut big_endian = false;
+        let mut encoding = String::from("raw");
+        let mut data_file: Option<String> = None;
+        let mut byte_skip = 0usize;
+        let mut line_skip = 0usize;
+        loop {
+            let end = match data[pos..].iter().position(|c| *c == b'\n') {
+                Some(e) => pos + e,
+                None => return Err(invalid_data("nrrd: no end of header")),
+            };
+            let line = String::from_utf8_lossy(&data[pos..end]).trim().to_string();
+            pos = end + 1;
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with('#') || line.starts_with("NRRD") || line.contains(":=") {
+                continue;
+            }
+            let (key, value) = match line.split_once(':') {
+                Some((k, v)) => (k.trim().to_lowercase(), v.trim().to_string()),
+                None => continue,
+            };
+            match key.as_str() {
+                "type" => {
+                    sample_type = Some(
+                        RawSampleType::from_nrrd(&value)
+                            .ok_or_else(|| invalid_data("nrrd: unsupported type"))?,
+                    )
+                }
+                "dimension" if value != "3" => {
+                    return Err(invalid_data("nrrd: only 3d volumes are supported"));
+                }
+                "sizes" => {
+                    let sizes: Vec<usize> = value
+                        .split_whitespace()
+                        .map(|s| s.parse::<usize>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid_data("nrrd: bad sizes"))?;
+                    if sizes.len() != 3 {
+                        return Err(invalid_data("nrrd: bad sizes"));
+                    }
+                    dims = Some([sizes[0], sizes[1], sizes[2]]);
+                }
+                "endian" => big_endian = value == "big",
+                "encoding" => encoding = value.to_lowercase(),
+                "data file" | "datafile" => data_file = Some(value),
+                "line skip" | "lineskip" => {
+                    line_skip = value
+                        .parse::<usize>()
+                        .map_err(|_| invalid_data("nrrd: bad line skip"))?
+                }
+                "byte skip" | "byteskip" => {
+                    byte_skip = value
+                        .parse::<usize>()
+                        .map_err(|_| invalid_data("nrrd: unsupported byte skip"))?
+                }
+                _ => {} // spacings, space directions, ...
+            }
+        }
+        let sample_type = sample_type.ok_or_else(|| invalid_data("nrrd: no type"))?;
+        let dims = dims.ok_or_else(|| invalid_data("nrrd: no sizes"))?;
+        let payload = match &data_file {
+            Some(name) => {
+                // the data file is next to the header
+                let is_local = Path::new(name)
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+                if !is_local {
+                    return Err(invalid_data(
+                        "nrrd: data file outside of the header directory",
+                    ));
+                }
+                read_data_file(name)?
+            }
+            None => data[pos..].to_vec(),
+        };
+        // the lines are skipped in the file, the bytes after the decompression
+        let mut payload = payload.as_slice();
+        for _ in 0..line_skip {
+            let end = payload
+                .iter()
+                .position(|c| *c == b'\n')
+                .ok_or_else(|| invalid_data("nrrd: not enough lines to skip"))?;
+            payload = &payload[end + 1..];
+        }
+        let (count, size) =
+            get_sizes(dims, sample_type).ok_or_else(|| invalid_data("nrrd: bad sizes"))?;
+        match encoding.as_str() {
+            "raw" | "gzip" | "gz" => {
+                // the byte skip is counted in the decompressed data
+                let bytes = if encoding == "raw" {
+                    payload.to_vec()
+                } else {
+                    gzip_decompress(payload, byte_skip.saturating_add(size))?
+                };
+                let bytes = bytes.get(byte_skip..).unwrap_or_default();
+                if bytes.len() < size {
+                    return Err(invalid_data("nrrd: not enough data"));
+                }
+                Ok(Self::from_bytes(bytes, dims, sample_type, big_endian))
+            }
+            "ascii" | "text" | "txt" => {
+                let values: Vec<f32> = String::from_utf8_lossy(payload)
+                    .split_whitespace()
+                    .take(count)
+                    .map(|s| s.parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid_data("nrrd: bad value"))?;
+                if values.len() < count {
+                    return Err(invalid_data("nrrd: not enough data"));
+                }
+                Ok(Self { dims, values })
+            }
+            _ => Err(invalid_data("nrrd: unsupported encoding")),
+        }
+    }
+
+    #[doc = "get the value at x,y,z"]
+    pub fn get_value(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[(z * self.dims[1] + y) * self.dims[0] + x]
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "add a voxel for each value of the volume mapped to a color by the transfer function. \
+             return the count of voxels added"]
+    pub fn add_raw_volume(&mut self, volume: &RawVolume, transfer: &TransferFunction) -> usize {
+        let mut count = 0;
+        for z in 0..volume.dims[2] {
+            for y in 0..volume.dims[1] {
+                for x in 0..volume.dims[0] {
+                    if let Some(c) = transfer.get_color(volume.get_value(x, y, z)) {
+                        self.add_voxel(x as i32, y as i32, z as i32, c);
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::zlib::{crc32, deflate};
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_raw_and_transfer() {
+        let data: Vec<u8> = [0u16, 100, 1000, 60000]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let volume =
+            RawVolume::read_raw(Cursor::new(data), [2, 2, 1], RawSampleType::U16, false).unwrap();
+        assert_eq!(volume.get_value(0, 1, 0), 1000.0);
+
+        let mut transfer = TransferFunction::create_empty();
+        transfer.add_range(50.0, 999.0, 3);
+        transfer.add_range(999.0, 70000.0, 4);
+        let mut vox = VoxWriter::create_empty();
+        assert_eq!(vox.add_raw_volume(&volume, &transfer), 3);
+        assert_eq!(vox.get_voxel(0, 0, 0), None);
+        assert_eq!(vox.get_voxel(1, 0, 0), Some(3));
+        assert_eq!(vox.get_voxel(1, 1, 0), Some(4));
+    }
+
+    #[test]
+    fn test_read_nrrd() {
+        let header = "NRRD0004\n# comment\ntype: float\ndimension: 3\nsizes: 2 1 1\n\
+                      endian: big\nencoding: raw\n\n";
+        let mut data = header.as_bytes().to_vec();
+        data.extend_from_slice(&1.5f32.to_be_bytes());
+        data.extend_from_slice(&(-2.0f32).to_be_bytes());
+        let volume = RawVolume::read_nrrd(Cursor::new(data)).unwrap();
+        assert_eq!(volume.values, vec![1.5, -2.0]);
+
+        let raw = [7u8, 8, 9];
+        let mut gz = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+        gz.extend(deflate(&raw));
+        gz.extend_from_slice(&crc32(&raw).to_le_bytes());
+        gz.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+        let header = "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 1 1 3\nencoding: gzip\n\n";
+        let mut data = header.as_bytes().to_vec();
+        data.extend(gz.clone());
+        let volume = RawVolume::read_nrrd(Cursor::new(data)).unwrap();
+        assert_eq!(volume.values, vec![7.0, 8.0, 9.0]);
+
+        // the byte skip applies to the decompressed data
+        let header = "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 1 1 2\nencoding: gzip\n\
+                      byte skip: 1\n\n";
+        let mut data = header.as_bytes().to_vec();
+        data.extend(gz);
+        let volume = RawVolume::read_nrrd(Cursor::new(data)).unwrap();
+        assert_eq!(volume.values, vec![8.0, 9.0]);
+    }
+
+    #[test]
+    fn test_read_nrrd_bad_headers() {
+        // the sizes overflow
+        let header = "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 4294967296 4294967296 2\n\n";
+        assert!(RawVolume::read_nrrd(Cursor::new(header)).is_err());
+        let header = "NRRD0004\ntype: double\ndimension: 3\nsizes: 4294967296 536870912 1\n\n";
+        assert!(RawVolume::read_nrrd(Cursor::new(header)).is_err());
+        assert!(RawVolume::read_raw(
+            Cursor::new(vec![0u8; 4]),
+            [1 << 32, 1 << 32, 2],
+            RawSampleType::U8,
+            false
+        )
+        .is_err());
+
+        // the lines of the data file are skipped before the bytes
+        let header = "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 1 1 2\n\
+                      data file: volume.raw\nline skip: 2\nbyte skip: 1\n\n";
+        let read = |name: &str| -> std::io::Result<Vec<u8>> {
+            assert_eq!(name, "volume.raw");
+            Ok(b"first line\nsecond line\n\x01\x02\x03".to_vec())
+        };
+        let volume = RawVolume::parse_nrrd(header.as_bytes(), read).unwrap();
+        assert_eq!(volume.values, vec![2.0, 3.0]);
+
+        // the data file must be in the directory of the header
+        for name in ["../volume.raw", "/tmp/volume.raw", "data/../../volume.raw"] {
+            let header = format!(
+                "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 1 1 1\ndata file: {}\n\n",
+                name
+            );
+            let volume = RawVolume::parse_nrrd(header.as_bytes(), |_| Ok(vec![0u8]));
+            assert!(volume.is_err());
+        }
+        let header = "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 1 1 1\n\
+                      data file: ./data/volume.raw\n\n";
+        assert!(RawVolume::parse_nrrd(header.as_bytes(), |_| Ok(vec![0u8])).is_ok());
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    /// an empty volume with the same cubes size and palette
+    pub(crate) fn create_same(&self) -> VoxWriter {
+        let mut vox = VoxWriter::create(
+            self.max_voxel_per_cube_x,
+            self.max_voxel_per_cube_y,
+            self.max_voxel_per_cube_z,
+        );
+        vox.colors = self.colors.clone();
+        vox
+    }
+
+    #[doc = "get a volume smaller by factor, each block of factor^3 voxels gives one voxel if at least \
+             min_occupancy (in [0:1]) of the block is filled, with the most common color of the block \
+             (the lowest index for the ties). the palette is kept"]
+    pub fn downsample(&self, factor: i32, min_occupancy: f64) -> VoxWriter {
+        let mut vox = self.create_same();
+        let factor = factor.max(1);
+        let mut blocks: HashMap<[i32; 3], [u32; 256]> = HashMap::new();
+        self.for_each_voxel(|x, y, z, c| {
+            let block = [x, y, z].map(|v| v.div_euclid(factor));
+            blocks.entry(block).or_insert([0; 256])[c as u8 as usize] += 1;
+        });
+        let min_count = min_occupancy * (factor as f64).powi(3);
+        let mut blocks: Vec<([i32; 3], [u32; 256])> = blocks.into_iter().collect();
+        blocks.sort_by_key(|b| [b.0[2], b.0[1], b.0[0]]);
+        for (p, counts) in blocks {
+            let count: u32 = counts.iter().sum();
+            if (count as f64) < min_count {
+                continue;
+            }
+            let mut color = 0;
+            for (c, n) in counts.iter().enumerate() {
+                if *n > counts[color] {
+                    color = c;
+                }
+            }
+            vox.add_voxel(p[0], p[1], p[2], color as i32);
+        }
+        vox
+    }
+
+    #[doc = "get a volume bigger by factor, each voxel gives a block of factor^3 voxels. the voxels \
+             whose block goes out of the i32 positions are dropped. the palette is kept"]
+    pub fn upscale(&self, factor: i32) -> VoxWriter {
+        let mut vox = self.create_same();
+        let factor = factor.max(1);
+        self.for_each_voxel(|x, y, z, c| {
+            // the last voxel of the block must fit, so the others do
+            let last = [x, y, z].map(|v| {
+                v.checked_mul(factor)
+                    .and_then(|v| v.checked_add(factor - 1))
+            });
+            if last.iter().any(|v| v.is_none()) {
+                return;
+            }
+            let (x, y, z) = (x * factor, y * factor, z * factor);
+            for dz in 0..factor {
+                for dy in 0..factor {
+                    for dx in 0..factor {
+                        vox.add_voxel(x + dx, y + dy, z + dz, c);
+                    }
+                }
+            }
+        });
+        vox
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::ShapeFill;
+    use super::*;
+
+    #[test]
+    fn test_resample() {
+        let mut vox = VoxWriter::create_empty();
+        vox.add_color(255, 0, 0, 255, 4);
+        vox.draw_box([-2, 0, 0], [1, 1, 1], 4, ShapeFill::Solid);
+        vox.add_voxel(2, 0, 0, 7);
+        vox.add_voxel(3, 0, 0, 7);
+        vox.add_voxel(2, 1, 0, 6);
+
+        let half = vox.downsample(2, 0.25);
+        assert_eq!(half.count_voxels(), 3);
+        assert_eq!(half.get_voxel(-1, 0, 0), Some(4));
+        assert_eq!(half.get_voxel(1, 0, 0), Some(7));
+        assert_eq!(half.get_palette_color(4), vox.get_palette_color(4));
+        assert_eq!(vox.downsample(2, 0.5).count_voxels(), 2);
+
+        let double = half.upscale(2);
+        assert_eq!(double.count_voxels(), 3 * 8);
+        assert_eq!(double.get_voxel(-2, 1, 1), Some(4));
+        assert_eq!(double.get_voxel(3, 1, 1), Some(7));
+
+        // the factors too big for the i32 positions
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(-3, 0, 0, 1);
+        vox.add_voxel(1 << 29, 0, 0, 2);
+        vox.add_voxel(i32::MAX, 0, 0, 3);
+        let big = vox.downsample(i32::MAX, 0.0);
+        assert_eq!(big.count_voxels(), 3);
+        assert_eq!(big.get_voxel(-1, 0, 0), Some(1));
+        assert_eq!(big.get_voxel(0, 0, 0), Some(2));
+        assert_eq!(big.get_voxel(1, 0, 0), Some(3));
+        let big = vox.upscale(4);
+        assert_eq!(big.count_voxels(), 64);
+        assert_eq!(big.get_voxel(i32::MAX, 3, 3), None);
+        assert_eq!(big.get_voxel(-9, 3, 3), Some(1));
+    }
+}
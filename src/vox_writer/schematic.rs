@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use super::nbt::{read_nbt, write_nbt, Nbt, TAG_COMPOUND};
+use super::zlib::{gzip_compress, gzip_decompress};
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+const AIR: &str = "minecraft:air";
+const MAX_DECOMPRESSED_SIZE: usize = 1 << 30; // the most bytes of the nbt data
+
+#[doc = "the version of the sponge schematic format"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SchematicVersion {
+    V2,
+    V3,
+}
+
+#[doc = "the mapping between the color indices and the minecraft block states"]
+#[derive(Clone, Debug)]
+pub struct SchematicOptions {
+    pub version: SchematicVersion,
+    pub data_version: i32, // the minecraft data version, 3465 is the 1.20.1
+    pub block_states: HashMap<i32, String>, // color index -> block state like minecraft:oak_log[axis=y]
+    pub default_block: String,              // block of the color indices not in block_states
+    pub default_color_index: i32,           // color of the block states not in block_states
+}
+
+impl SchematicOptions {
+    #[doc = "v2 schematics for the 1.20.1, all the colors are stone"]
+    pub fn create_empty() -> Self {
+        Self {
+            version: SchematicVersion::V2,
+            data_version: 3465,
+            block_states: HashMap::new(),
+            default_block: "minecraft:stone".to_string(),
+            default_color_index: 1,
+        }
+    }
+
+    #[doc = "set the block state of a color index"]
+    pub fn set_block_state(&mut self, color_index: i32, block_state: String) {
+        self.block_states.insert(color_index, block_state);
+    }
+
+    fn get_block_state(&self, color_index: i32) -> &str {
+        self.block_states
+            .get(&color_index)
+            .unwrap_or(&self.default_block)
+    }
+
+    /// the color of a block state, the properties are ignored if there is no exact match
+    fn get_color_index(&self, block_state: &str) -> i32 {
+        let name = |s: &str| s.split('[').next().unwrap_or("").to_string();
+        let mut found: Option<i32> = None;
+        let mut found_by_name: Option<i32> = None;
+        for (color, state) in &self.block_states {
+            if state == block_state {
+                found = Some(found.map_or(*color, |c| i32::min(c, *color)));
+            } else if name(state) == name(block_state) {
+                found_by_name = Some(found_by_name.map_or(*color, |c| i32::min(c, *color)));
+            }
+        }
+        found.or(found_by_name).unwrap_or(self.default_color_index)
+    }
+}
+
+fn is_air(block_state: &str) -> bool {
+    matches!(
+        block_state,
+        "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air"
+    )
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a sponge schematic, y is up"]
+#[derive(Clone, Debug)]
+pub struct Schematic {
+    pub version: SchematicVersion,
+    pub data_version: i32,
+    pub size: [u16; 3], // width (x), height (y), length (z)
+    pub offset: [i32; 3],
+    pub palette: Vec<String>, // block states, the index is the block id
+    pub blocks: Vec<u32>,     // x + z * width + y * width * length
+}
+
+impl Schematic {
+    #[doc = "create a schematic full of air"]
+    pub fn create(size: [u16; 3]) -> Self {
+        let count = size[0] as usize * size[1] as usize * size[2] as usize;
+        Self {
+            version: SchematicVersion::V2,
+            data_version: 3465,
+            size,
+            offset: [0; 3],
+            palette: vec![AIR.to_string()],
+            blocks: vec![0; count],
+        }
+    }
+
+    fn index(&self, x: u16, y: u16, z: u16) -> usize {
+        (y as usize * self.size[2] as usize + z as usize) * self.size[0] as usize + x as usize
+    }
+
+    #[doc = "get the block state at x,y,z"]
+    pub fn get_block(&self, x: u16, y: u16, z: u16) -> &str {
+        let id = self.blocks[self.index(x, y, z)] as usize;
+        self.palette.get(id).map_or(AIR, |s| s.as_str())
+    }
+
+    #[doc = "set the block state at x,y,z, the state is added to the palette if needed"]
+    pub fn set_block(&mut self, x: u16, y: u16, z: u16, block_state: &str) {
+        let id = match self.palette.iter().position(|s| s == block_state) {
+            Some(id) => id,
+            None => {
+                self.palette.push(block_state.to_string());
+                self.palette.len() - 1
+            }
+        };
+        let i = self.index(x, y, z);
+        self.blocks[i] = id as u32;
+    }
+
+    #[doc = "load a .schem file, v1, v2 or v3"]
+    pub fn load_from_file(file_path_name: String) -> std::io::Result<Self> {
+        let file = File::open(file_path_name)?;
+        Self::read(BufReader::new(file))
+    }
+
+    #[doc = "read a gzip compressed (or not) schematic"]
+    pub fn read<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut data: Vec<u8> = vec![];
+        reader.read_to_end(&mut data)?;
+        if data.len() >= 2 && data[0] == 0x1f && data[1] == 0x8b {
+            data = gzip_decompress(&data, MAX_DECOMPRESSED_SIZE)?;
+        }
+        let (_, root) = read_nbt(&data)?;
+        // the v3 put the schematic in a compound of the root
+        let schem = match root.get("Schematic") {
+            Some(s @ Nbt::Compound(_)) => s,
+            _ => &root,
+        };
+        let int = |n: &Nbt, name: &str| -> std::io::Result<i64> {
+            n.get(name)
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| invalid_data(&format!("schematic: no {}", name)))
+        };
+        let version_number = int(schem, "Version")?;
+        // the sizes are unsigned shorts in short tags, the other tags must not be negative
+        let side = |name: &str| -> std::io::Result<u16> {
+            match schem.get(name) {
+                Some(Nbt::Short(v)) => Ok(*v as u16),
+                _ => u16::try_from(int(schem, name)?)
+                    .map_err(|_| invalid_data("schematic: bad size")),
+            }
+        };
+        let size = [side("Width")?, side("Height")?, side("Length")?];
+        let offset = match schem.get("Offset") {
+            Some(Nbt::IntArray(v)) if v.len() == 3 => [v[0], v[1], v[2]],
+            _ => [0; 3],
+        };
+        let (palette_tag, data_tag) = if version_number >= 3 {
+            let blocks = schem
+                .get("Blocks")
+                .ok_or_else(|| invalid_data("schematic: no Blocks"))?;
+            (blocks.get("Palette"), blocks.get("Data"))
+        } else {
+            (schem.get("Palette"), schem.get("BlockData"))
+        };
+        let mut palette: Vec<String> = vec![];
+        if let Some(Nbt::Compound(entries)) = palette_tag {
+            for (state, id) in entries {
+                let id = id
+                    .as_i64()
+                    .filter(|id| (0..=u16::MAX as i64).contains(id))
+                    .ok_or_else(|| invalid_data("schematic: bad palette id"))?
+                    as usize;
+                if palette.len() <= id {
+                    palette.resize(id + 1, AIR.to_string());
+                }
+                palette[id] = state.clone();
+            }
+        }
+        let block_data = match data_tag {
+            Some(Nbt::ByteArray(v)) => v,
+            _ => return Err(invalid_data("schematic: no block data")),
+        };
+        let count = size[0] as usize * size[1] as usize * size[2] as usize;
+        // each block takes at least one byte of the varints
+        if block_data.len() < count {
+            return Err(invalid_data("schematic: not enough block data"));
+        }
+        let mut blocks = Vec::with_capacity(count);
+        let mut value = 0u32;
+        let mut shift = 0;
+        for b in block_data {
+            if shift > 28 {
+                return Err(invalid_data("schematic: bad varint"));
+            }
+            value |= ((b & 0x7f) as u32) << shift;
+            if b & 0x80 == 0 {
+                blocks.push(value);
+                value = 0;
+                shift = 0;
+            } else {
+                shift += 7;
+            }
+        }
+        if blocks.len() < count {
+            return Err(invalid_data("schematic: not enough block data"));
+        }
+        blocks.truncate(count);
+        Ok(Self {
+            version: if version_number >= 3 {
+                SchematicVersion::V3
+            } else {
+                SchematicVersion::V2
+            },
+            data_version: schem
+                .get("DataVersion")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(0) as i32,
+            size,
+            offset,
+            palette,
+            blocks,
+        })
+    }
+
+    #[doc = "save the schematic to a .schem file"]
+    pub fn save_to_file(&self, file_path_name: String) -> std::io::Result<()> {
+        let file = File::create(file_path_name)?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    #[doc = "write the schematic as gzip compressed nbt, in its version"]
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let palette = Nbt::Compound(
+            self.palette
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (s.clone(), Nbt::Int(i as i32)))
+                .collect(),
+        );
+        let mut block_data: Vec<u8> = vec![];
+        for b in &self.blocks {
+            let mut v = *b;
+            while v >= 0x80 {
+                block_data.push((v & 0x7f) as u8 | 0x80);
+                v >>= 7;
+            }
+            block_data.push(v as u8);
+        }
+        let block_entities = Nbt::List(TAG_COMPOUND, vec![]);
+        let mut entries = vec![
+            (
+                "Version".to_string(),
+                Nbt::Int(match self.version {
+                    SchematicVersion::V2 => 2,
+                    SchematicVersion::V3 => 3,
+                }),
+            ),
+            ("DataVersion".to_string(), Nbt::Int(self.data_version)),
+            ("Width".to_string(), Nbt::Short(self.size[0] as i16)),
+            ("Height".to_string(), Nbt::Short(self.size[1] as i16)),
+            ("Length".to_string(), Nbt::Short(self.size[2] as i16)),
+            ("Offset".to_string(), Nbt::IntArray(self.offset.to_vec())),
+        ];
+        let nbt = match self.version {
+            SchematicVersion::V2 => {
+                entries.push((
+                    "PaletteMax".to_string(),
+                    Nbt::Int(self.palette.len() as i32),
+                ));
+                entries.push(("Palette".to_string(), palette));
+                entries.push(("BlockData".to_string(), Nbt::ByteArray(block_data)));
+                entries.push(("BlockEntities".to_string(), block_entities));
+                write_nbt("Schematic", &Nbt::Compound(entries))
+            }
+            SchematicVersion::V3 => {
+                entries.push((
+                    "Blocks".to_string(),
+                    Nbt::Compound(vec![
+                        ("Palette".to_string(), palette),
+                        ("Data".to_string(), Nbt::ByteArray(block_data)),
+                        ("BlockEntities".to_string(), block_entities),
+                    ]),
+                ));
+                let root = Nbt::Compound(vec![("Schematic".to_string(), Nbt::Compound(entries))]);
+                write_nbt("", &root)
+            }
+        };
+        writer.write_all(&gzip_compress(&nbt))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "get a schematic of the voxels, the block states come from the color indices. \
+             the z up of magicavoxel become the y up of minecraft and the y of the vox goes to the north (-z)"]
+    pub fn to_schematic(&self, options: &SchematicOptions) -> Schematic {
+        let (min, max) = self.get_bounds().unwrap_or(([0; 3], [-1; 3]));
+        let size = [
+            (max[0] - min[0] + 1) as u16,
+            (max[2] - min[2] + 1) as u16,
+            (max[1] - min[1] + 1) as u16,
+        ];
+        let mut schem = Schematic::create(size);
+        schem.version = options.version;
+        schem.data_version = options.data_version;
+        self.for_each_voxel(|x, y, z, c| {
+            schem.set_block(
+                (x - min[0]) as u16,
+                (z - min[2]) as u16,
+                (max[1] - y) as u16,
+                options.get_block_state(c),
+            );
+        });
+        schem
+    }
+
+    #[doc = "add the blocks of a schematic, except the air. the block states are mapped to the \
+             color indices of options, or to its default_color_index. return the count of voxels added"]
+    pub fn add_schematic(&mut self, schem: &Schematic, options: &SchematicOptions) -> usize {
+        let colors: Vec<Option<i32>> = schem
+            .palette
+            .iter()
+            .map(|s| (!is_air(s)).then(|| options.get_color_index(s)))
+            .collect();
+        let mut count = 0;
+        for y in 0..schem.size[1] {
+            for z in 0..schem.size[2] {
+                for x in 0..schem.size[0] {
+                    let id = schem.blocks[schem.index(x, y, z)] as usize;
+                    if let Some(Some(c)) = colors.get(id) {
+                        let vy = schem.size[2] as i32 - 1 - z as i32;
+                        self.add_voxel(x as i32, vy, y as i32, *c);
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_schematic_round_trip() {
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(0, 0, 0, 1);
+        vox.add_voxel(3, 1, 0, 2);
+        vox.add_voxel(3, 1, 200, 7);
+        let mut options = SchematicOptions::create_empty();
+        options.set_block_state(2, "minecraft:oak_log[axis=y]".to_string());
+        options.set_block_state(5, "minecraft:glass".to_string());
+
+        for version in [SchematicVersion::V2, SchematicVersion::V3] {
+            options.version = version;
+            let schem = vox.to_schematic(&options);
+            assert_eq!(schem.size, [4, 201, 2]);
+            // the vox y 0 is at the south
+            assert_eq!(schem.get_block(0, 0, 1), "minecraft:stone");
+            assert_eq!(schem.get_block(3, 0, 0), "minecraft:oak_log[axis=y]");
+
+            let mut data: Vec<u8> = vec![];
+            schem.write(&mut data).unwrap();
+            let read = Schematic::read(Cursor::new(data)).unwrap();
+            assert_eq!(read.version, version);
+            assert_eq!(read.blocks, schem.blocks);
+
+            let mut copy = VoxWriter::create_empty();
+            assert_eq!(copy.add_schematic(&read, &options), 3);
+            assert_eq!(copy.get_voxel(0, 0, 0), Some(1));
+            assert_eq!(copy.get_voxel(3, 1, 0), Some(2));
+            // the stone is the default block, so it get the default color
+            assert_eq!(copy.get_voxel(3, 1, 200), Some(1));
+        }
+    }
+
+    #[test]
+    fn test_block_state_properties() {
+        let mut options = SchematicOptions::create_empty();
+        options.set_block_state(4, "minecraft:oak_log[axis=y]".to_string());
+        assert_eq!(options.get_color_index("minecraft:oak_log[axis=x]"), 4);
+        assert_eq!(options.get_color_index("minecraft:dirt"), 1);
+    }
+
+    #[test]
+    fn test_read_schematic_bad_size() {
+        // the sizes of the header are far over the block data
+        let header = |width: Nbt| {
+            Nbt::Compound(vec![
+                ("Version".to_string(), Nbt::Int(2)),
+                ("Width".to_string(), width),
+                ("Height".to_string(), Nbt::Short(-1)),
+                ("Length".to_string(), Nbt::Short(-1)),
+                ("BlockData".to_string(), Nbt::ByteArray(vec![0; 4])),
+            ])
+        };
+        for width in [Nbt::Short(-1), Nbt::Int(-1), Nbt::Int(70000)] {
+            let data = write_nbt("", &header(width));
+            assert!(Schematic::read(Cursor::new(data)).is_err());
+        }
+    }
+}
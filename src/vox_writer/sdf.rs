@@ -0,0 +1,287 @@
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "a signed distance function, negative inside. the primitives are centered on the origin, \
+         and the shapes around an axis go along z"]
+#[derive(Clone, Debug)]
+pub enum Sdf {
+    Sphere(f64),                          // radius
+    Box([f64; 3]),                        // half size
+    Cylinder(f64, f64),                   // radius, half height
+    Torus(f64, f64),                      // major radius, minor radius
+    Capsule([f64; 3], [f64; 3], f64),     // from, to, radius
+    Plane([f64; 3]),                      // normalized normal, the inside is below
+    Translate(Box<Sdf>, [f64; 3]),        // the shape moved by the offset
+    Union(Box<Sdf>, Box<Sdf>),            // inside a or b
+    Subtract(Box<Sdf>, Box<Sdf>),         // inside a but not b
+    Intersect(Box<Sdf>, Box<Sdf>),        // inside a and b
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f64), // union blended over the distance k
+    Repeat(Box<Sdf>, [f64; 3]),           // the shape repeated with a period per axis, 0 for none
+    Twist(Box<Sdf>, f64), // the shape turned around z by the angle in radians per voxel
+}
+
+fn length(p: [f64; 3]) -> f64 {
+    f64::sqrt(p[0] * p[0] + p[1] * p[1] + p[2] * p[2])
+}
+
+impl Sdf {
+    #[doc = "the shape moved by offset"]
+    pub fn translate(self, offset: [f64; 3]) -> Self {
+        Sdf::Translate(Box::new(self), offset)
+    }
+
+    #[doc = "the union of the shape and other"]
+    pub fn union(self, other: Sdf) -> Self {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    #[doc = "the shape without other"]
+    pub fn subtract(self, other: Sdf) -> Self {
+        Sdf::Subtract(Box::new(self), Box::new(other))
+    }
+
+    #[doc = "the intersection of the shape and other"]
+    pub fn intersect(self, other: Sdf) -> Self {
+        Sdf::Intersect(Box::new(self), Box::new(other))
+    }
+
+    #[doc = "the union of the shape and other, blended over the distance k"]
+    pub fn smooth_union(self, other: Sdf, k: f64) -> Self {
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    #[doc = "the shape repeated every period voxels, 0 for no repetition along an axis"]
+    pub fn repeat(self, period: [f64; 3]) -> Self {
+        Sdf::Repeat(Box::new(self), period)
+    }
+
+    #[doc = "the shape twisted around z by rate radians per voxel"]
+    pub fn twist(self, rate: f64) -> Self {
+        Sdf::Twist(Box::new(self), rate)
+    }
+
+    #[doc = "the signed distance of p to the shape. it never over-estimates the distance, \
+             the twist is divided by its largest stretch within the distance"]
+    pub fn distance(&self, p: [f64; 3]) -> f64 {
+        match self {
+            Sdf::Sphere(r) => length(p) - r,
+            Sdf::Box(half) => {
+                let q = [0, 1, 2].map(|i| p[i].abs() - half[i]);
+                let outside = length(q.map(|v| v.max(0.0)));
+                outside + f64::min(q[0].max(q[1]).max(q[2]), 0.0)
+            }
+            Sdf::Cylinder(r, half_height) => {
+                let d = [f64::hypot(p[0], p[1]) - r, p[2].abs() - half_height];
+                f64::min(d[0].max(d[1]), 0.0) + f64::hypot(d[0].max(0.0), d[1].max(0.0))
+            }
+            Sdf::Torus(major, minor) => f64::hypot(f64::hypot(p[0], p[1]) - major, p[2]) - minor,
+            Sdf::Capsule(a, b, r) => {
+                let pa = [0, 1, 2].map(|i| p[i] - a[i]);
+                let ba = [0, 1, 2].map(|i| b[i] - a[i]);
+                let dot = |u: [f64; 3], v: [f64; 3]| u[0] * v[0] + u[1] * v[1] + u[2] * v[2];
+                let len2 = dot(ba, ba);
+                let h = if len2 > 0.0 {
+                    f64::clamp(dot(pa, ba) / len2, 0.0, 1.0)
+                } else {
+                    0.0
+                };
+                length([0, 1, 2].map(|i| pa[i] - ba[i] * h)) - r
+            }
+            Sdf::Plane(n) => p[0] * n[0] + p[1] * n[1] + p[2] * n[2],
+            Sdf::Translate(s, o) => s.distance([0, 1, 2].map(|i| p[i] - o[i])),
+            Sdf::Union(a, b) => f64::min(a.distance(p), b.distance(p)),
+            Sdf::Subtract(a, b) => f64::max(a.distance(p), -b.distance(p)),
+            Sdf::Intersect(a, b) => f64::max(a.distance(p), b.distance(p)),
+            Sdf::SmoothUnion(a, b, k) => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                if *k <= 0.0 {
+                    return f64::min(da, db);
+                }
+                // the polynomial smooth min, it stays below the min
+                let h = f64::max(k - (da - db).abs(), 0.0) / k;
+                f64::min(da, db) - h * h * k * 0.25
+            }
+            Sdf::Repeat(s, period) => s.distance([0, 1, 2].map(|i| {
+                if period[i] > 0.0 {
+                    p[i] - period[i] * f64::round(p[i] / period[i])
+                } else {
+                    p[i]
+                }
+            })),
+            Sdf::Twist(s, rate) => {
+                let (sin, cos) = f64::sin_cos(-rate * p[2]);
+                let q = [cos * p[0] - sin * p[1], sin * p[0] + cos * p[1], p[2]];
+                // the stretch is taken at the radius of p plus the distance, so it bounds the
+                // stretch over every point the distance can reach
+                let d = s.distance(q);
+                let stretch = rate * (f64::hypot(p[0], p[1]) + d.abs());
+                d / f64::sqrt(1.0 + stretch * stretch)
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "add the voxels of bounds (min and max, both included) whose center has a distance <= 0, \
+             with the color index of color_fn. sdf must not over-estimate the distance: the blocks \
+             further from the surface than their size are skipped or filled at once. \
+             return the count of new voxels"]
+    pub fn fill_sdf<F: Fn([f64; 3]) -> f64, C: Fn([i32; 3]) -> i32>(
+        &mut self,
+        bounds: ([i32; 3], [i32; 3]),
+        sdf: F,
+        color_fn: C,
+    ) -> usize {
+        let (min, max) = bounds;
+        if (0..3).any(|i| min[i] > max[i]) {
+            return 0;
+        }
+        // i64 as the extent of the full i32 range and its power of two do not fit an i32
+        let extent = (0..3)
+            .map(|i| max[i] as i64 - min[i] as i64 + 1)
+            .max()
+            .unwrap_or(1);
+        let mut size: i64 = 1;
+        while size < extent {
+            size *= 2;
+        }
+        self.fill_sdf_block(min.map(i64::from), size, bounds, &sdf, &color_fn)
+    }
+
+    /// fill the block of size^3 voxels from origin, clipped to bounds
+    fn fill_sdf_block<F: Fn([f64; 3]) -> f64, C: Fn([i32; 3]) -> i32>(
+        &mut self,
+        origin: [i64; 3],
+        size: i64,
+        bounds: ([i32; 3], [i32; 3]),
+        sdf: &F,
+        color_fn: &C,
+    ) -> usize {
+        let (min, max) = bounds;
+        let lo = [0, 1, 2].map(|i| i64::max(origin[i], min[i] as i64) as i32);
+        let hi = [0, 1, 2].map(|i| i64::min(origin[i] + size - 1, max[i] as i64) as i32);
+        if (0..3).any(|i| lo[i] > hi[i]) {
+            return 0;
+        }
+        let half = (size - 1) as f64 * 0.5;
+        let d = sdf([0, 1, 2].map(|i| origin[i] as f64 + half));
+        // the distance from the center to the center of a corner voxel
+        let radius = half * f64::sqrt(3.0);
+        if d > radius {
+            return 0;
+        }
+        if size == 1 || d <= -radius {
+            let mut count = 0;
+            for z in lo[2]..=hi[2] {
+                for y in lo[1]..=hi[1] {
+                    for x in lo[0]..=hi[0] {
+                        count += self.draw_voxel([x, y, z], color_fn([x, y, z])) as usize;
+                    }
+                }
+            }
+            return count;
+        }
+        let s = size / 2;
+        let mut count = 0;
+        for k in 0..8 {
+            let child = [0, 1, 2].map(|i| origin[i] + ((k >> i) & 1) * s);
+            count += self.fill_sdf_block(child, s, bounds, sdf, color_fn);
+        }
+        count
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::ShapeFill;
+    use super::*;
+
+    #[test]
+    fn test_fill_sdf() {
+        let mut vox = VoxWriter::create_empty();
+        let sphere = Sdf::Sphere(9.0);
+        let bounds = ([-10; 3], [10; 3]);
+        let count = vox.fill_sdf(bounds, |p| sphere.distance(p), |_| 1);
+        let mut reference = VoxWriter::create_empty();
+        assert_eq!(
+            count,
+            reference.draw_sphere([0; 3], 9.0, 1, ShapeFill::Solid)
+        );
+
+        // the hierarchical fill gives the same voxels as the brute force one
+        let shape = Sdf::Box([6.0, 4.0, 3.0])
+            .subtract(Sdf::Cylinder(2.5, 10.0))
+            .smooth_union(Sdf::Torus(5.0, 1.5).translate([0.0, 0.0, 4.0]), 2.0)
+            .intersect(Sdf::Plane([0.0, 0.0, 1.0]).translate([0.0, 0.0, 4.5]))
+            .twist(0.05)
+            .repeat([40.0, 0.0, 0.0]);
+        let bounds = ([-12, -9, -9], [50, 9, 9]);
+        let mut vox = VoxWriter::create_empty();
+        let count = vox.fill_sdf(bounds, |p| shape.distance(p), |p| 1 + (p[2] > 0) as i32);
+        let mut brute = 0;
+        for z in -9..=9 {
+            for y in -9..=9 {
+                for x in -12..=50 {
+                    if shape.distance([x as f64, y as f64, z as f64]) <= 0.0 {
+                        brute += 1;
+                        assert_eq!(vox.get_voxel(x, y, z), Some(1 + (z > 0) as i32));
+                    }
+                }
+            }
+        }
+        assert_eq!(count, brute);
+        // the hole of the cylinder, and the second copy
+        assert_eq!(vox.get_voxel(0, 0, -2), None);
+        assert!(vox.get_voxel(45, 0, -2).is_some());
+    }
+
+    #[test]
+    fn test_fill_sdf_twist() {
+        // strongly twisted shapes off the axis: the stretch grows with the radius, and a block
+        // center near the axis must not take the small stretch of its own radius
+        let shapes = [0.1, 0.3, 1.0]
+            .map(|rate| {
+                Sdf::Box([1.5, 1.0, 14.0])
+                    .translate([20.0, 0.0, 0.0])
+                    .union(Sdf::Box([1.0, 1.0, 14.0]).translate([0.0, -11.0, 0.0]))
+                    .twist(rate)
+            })
+            .into_iter()
+            .chain([Sdf::Box([2.0; 3]).translate([3.0, 0.0, 0.0]).twist(0.3)]);
+        for shape in shapes {
+            let bounds = ([-31; 3], [32; 3]);
+            let mut vox = VoxWriter::create_empty();
+            let count = vox.fill_sdf(bounds, |p| shape.distance(p), |_| 1);
+            let mut brute = 0;
+            for z in -31..=32 {
+                for y in -31..=32 {
+                    for x in -31..=32 {
+                        if shape.distance([x as f64, y as f64, z as f64]) <= 0.0 {
+                            brute += 1;
+                            assert_eq!(vox.get_voxel(x, y, z), Some(1));
+                        }
+                    }
+                }
+            }
+            assert_eq!(count, brute);
+        }
+    }
+
+    #[test]
+    fn test_fill_sdf_large_bounds() {
+        // the bounds over the full i32 range do not overflow the block sizes
+        let mut vox = VoxWriter::create_empty();
+        let sphere = Sdf::Sphere(3.0).translate([100.0, -50.0, 7.0]);
+        let bounds = ([i32::MIN; 3], [i32::MAX; 3]);
+        let count = vox.fill_sdf(bounds, |p| sphere.distance(p), |_| 1);
+        let mut reference = VoxWriter::create_empty();
+        let expected = reference.draw_sphere([100, -50, 7], 3.0, 1, ShapeFill::Solid);
+        assert_eq!(count, expected);
+        assert_eq!(vox.get_voxel(100, -50, 7), Some(1));
+    }
+}
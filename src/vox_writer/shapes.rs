@@ -0,0 +1,385 @@
+use super::flood::{BitGrid, NEIGHBOURS_6};
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "an axis of the volume"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    /// the coords of d as (u, v, w), with w along the axis
+    fn split(self, d: [i32; 3]) -> (i32, i32, i32) {
+        match self {
+            Axis::X => (d[1], d[2], d[0]),
+            Axis::Y => (d[0], d[2], d[1]),
+            Axis::Z => (d[0], d[1], d[2]),
+        }
+    }
+
+    /// the offset of (u, v, w) in the volume, the reverse of split
+    fn join(self, u: i32, v: i32, w: i32) -> [i32; 3] {
+        match self {
+            Axis::X => [w, u, v],
+            Axis::Y => [u, w, v],
+            Axis::Z => [u, v, w],
+        }
+    }
+}
+
+#[doc = "how a shape is filled"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ShapeFill {
+    Solid,
+    Shell(i32), // only the voxels up to this thickness from the outside
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    /// add the voxel at p if there is none, return true if it was added. the drawing functions
+    /// count only the new voxels, so the voxels drawn twice or already there are not counted
+    pub(crate) fn draw_voxel(&mut self, p: [i32; 3], color_index: i32) -> bool {
+        if self.get_voxel(p[0], p[1], p[2]).is_some() {
+            return false;
+        }
+        self.add_voxel(p[0], p[1], p[2], color_index);
+        true
+    }
+
+    /// add the voxels of min..=max for which inside is true, with the fill. return the count of new
+    /// voxels
+    fn draw_shape<F: Fn([i32; 3]) -> bool>(
+        &mut self,
+        min: [i32; 3],
+        max: [i32; 3],
+        inside: F,
+        color_index: i32,
+        fill: ShapeFill,
+    ) -> usize {
+        if (0..3).any(|i| min[i] > max[i]) {
+            return 0;
+        }
+        let thickness = match fill {
+            ShapeFill::Solid => {
+                let mut count = 0;
+                for z in min[2]..=max[2] {
+                    for y in min[1]..=max[1] {
+                        for x in min[0]..=max[0] {
+                            if inside([x, y, z]) {
+                                count += self.draw_voxel([x, y, z], color_index) as usize;
+                            }
+                        }
+                    }
+                }
+                return count;
+            }
+            ShapeFill::Shell(t) => t,
+        };
+        if thickness < 1 {
+            return 0;
+        }
+        // the grids have a border of empty voxels
+        let lo = min.map(|v| v - 1);
+        let hi = max.map(|v| v + 1);
+        let mut solid = BitGrid::create(lo, hi);
+        for z in min[2]..=max[2] {
+            for y in min[1]..=max[1] {
+                for x in min[0]..=max[0] {
+                    if inside([x, y, z]) {
+                        solid.set([x, y, z], true);
+                    }
+                }
+            }
+        }
+        let add = |p: [i32; 3], d: [i32; 3]| [p[0] + d[0], p[1] + d[1], p[2] + d[2]];
+        // the voxels touching the outside, then the next layers inward
+        let mut kept = BitGrid::create(lo, hi);
+        let mut front = vec![];
+        for z in min[2]..=max[2] {
+            for y in min[1]..=max[1] {
+                for x in min[0]..=max[0] {
+                    let p = [x, y, z];
+                    if solid.get(p) && NEIGHBOURS_6.iter().any(|d| !solid.get(add(p, *d))) {
+                        kept.set(p, true);
+                        front.push(p);
+                    }
+                }
+            }
+        }
+        for _ in 1..thickness {
+            let mut next = vec![];
+            for p in front {
+                for d in NEIGHBOURS_6 {
+                    let n = add(p, d);
+                    if solid.get(n) && !kept.get(n) {
+                        kept.set(n, true);
+                        next.push(n);
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            front = next;
+        }
+        let mut count = 0;
+        for z in min[2]..=max[2] {
+            for y in min[1]..=max[1] {
+                for x in min[0]..=max[0] {
+                    if kept.get([x, y, z]) {
+                        count += self.draw_voxel([x, y, z], color_index) as usize;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// draw a shape around an axis, inside get (u, v, w) with w from 0 to height - 1
+    fn draw_axis_shape<F: Fn(i32, i32, i32) -> bool>(
+        &mut self,
+        base: [i32; 3],
+        axis: Axis,
+        extent: [i32; 2], // radius around the axis, height along the axis
+        inside: F,
+        color_index: i32,
+        fill: ShapeFill,
+    ) -> usize {
+        let [r, h] = extent;
+        let lo = axis.join(-r, -r, 0);
+        let hi = axis.join(r, r, h - 1);
+        let min = [0, 1, 2].map(|i| base[i] + lo[i]);
+        let max = [0, 1, 2].map(|i| base[i] + hi[i]);
+        let shape = |p: [i32; 3]| {
+            let (u, v, w) = axis.split([0, 1, 2].map(|i| p[i] - base[i]));
+            inside(u, v, w)
+        };
+        self.draw_shape(min, max, shape, color_index, fill)
+    }
+
+    #[doc = "draw the box from min to max, both included. return the count of new voxels"]
+    pub fn draw_box(
+        &mut self,
+        min: [i32; 3],
+        max: [i32; 3],
+        color_index: i32,
+        fill: ShapeFill,
+    ) -> usize {
+        self.draw_shape(min, max, |_| true, color_index, fill)
+    }
+
+    #[doc = "draw the voxels whose center is at radius or less of center. return the count of new voxels"]
+    pub fn draw_sphere(
+        &mut self,
+        center: [i32; 3],
+        radius: f64,
+        color_index: i32,
+        fill: ShapeFill,
+    ) -> usize {
+        self.draw_ellipsoid(center, [radius; 3], color_index, fill)
+    }
+
+    #[doc = "draw an ellipsoid of radii along x, y and z. return the count of new voxels"]
+    pub fn draw_ellipsoid(
+        &mut self,
+        center: [i32; 3],
+        radii: [f64; 3],
+        color_index: i32,
+        fill: ShapeFill,
+    ) -> usize {
+        if radii.iter().any(|r| *r <= 0.0) {
+            return 0;
+        }
+        let r = radii.map(|r| f64::floor(r) as i32);
+        let min = [0, 1, 2].map(|i| center[i] - r[i]);
+        let max = [0, 1, 2].map(|i| center[i] + r[i]);
+        let inside = |p: [i32; 3]| {
+            (0..3)
+                .map(|i| {
+                    let d = (p[i] - center[i]) as f64 / radii[i];
+                    d * d
+                })
+                .sum::<f64>()
+                <= 1.0
+        };
+        self.draw_shape(min, max, inside, color_index, fill)
+    }
+
+    #[doc = "draw a cylinder of radius, from base and height voxels along the axis. \
+             the shell keeps the caps. return the count of new voxels"]
+    pub fn draw_cylinder(
+        &mut self,
+        base: [i32; 3],
+        axis: Axis,
+        radius: f64,
+        height: i32,
+        color_index: i32,
+        fill: ShapeFill,
+    ) -> usize {
+        let extent = [f64::floor(radius) as i32, height];
+        let inside = |u: i32, v: i32, _| ((u * u + v * v) as f64) <= radius * radius;
+        self.draw_axis_shape(base, axis, extent, inside, color_index, fill)
+    }
+
+    #[doc = "draw a cone of radius at base, with its tip height - 1 voxels along the axis. \
+             return the count of new voxels"]
+    pub fn draw_cone(
+        &mut self,
+        base: [i32; 3],
+        axis: Axis,
+        radius: f64,
+        height: i32,
+        color_index: i32,
+        fill: ShapeFill,
+    ) -> usize {
+        let extent = [f64::floor(radius) as i32, height];
+        let inside = |u: i32, v: i32, w: i32| {
+            let r = radius * (height - w) as f64 / height as f64;
+            ((u * u + v * v) as f64) <= r * r
+        };
+        self.draw_axis_shape(base, axis, extent, inside, color_index, fill)
+    }
+
+    #[doc = "draw a torus around the axis, major_radius from center to the middle of the tube, \
+             minor_radius for the tube. return the count of new voxels"]
+    pub fn draw_torus(
+        &mut self,
+        center: [i32; 3],
+        axis: Axis,
+        major_radius: f64,
+        minor_radius: f64,
+        color_index: i32,
+        fill: ShapeFill,
+    ) -> usize {
+        let r = f64::floor(major_radius + minor_radius) as i32;
+        let h = f64::floor(minor_radius) as i32;
+        let mut base = center;
+        let (_, _, w) = axis.split([0, 1, 2]);
+        base[w as usize] -= h;
+        let inside = |u: i32, v: i32, w: i32| {
+            let d = f64::sqrt((u * u + v * v) as f64) - major_radius;
+            let w = (w - h) as f64;
+            d * d + w * w <= minor_radius * minor_radius
+        };
+        self.draw_axis_shape(base, axis, [r, 2 * h + 1], inside, color_index, fill)
+    }
+
+    #[doc = "draw a 3d bresenham line from start to end, both included. return the count of new voxels"]
+    pub fn draw_line(&mut self, start: [i32; 3], end: [i32; 3], color_index: i32) -> usize {
+        let d = [0, 1, 2].map(|i| (end[i] - start[i]).abs());
+        let s = [0, 1, 2].map(|i| (end[i] - start[i]).signum());
+        // the main axis moves at each step, the two others when their error is positive
+        let m = if d[0] >= d[1] && d[0] >= d[2] {
+            0
+        } else if d[1] >= d[2] {
+            1
+        } else {
+            2
+        };
+        let (a, b) = ((m + 1) % 3, (m + 2) % 3);
+        let mut err_a = 2 * d[a] - d[m];
+        let mut err_b = 2 * d[b] - d[m];
+        let mut p = start;
+        let mut count = 0;
+        for _ in 0..d[m] {
+            count += self.draw_voxel(p, color_index) as usize;
+            if err_a > 0 {
+                p[a] += s[a];
+                err_a -= 2 * d[m];
+            }
+            if err_b > 0 {
+                p[b] += s[b];
+                err_b -= 2 * d[m];
+            }
+            err_a += 2 * d[a];
+            err_b += 2 * d[b];
+            p[m] += s[m];
+        }
+        count + self.draw_voxel(end, color_index) as usize
+    }
+
+    #[doc = "draw the lines between the successive points. return the count of new voxels"]
+    pub fn draw_polyline(&mut self, points: &[[i32; 3]], color_index: i32) -> usize {
+        match points {
+            [p] => self.draw_line(*p, *p, color_index),
+            // the joints shared by two lines are counted once, as the other voxels drawn twice
+            _ => points
+                .windows(2)
+                .map(|w| self.draw_line(w[0], w[1], color_index))
+                .sum(),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shells() {
+        let mut vox = VoxWriter::create_empty();
+        // a 5x5x5 box without its 3x3x3 center, across the cubes of 126 voxels
+        assert_eq!(
+            vox.draw_box([124; 3], [128; 3], 1, ShapeFill::Shell(1)),
+            125 - 27
+        );
+        assert_eq!(vox.get_voxel(126, 126, 126), None);
+        assert_eq!(vox.get_voxel(124, 126, 126), Some(1));
+        assert_eq!(vox.draw_box([0; 3], [4; 3], 1, ShapeFill::Shell(2)), 124);
+
+        // the radius 1 sphere is a 3d cross
+        assert_eq!(vox.draw_sphere([20, 0, 0], 1.0, 2, ShapeFill::Solid), 7);
+        let solid = vox.draw_cylinder([40, 0, 0], Axis::Y, 3.0, 4, 3, ShapeFill::Solid);
+        assert_eq!(solid, 29 * 4);
+        // the caps are kept
+        let shell = vox.draw_cylinder([60, 0, 0], Axis::Y, 3.0, 4, 3, ShapeFill::Shell(1));
+        assert_eq!(shell, 29 * 2 + 16 * 2);
+        assert_eq!(vox.get_voxel(60, 3, 3), Some(3));
+
+        let torus = vox.draw_torus([80, 0, 0], Axis::Z, 4.0, 1.0, 4, ShapeFill::Solid);
+        assert!(torus > 0);
+        assert_eq!(vox.get_voxel(80, 0, 0), None);
+        assert_eq!(vox.get_voxel(84, 0, 0), Some(4));
+        assert_eq!(vox.get_voxel(84, 0, 1), Some(4));
+
+        // only the new voxels are counted, the others keep their color
+        assert_eq!(vox.draw_box([0; 3], [4; 3], 5, ShapeFill::Solid), 1);
+        assert_eq!(vox.get_voxel(0, 0, 0), Some(1));
+        assert_eq!(vox.get_voxel(2, 2, 2), Some(5));
+        assert_eq!(vox.draw_sphere([20, 0, 0], 1.0, 6, ShapeFill::Shell(1)), 0);
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut vox = VoxWriter::create_empty();
+        assert_eq!(vox.draw_line([0, 0, 0], [6, -3, 2], 1), 7);
+        assert_eq!(vox.get_voxel(6, -3, 2), Some(1));
+        assert_eq!(vox.get_voxel(2, -1, 1), Some(1));
+        assert_eq!(
+            vox.draw_polyline(&[[0, 10, 0], [0, 10, 4], [4, 10, 4]], 2),
+            9
+        );
+        assert_eq!(vox.count_voxels(), 7 + 9);
+
+        // a closed polyline crossing itself, and a line over the voxels already there
+        let square = [
+            [0, 20, 0],
+            [4, 20, 0],
+            [4, 24, 0],
+            [0, 24, 0],
+            [0, 20, 0],
+            [4, 24, 0],
+        ];
+        assert_eq!(vox.draw_polyline(&square, 3), 16 + 3);
+        assert_eq!(vox.draw_line([0, 0, 0], [6, -3, 2], 4), 0);
+        assert_eq!(vox.get_voxel(6, -3, 2), Some(1));
+        assert_eq!(vox.count_voxels(), 7 + 9 + 16 + 3);
+    }
+}
@@ -0,0 +1,248 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+use super::png::{decode_png, encode_png_rgba};
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "how the pixels of a slice image become voxels"]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SliceMapping {
+    // the pixels with a luminance >= threshold get color_index
+    Threshold { threshold: u8, color_index: i32 },
+    // the pixels with a luminance >= threshold keep their color, mapped to the palette
+    Palette { threshold: u8 },
+}
+
+fn luminance(c: [u8; 4]) -> u8 {
+    (0.299 * c[0] as f64 + 0.587 * c[1] as f64 + 0.114 * c[2] as f64).round() as u8
+}
+
+/// compare the names with their runs of digits as numbers, so slice_2 comes before slice_10.
+/// the names equal as numbers (a01 and a1) are ordered by their bytes
+fn natural_cmp(name_a: &str, name_b: &str) -> Ordering {
+    let (mut a, mut b) = (name_a.as_bytes(), name_b.as_bytes());
+    while let (Some(ca), Some(cb)) = (a.first(), b.first()) {
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let na = a.iter().take_while(|c| c.is_ascii_digit()).count();
+            let nb = b.iter().take_while(|c| c.is_ascii_digit()).count();
+            // without the leading zeros, the longest run is the biggest number
+            let da = &a[..na][a[..na].iter().take_while(|c| **c == b'0').count()..];
+            let db = &b[..nb][b[..nb].iter().take_while(|c| **c == b'0').count()..];
+            let ord = da.len().cmp(&db.len()).then(da.cmp(db));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            a = &a[na..];
+            b = &b[nb..];
+        } else {
+            if ca != cb {
+                return ca.cmp(cb);
+            }
+            a = &a[1..];
+            b = &b[1..];
+        }
+    }
+    a.len().cmp(&b.len()).then(name_a.cmp(name_b))
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "add the voxels of a png slice at the height z. the top of the image is at the max y. \
+             the transparent pixels are always empty. return the count of voxels added"]
+    pub fn add_png_slice(
+        &mut self,
+        png_data: &[u8],
+        z: i32,
+        mapping: &SliceMapping,
+    ) -> std::io::Result<usize> {
+        let img = decode_png(png_data)?;
+        let mut voxels: Vec<([i32; 3], [u8; 3])> = vec![];
+        for y in 0..img.height {
+            for x in 0..img.width {
+                let c = img.get_rgba(x, y);
+                let threshold = match mapping {
+                    SliceMapping::Threshold { threshold, .. } => *threshold,
+                    SliceMapping::Palette { threshold } => *threshold,
+                };
+                if c[3] < 128 || luminance(c) < threshold {
+                    continue;
+                }
+                let pos = [x as i32, (img.height - 1 - y) as i32, z];
+                voxels.push((pos, [c[0], c[1], c[2]]));
+            }
+        }
+        match mapping {
+            SliceMapping::Threshold { color_index, .. } => {
+                for (p, _) in &voxels {
+                    self.add_voxel(p[0], p[1], p[2], *color_index);
+                }
+            }
+            SliceMapping::Palette { .. } => self.add_voxels_rgb(&voxels),
+        }
+        Ok(voxels.len())
+    }
+
+    #[doc = "add the png slices of a directory, sorted by file name with the numbers in the names \
+             compared by value (slice_2 before slice_10), the first one at z = 0. \
+             return the count of slices read"]
+    pub fn add_image_slices(
+        &mut self,
+        dir_path: String,
+        mapping: &SliceMapping,
+    ) -> std::io::Result<usize> {
+        let mut files: Vec<_> = fs::read_dir(dir_path)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("png"))
+            })
+            .collect();
+        files.sort_by(|a, b| {
+            let a = a.file_name().unwrap_or_default().to_string_lossy();
+            let b = b.file_name().unwrap_or_default().to_string_lossy();
+            natural_cmp(&a, &b)
+        });
+        for (z, file) in files.iter().enumerate() {
+            let data = fs::read(file)?;
+            self.add_png_slice(&data, z as i32, mapping)?;
+        }
+        Ok(files.len())
+    }
+
+    #[doc = "get the png of the slice at the height z, over the x,y bounds of the volume. \
+             the empty voxels are transparent"]
+    pub fn get_png_slice(&self, z: i32) -> Vec<u8> {
+        let (min, max) = self.get_bounds().unwrap_or(([0; 3], [0; 3]));
+        let width = (max[0] - min[0] + 1) as usize;
+        let height = (max[1] - min[1] + 1) as usize;
+        let mut pixels = vec![];
+        self.for_each_voxel(|x, y, vz, c| {
+            if vz == z {
+                pixels.push((
+                    ((max[1] - y) as usize * width + (x - min[0]) as usize),
+                    c as u8,
+                ));
+            }
+        });
+        encode_png_rgba(width, height, &self.get_slice_rgba(width, height, &pixels))
+    }
+
+    #[doc = "save each z slice of the volume as slice_NNNN.png in dir_path. return the count of files written"]
+    pub fn save_slices_to_dir(&self, dir_path: String) -> std::io::Result<usize> {
+        let (min, max) = match self.get_bounds() {
+            Some(b) => b,
+            None => return Ok(0),
+        };
+        fs::create_dir_all(&dir_path)?;
+        let width = (max[0] - min[0] + 1) as usize;
+        let height = (max[1] - min[1] + 1) as usize;
+        // the voxels are bucketed by z, only one image is in memory at a time
+        let mut slices: Vec<Vec<(usize, u8)>> = vec![vec![]; (max[2] - min[2] + 1) as usize];
+        self.for_each_voxel(|x, y, z, c| {
+            let p = (max[1] - y) as usize * width + (x - min[0]) as usize;
+            slices[(z - min[2]) as usize].push((p, c as u8));
+        });
+        for (i, pixels) in slices.iter_mut().enumerate() {
+            let rgba = self.get_slice_rgba(width, height, pixels);
+            *pixels = vec![];
+            let file = Path::new(&dir_path).join(format!("slice_{:04}.png", i));
+            fs::write(file, encode_png_rgba(width, height, &rgba))?;
+        }
+        Ok(slices.len())
+    }
+
+    /// the rgba image of a slice from its pixels, the pixel index and the color index of the voxels
+    fn get_slice_rgba(&self, width: usize, height: usize, pixels: &[(usize, u8)]) -> Vec<u8> {
+        let mut rgba = vec![0u8; width * height * 4];
+        for (p, c) in pixels {
+            rgba[p * 4..p * 4 + 4].copy_from_slice(&self.get_palette_color(*c));
+            rgba[p * 4 + 3] = 255;
+        }
+        rgba
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_round_trip() {
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel_rgb(0, 0, 0, 255, 0, 0);
+        vox.add_voxel_rgb(2, 1, 0, 0, 0, 255);
+        vox.add_voxel_rgb(1, 1, 1, 0, 255, 0);
+        let png = vox.get_png_slice(0);
+
+        let mut copy = VoxWriter::create_empty();
+        let count = copy
+            .add_png_slice(&png, 5, &SliceMapping::Palette { threshold: 0 })
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(copy.get_voxel(0, 0, 5), Some(2));
+        assert_eq!(copy.get_palette_color(2), [255, 0, 0, 255]);
+        assert_eq!(copy.get_voxel(2, 1, 5), Some(1));
+
+        let mut mask = VoxWriter::create_empty();
+        let mapping = SliceMapping::Threshold {
+            threshold: 50,
+            color_index: 9,
+        };
+        // the luminance of the pure blue is under the threshold
+        assert_eq!(mask.add_png_slice(&png, 0, &mapping).unwrap(), 1);
+        assert_eq!(mask.get_voxel(0, 0, 0), Some(9));
+    }
+
+    #[test]
+    fn test_save_slices_to_dir() {
+        let mut vox = VoxWriter::create_empty();
+        vox.add_voxel(0, 0, -2, 3);
+        vox.add_voxel(1, 1, 0, 4);
+        let dir = std::env::temp_dir().join("vox_writer_test_slices");
+        let _ = fs::remove_dir_all(&dir);
+        let dir = dir.to_string_lossy().to_string();
+        assert_eq!(vox.save_slices_to_dir(dir.clone()).unwrap(), 3);
+
+        let mut copy = VoxWriter::create_empty();
+        let mapping = SliceMapping::Threshold {
+            threshold: 0,
+            color_index: 1,
+        };
+        assert_eq!(copy.add_image_slices(dir.clone(), &mapping).unwrap(), 3);
+        assert_eq!(copy.count_voxels(), 2);
+        assert_eq!(copy.get_voxel(0, 0, 0), Some(1));
+        assert_eq!(copy.get_voxel(1, 1, 2), Some(1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec![
+            "slice_10.png",
+            "slice_2.png",
+            "slice_002b.png",
+            "slice_1.png",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec![
+                "slice_1.png",
+                "slice_2.png",
+                "slice_002b.png",
+                "slice_10.png"
+            ]
+        );
+        assert_eq!(natural_cmp("a01", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("a1", "a01"), Ordering::Greater);
+        assert_eq!(natural_cmp("a1", "a1"), Ordering::Equal);
+        assert_eq!(natural_cmp("a", "a0"), Ordering::Less);
+    }
+}
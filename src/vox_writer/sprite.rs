@@ -0,0 +1,177 @@
+use super::png::decode_png;
+use super::VoxWriter;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[doc = "the options of the extrusion of an image into voxels"]
+#[derive(Clone, Copy, Debug)]
+pub struct SpriteOptions {
+    pub depth: i32,          // thickness in voxels of the edges
+    pub inflation: i32,      // extra voxels on the front and on the back at the full inflation
+    pub bevel_width: f64,    // pixels from the edges to the full inflation, 0 for up to the middle
+    pub round: bool,         // a round profile instead of a straight bevel
+    pub alpha_threshold: u8, // the pixels with a lower alpha are empty
+}
+
+impl SpriteOptions {
+    #[doc = "a flat extrusion of depth voxels"]
+    pub fn create(depth: i32) -> Self {
+        Self {
+            depth,
+            inflation: 0,
+            bevel_width: 0.0,
+            round: false,
+            alpha_threshold: 128,
+        }
+    }
+}
+
+/// distance of each opaque pixel to the nearest empty pixel or to the border, with a 3-4 chamfer
+fn edge_distances(width: usize, height: usize, opaque: &[bool]) -> Vec<f64> {
+    const FAR: i32 = i32::MAX / 2;
+    let mut d: Vec<i32> = opaque.iter().map(|o| if *o { FAR } else { 0 }).collect();
+    let get = |d: &[i32], x: i64, y: i64| -> i32 {
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            0 // outside is empty
+        } else {
+            d[y as usize * width + x as usize]
+        }
+    };
+    let forward = [(-1, 0, 3), (0, -1, 3), (-1, -1, 4), (1, -1, 4)];
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let i = y as usize * width + x as usize;
+            for (dx, dy, w) in forward {
+                d[i] = i32::min(d[i], get(&d, x + dx, y + dy) + w);
+            }
+        }
+    }
+    for y in (0..height as i64).rev() {
+        for x in (0..width as i64).rev() {
+            let i = y as usize * width + x as usize;
+            for (dx, dy, w) in forward {
+                d[i] = i32::min(d[i], get(&d, x - dx, y - dy) + w);
+            }
+        }
+    }
+    d.iter().map(|v| *v as f64 / 3.0).collect()
+}
+
+/// the voxels of an extruded rgba image, the image is in the x,z plane with its top at the max z,
+/// and the extrusion goes along y from 0 to depth - 1, plus the inflation on both sides
+pub(crate) fn sprite_voxels(
+    width: usize,
+    height: usize,
+    rgba: &[u8],
+    options: &SpriteOptions,
+) -> Vec<([i32; 3], [u8; 3])> {
+    let opaque: Vec<bool> = (0..width * height)
+        .map(|i| rgba[i * 4 + 3] >= options.alpha_threshold)
+        .collect();
+    let dist = edge_distances(width, height, &opaque);
+    let ramp = if options.bevel_width > 0.0 {
+        options.bevel_width
+    } else {
+        dist.iter().copied().fold(0.0, f64::max)
+    };
+    let mut voxels = vec![];
+    for py in 0..height {
+        for px in 0..width {
+            let i = py * width + px;
+            if !opaque[i] {
+                continue;
+            }
+            let extra = if options.inflation > 0 && ramp > 0.0 {
+                // the edge pixels are at the distance 1
+                let t = f64::clamp((dist[i] - 1.0) / f64::max(ramp - 1.0, 1.0), 0.0, 1.0);
+                let t = if options.round {
+                    f64::sqrt(1.0 - (1.0 - t) * (1.0 - t))
+                } else {
+                    t
+                };
+                f64::round(t * options.inflation as f64) as i32
+            } else {
+                0
+            };
+            let c = [rgba[i * 4], rgba[i * 4 + 1], rgba[i * 4 + 2]];
+            let z = (height - 1 - py) as i32;
+            for y in -extra..options.depth + extra {
+                voxels.push(([px as i32, y, z], c));
+            }
+        }
+    }
+    voxels
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "extrude a rgba image into voxels at origin. the image stands in the x,z plane with its top \
+             at the max z, the depth goes along y. the colors are mapped to the palette, quantized if \
+             there is not enough free colors. return the count of voxels added"]
+    pub fn add_sprite_rgba(
+        &mut self,
+        width: usize,
+        height: usize,
+        rgba: &[u8],
+        origin: [i32; 3],
+        options: &SpriteOptions,
+    ) -> usize {
+        let mut voxels = sprite_voxels(width, height, rgba, options);
+        for (p, _) in voxels.iter_mut() {
+            for i in 0..3 {
+                p[i] += origin[i];
+            }
+        }
+        self.add_voxels_rgb(&voxels);
+        voxels.len()
+    }
+
+    #[doc = "extrude a png image into voxels at origin, see add_sprite_rgba"]
+    pub fn add_sprite_png(
+        &mut self,
+        png_data: &[u8],
+        origin: [i32; 3],
+        options: &SpriteOptions,
+    ) -> std::io::Result<usize> {
+        let img = decode_png(png_data)?;
+        let mut rgba = Vec::with_capacity(img.width * img.height * 4);
+        for y in 0..img.height {
+            for x in 0..img.width {
+                rgba.extend_from_slice(&img.get_rgba(x, y));
+            }
+        }
+        Ok(self.add_sprite_rgba(img.width, img.height, &rgba, origin, options))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sprite_inflation() {
+        // a 5x5 opaque square, with a transparent corner
+        let mut rgba = vec![255u8; 5 * 5 * 4];
+        rgba[3] = 0;
+        let mut options = SpriteOptions::create(2);
+        let voxels = sprite_voxels(5, 5, &rgba, &options);
+        assert_eq!(voxels.len(), 24 * 2);
+
+        options.inflation = 2;
+        let mut vox = VoxWriter::create_empty();
+        vox.add_sprite_rgba(5, 5, &rgba, [10, 0, 0], &options);
+        // the edges keep the depth, the center get the full inflation on both sides
+        assert_eq!(vox.get_voxel(10, -1, 0), None);
+        assert!(vox.get_voxel(12, -2, 2).is_some());
+        assert!(vox.get_voxel(12, 3, 2).is_some());
+        // the transparent pixel is the top left
+        assert_eq!(vox.get_voxel(10, 0, 4), None);
+        assert_eq!(
+            vox.get_palette_color(vox.get_voxel(11, 0, 4).unwrap() as u8),
+            [255; 4]
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use super::{invalid_data, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+// the 5x7 glyphs of the ascii 32 to 126, one byte per column from the left, the bit 0 is the top row
+const FONT_5X7: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x08, 0x2a, 0x1c, 0x2a, 0x08], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // backslash
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
+
+#[doc = "a glyph of a bitmap font. the bottom of the bitmap is y_offset pixels above the baseline"]
+#[derive(Clone, Debug)]
+pub struct BitmapGlyph {
+    pub width: i32,
+    pub height: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub advance: i32,
+    pub bits: Vec<bool>, // width x height, rows from the top
+}
+
+#[doc = "a bitmap font, the built-in 5x7 one or a bdf font"]
+#[derive(Clone, Debug)]
+pub struct BitmapFont {
+    pub line_height: i32,
+    pub glyphs: HashMap<char, BitmapGlyph>,
+}
+
+impl BitmapFont {
+    #[doc = "the built-in 5x7 font of the printable ascii chars"]
+    pub fn create_default() -> Self {
+        let mut glyphs = HashMap::new();
+        for (i, columns) in FONT_5X7.iter().enumerate() {
+            let mut bits = vec![false; 5 * 7];
+            for (x, column) in columns.iter().enumerate() {
+                for y in 0..7 {
+                    bits[y * 5 + x] = column & (1 << y) != 0;
+                }
+            }
+            let glyph = BitmapGlyph {
+                width: 5,
+                height: 7,
+                x_offset: 0,
+                y_offset: 0,
+                advance: 6,
+                bits,
+            };
+            glyphs.insert((b' ' + i as u8) as char, glyph);
+        }
+        Self {
+            line_height: 8,
+            glyphs,
+        }
+    }
+
+    #[doc = "load a bdf font"]
+    pub fn load_bdf(file_path_name: String) -> std::io::Result<Self> {
+        let file = File::open(file_path_name)?;
+        Self::read_bdf(BufReader::new(file))
+    }
+
+    #[doc = "read a bdf font. the glyphs are keyed by their unicode encoding"]
+    pub fn read_bdf<R: BufRead>(reader: R) -> std::io::Result<Self> {
+        let mut line_height = 0;
+        let mut glyphs = HashMap::new();
+        let mut encoding: Option<char> = None;
+        let mut advance = 0;
+        let mut bbx = [0i32; 4];
+        let mut bitmap: Option<Vec<bool>> = None;
+        let parse = |words: &[&str], i: usize| -> std::io::Result<i32> {
+            words
+                .get(i)
+                .and_then(|w| w.parse::<i32>().ok())
+                .ok_or_else(|| invalid_data("bdf: bad value"))
+        };
+        for line in reader.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let Some(bits) = bitmap.as_mut() {
+                if words.first() == Some(&"ENDCHAR") {
+                    if let Some(c) = encoding {
+                        glyphs.insert(
+                            c,
+                            BitmapGlyph {
+                                width: bbx[0],
+                                height: bbx[1],
+                                x_offset: bbx[2],
+                                y_offset: bbx[3],
+                                advance,
+                                bits: std::mem::take(bits),
+                            },
+                        );
+                    }
+                    bitmap = None;
+                    continue;
+                }
+                // a row in hex, the first pixel is the high bit
+                let row: Vec<u8> = line
+                    .trim()
+                    .as_bytes()
+                    .chunks_exact(2)
+                    .map(|h| {
+                        std::str::from_utf8(h)
+                            .ok()
+                            .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    })
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| invalid_data("bdf: bad bitmap"))?;
+                for x in 0..bbx[0] as usize {
+                    let byte = row.get(x / 8).copied().unwrap_or(0);
+                    bits.push(byte & (0x80 >> (x % 8)) != 0);
+                }
+                continue;
+            }
+            match words.first().copied() {
+                Some("FONTBOUNDINGBOX") => line_height = parse(&words, 2)?,
+                Some("STARTCHAR") => {
+                    encoding = None;
+                    advance = 0;
+                    bbx = [0; 4];
+                }
+                Some("ENCODING") => {
+                    encoding = u32::try_from(parse(&words, 1)?)
+                        .ok()
+                        .and_then(char::from_u32);
+                }
+                Some("DWIDTH") => advance = parse(&words, 1)?,
+                Some("BBX") => {
+                    bbx = [
+                        parse(&words, 1)?,
+                        parse(&words, 2)?,
+                        parse(&words, 3)?,
+                        parse(&words, 4)?,
+                    ];
+                    if bbx[0] < 0 || bbx[1] < 0 || bbx[0] > 1024 || bbx[1] > 1024 {
+                        return Err(invalid_data("bdf: bad glyph size"));
+                    }
+                }
+                Some("BITMAP") => bitmap = Some(vec![]),
+                _ => {}
+            }
+        }
+        for g in glyphs.values_mut() {
+            // the missing rows are empty
+            g.bits.resize((g.width * g.height) as usize, false);
+        }
+        Ok(Self {
+            line_height,
+            glyphs,
+        })
+    }
+
+    #[doc = "get the width in pixels of the longest line of a text"]
+    pub fn get_text_width(&self, text: &str) -> i32 {
+        text.split('\n')
+            .map(|line| {
+                line.chars()
+                    .map(|c| self.glyphs.get(&c).map_or(0, |g| g.advance))
+                    .sum::<i32>()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl VoxWriter {
+    #[doc = "draw a text with the built-in 5x7 font, see draw_text_with_font"]
+    pub fn draw_text(
+        &mut self,
+        text: &str,
+        origin: [i32; 3],
+        direction: [i32; 3],
+        color_index: i32,
+        depth: i32,
+    ) -> usize {
+        let font = BitmapFont::create_default();
+        self.draw_text_with_font(&font, text, origin, direction, color_index, depth)
+    }
+
+    #[doc = "draw a text from origin, the left of the baseline of the first line. direction is the axis \
+             of the writing, like [1, 0, 0], nothing is drawn if it is not along one axis. the up of the \
+             letters is z, or y if the text goes along z. the letters are depth voxels thick, away from \
+             the reader. return the count of new voxels, the voxels already there keep their color"]
+    pub fn draw_text_with_font(
+        &mut self,
+        font: &BitmapFont,
+        text: &str,
+        origin: [i32; 3],
+        direction: [i32; 3],
+        color_index: i32,
+        depth: i32,
+    ) -> usize {
+        if direction.iter().filter(|v| **v != 0).count() != 1 {
+            return 0;
+        }
+        let dir = direction.map(|v| v.signum());
+        let up = if dir[2] != 0 { [0, 1, 0] } else { [0, 0, 1] };
+        // the reader looks along the depth axis, so the direction is at its right
+        let back = [
+            up[1] * dir[2] - up[2] * dir[1],
+            up[2] * dir[0] - up[0] * dir[2],
+            up[0] * dir[1] - up[1] * dir[0],
+        ];
+        let mut count = 0;
+        for (l, line) in text.split('\n').enumerate() {
+            let mut pen = 0;
+            let baseline = -(l as i32) * font.line_height;
+            for c in line.chars() {
+                let glyph = match font.glyphs.get(&c) {
+                    Some(g) => g,
+                    None => continue,
+                };
+                for gy in 0..glyph.height {
+                    for gx in 0..glyph.width {
+                        if !glyph.bits[(gy * glyph.width + gx) as usize] {
+                            continue;
+                        }
+                        let u = pen + glyph.x_offset + gx;
+                        let v = baseline + glyph.y_offset + glyph.height - 1 - gy;
+                        for d in 0..depth {
+                            let p =
+                                [0, 1, 2].map(|i| origin[i] + dir[i] * u + up[i] * v + back[i] * d);
+                            if self.get_voxel(p[0], p[1], p[2]).is_none() {
+                                self.add_voxel(p[0], p[1], p[2], color_index);
+                                count += 1;
+                            }
+                        }
+                    }
+                }
+                pen += glyph.advance;
+            }
+        }
+        count
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_draw_text() {
+        let mut vox = VoxWriter::create_empty();
+        // the I is 3 + 5 + 3 pixels, the - is 5 pixels
+        assert_eq!(
+            vox.draw_text("I\n-", [0, 0, 0], [1, 0, 0], 3, 2),
+            (11 + 5) * 2
+        );
+        // the top bar of the I, the depth goes along y
+        assert_eq!(vox.get_voxel(1, 0, 6), Some(3));
+        assert_eq!(vox.get_voxel(1, 1, 6), Some(3));
+        assert_eq!(vox.get_voxel(0, 0, 6), None);
+        // the - is on the second line, 8 voxels below
+        assert_eq!(vox.get_voxel(0, 0, 3 - 8), Some(3));
+        assert_eq!(BitmapFont::create_default().get_text_width("ab\nc"), 12);
+        // the voxels already there are not counted
+        assert_eq!(vox.draw_text("I", [0, 0, 0], [1, 0, 0], 4, 2), 0);
+        assert_eq!(vox.draw_text("I", [0, 0, 0], [1, 0, 0], 4, 3), 11);
+        // the directions along several axes are rejected
+        assert_eq!(vox.draw_text("I", [0, 50, 0], [1, 1, 0], 4, 1), 0);
+        assert_eq!(vox.draw_text("I", [0, 50, 0], [0, 0, 0], 4, 1), 0);
+        assert_eq!(vox.draw_text("I", [0, 50, 0], [0, -3, 0], 4, 1), 11);
+    }
+
+    #[test]
+    fn test_read_bdf() {
+        let bdf = "STARTFONT 2.1\nFONTBOUNDINGBOX 9 10 0 -2\nCHARS 1\n\
+                   STARTCHAR A\nENCODING 65\nDWIDTH 10 0\nBBX 9 2 0 -1\nBITMAP\nFF80\n8000\nENDCHAR\n\
+                   ENDFONT\n";
+        let font = BitmapFont::read_bdf(Cursor::new(bdf)).unwrap();
+        assert_eq!(font.line_height, 10);
+        let g = &font.glyphs[&'A'];
+        assert_eq!((g.width, g.height, g.y_offset, g.advance), (9, 2, -1, 10));
+        assert!(g.bits[0..9].iter().all(|b| *b));
+        assert_eq!(g.bits[9..].iter().filter(|b| **b).count(), 1);
+
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_text_with_font(&font, "A", [0, 0, 0], [0, 1, 0], 1, 1);
+        // along y, the depth goes along -x
+        assert_eq!(vox.get_voxel(0, 8, 0), Some(1));
+        assert_eq!(vox.get_voxel(0, 0, -1), Some(1));
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Axis, VoxWriter};
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// the index of the coord along the axis, and of the two others in the direct order
+fn get_axes(axis: Axis) -> (usize, usize, usize) {
+    match axis {
+        Axis::X => (0, 1, 2),
+        Axis::Y => (1, 2, 0),
+        Axis::Z => (2, 0, 1),
+    }
+}
+
+impl VoxWriter {
+    /// replace each voxel by f(pos), then make the cubes again
+    fn transform_voxels<F: Fn([i32; 3]) -> [i32; 3]>(&mut self, f: F) {
+        let voxels: Vec<([i32; 3], u8)> = self
+            .get_raw_voxels([0; 3])
+            .into_iter()
+            .map(|(p, c)| (f(p), c))
+            .collect();
+        self.rebuild(&voxels);
+    }
+
+    #[doc = "rotate the main volume by turns of 90 degrees around the axis, counterclockwise when the \
+             axis points to the viewer. the min corner of the bounds stays in place"]
+    pub fn rotate_90(&mut self, axis: Axis, turns: i32) {
+        let (min, max) = match self.get_bounds() {
+            Some(b) => b,
+            None => return,
+        };
+        let (_, u, v) = get_axes(axis);
+        let turns = turns.rem_euclid(4);
+        if turns == 0 {
+            return;
+        }
+        self.transform_voxels(|p| {
+            let (a, b) = (p[u] - min[u], p[v] - min[v]);
+            let (su, sv) = (max[u] - min[u], max[v] - min[v]);
+            let (a, b) = match turns {
+                1 => (sv - b, a),
+                2 => (su - a, sv - b),
+                _ => (b, su - a),
+            };
+            let mut q = p;
+            q[u] = min[u] + a;
+            q[v] = min[v] + b;
+            q
+        });
+    }
+
+    #[doc = "mirror the main volume along the axis, inside its bounds"]
+    pub fn mirror(&mut self, axis: Axis) {
+        let (min, max) = match self.get_bounds() {
+            Some(b) => b,
+            None => return,
+        };
+        let (w, _, _) = get_axes(axis);
+        self.transform_voxels(|mut p| {
+            p[w] = min[w] + max[w] - p[w];
+            p
+        });
+    }
+
+    #[doc = "move all the voxels of the main volume by offset"]
+    pub fn translate(&mut self, offset: [i32; 3]) {
+        self.transform_voxels(|p| [p[0] + offset[0], p[1] + offset[1], p[2] + offset[2]]);
+    }
+
+    #[doc = "remove the voxels of the main volume outside of min to max, both included. \
+             return the count of voxels removed"]
+    pub fn crop(&mut self, min: [i32; 3], max: [i32; 3]) -> usize {
+        let voxels: Vec<([i32; 3], u8)> = self
+            .get_raw_voxels([0; 3])
+            .into_iter()
+            .filter(|(p, _)| (0..3).all(|i| p[i] >= min[i] && p[i] <= max[i]))
+            .collect();
+        let removed = self.count_voxels() as usize - voxels.len();
+        self.rebuild(&voxels);
+        removed
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_mirror() {
+        let mut vox = VoxWriter::create_empty();
+        // an L in the x,y plane, from 10,20,0
+        vox.draw_line([10, 20, 0], [13, 20, 0], 1);
+        vox.add_voxel(10, 21, 0, 2);
+        vox.rotate_90(Axis::Z, 1);
+        assert_eq!(vox.get_bounds(), Some(([10, 20, 0], [11, 23, 0])));
+        assert_eq!(vox.get_voxel(11, 23, 0), Some(1));
+        assert_eq!(vox.get_voxel(10, 20, 0), Some(2));
+        vox.rotate_90(Axis::Z, -1);
+        assert_eq!(vox.get_voxel(13, 20, 0), Some(1));
+        assert_eq!(vox.get_voxel(10, 21, 0), Some(2));
+        vox.rotate_90(Axis::X, 2);
+        assert_eq!(vox.get_voxel(10, 20, 0), Some(2));
+        assert_eq!(vox.get_voxel(13, 21, 0), Some(1));
+        vox.mirror(Axis::X);
+        assert_eq!(vox.get_voxel(13, 20, 0), Some(2));
+        assert_eq!(vox.count_voxels(), 5);
+    }
+
+    #[test]
+    fn test_translate_crop() {
+        let mut vox = VoxWriter::create_empty();
+        vox.draw_line([0, 0, 0], [0, 0, 199], 1);
+        vox.translate([-5, 300, 0]);
+        assert_eq!(vox.get_bounds(), Some(([-5, 300, 0], [-5, 300, 199])));
+        assert_eq!(vox.get_voxel(-5, 300, 130), Some(1));
+        assert_eq!(vox.crop([-10, 0, 10], [0, 400, 19]), 190);
+        assert_eq!(vox.get_bounds(), Some(([-5, 300, 10], [-5, 300, 19])));
+        // the empty cubes are gone
+        assert_eq!(vox.cubes.len(), 1);
+        vox.clear_voxels();
+        vox.add_voxel(0, 0, 0, 3);
+        assert_eq!(vox.get_voxel(0, 0, 0), Some(3));
+    }
+}
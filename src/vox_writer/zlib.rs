@@ -0,0 +1,630 @@
+use super::invalid_data;
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn create(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> std::io::Result<u32> {
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| invalid_data("inflate: unexpected end of data"))?;
+            self.pos += 1;
+            self.bit_buf |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let v = self.bit_buf & ((1u64 << count) - 1) as u32;
+        self.bit_buf >>= count;
+        self.bit_count -= count;
+        Ok(v)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+}
+
+/// canonical huffman table, decoded bit per bit
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn create(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for l in lengths {
+            counts[*l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (s, l) in lengths.iter().enumerate() {
+            if *l != 0 {
+                symbols[offsets[*l as usize] as usize] = s as u16;
+                offsets[*l as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> std::io::Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(invalid_data("inflate: bad huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (i, l) in lengths.iter_mut().enumerate() {
+        *l = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::create(&lengths), Huffman::create(&[5u8; 30]))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> std::io::Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let hlit = reader.bits(5)? as usize + 257;
+    let hdist = reader.bits(5)? as usize + 1;
+    let hclen = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for i in ORDER.iter().take(hclen) {
+        code_lengths[*i] = reader.bits(3)? as u8;
+    }
+    let code_table = Huffman::create(&code_lengths);
+    let mut lengths = vec![0u8; hlit + hdist];
+    let mut i = 0;
+    while i < hlit + hdist {
+        let sym = code_table.decode(reader)?;
+        let (value, repeat) = match sym {
+            0..=15 => (sym as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(invalid_data("inflate: repeat without previous length"));
+                }
+                (lengths[i - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > hlit + hdist {
+            return Err(invalid_data("inflate: too many lengths"));
+        }
+        for l in lengths.iter_mut().skip(i).take(repeat) {
+            *l = value;
+        }
+        i += repeat;
+    }
+    Ok((
+        Huffman::create(&lengths[..hlit]),
+        Huffman::create(&lengths[hlit..]),
+    ))
+}
+
+/// decompress a raw deflate stream of at most max_len bytes, return the data and the count of
+/// bytes read
+pub(crate) fn inflate(data: &[u8], max_len: usize) -> std::io::Result<(Vec<u8>, usize)> {
+    let too_much = || invalid_data("inflate: too much data");
+    let mut out: Vec<u8> = vec![];
+    let mut reader = BitReader::create(data);
+    loop {
+        let is_last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let p = reader.pos;
+                if p + 4 > data.len() {
+                    return Err(invalid_data("inflate: unexpected end of data"));
+                }
+                let len = u16::from_le_bytes([data[p], data[p + 1]]) as usize;
+                let end = p + 4 + len;
+                if end > data.len() {
+                    return Err(invalid_data("inflate: unexpected end of data"));
+                }
+                if out.len() + len > max_len {
+                    return Err(too_much());
+                }
+                out.extend_from_slice(&data[p + 4..end]);
+                reader.pos = end;
+            }
+            t @ (1 | 2) => {
+                let (lit, dist) = if t == 1 {
+                    fixed_tables()
+                } else {
+                    dynamic_tables(&mut reader)?
+                };
+                loop {
+                    let sym = lit.decode(&mut reader)? as usize;
+                    if sym < 256 {
+                        if out.len() >= max_len {
+                            return Err(too_much());
+                        }
+                        out.push(sym as u8);
+                    } else if sym == 256 {
+                        break;
+                    } else {
+                        let sym = sym - 257;
+                        if sym >= 29 {
+                            return Err(invalid_data("inflate: bad length"));
+                        }
+                        let len = LENGTH_BASE[sym] as usize
+                            + reader.bits(LENGTH_EXTRA[sym] as u32)? as usize;
+                        let dsym = dist.decode(&mut reader)? as usize;
+                        if dsym >= 30 {
+                            return Err(invalid_data("inflate: bad distance"));
+                        }
+                        let d = DIST_BASE[dsym] as usize
+                            + reader.bits(DIST_EXTRA[dsym] as u32)? as usize;
+                        if d > out.len() {
+                            return Err(invalid_data("inflate: distance too far"));
+                        }
+                        if out.len() + len > max_len {
+                            return Err(too_much());
+                        }
+                        let start = out.len() - d;
+                        for k in 0..len {
+                            out.push(out[start + k]);
+                        }
+                    }
+                }
+            }
+            _ => return Err(invalid_data("inflate: bad block type")),
+        }
+        if is_last {
+            break;
+        }
+    }
+    Ok((out, reader.pos))
+}
+
+/// decompress a zlib stream (2 bytes header, deflate data, adler32) of at most max_len bytes
+pub(crate) fn zlib_decompress(data: &[u8], max_len: usize) -> std::io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || (data[0] as u16 * 256 + data[1] as u16) % 31 != 0 {
+        return Err(invalid_data("zlib: bad header"));
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(invalid_data("zlib: preset dictionary not supported"));
+    }
+    let (out, len) = inflate(&data[2..], max_len)?;
+    match data.get(2 + len..2 + len + 4) {
+        Some(b) if u32::from_be_bytes([b[0], b[1], b[2], b[3]]) == adler32(&out) => Ok(out),
+        Some(_) => Err(invalid_data("zlib: bad checksum")),
+        None => Err(invalid_data("zlib: no checksum")),
+    }
+}
+
+/// decompress a gzip member of at most max_len bytes
+pub(crate) fn gzip_decompress(data: &[u8], max_len: usize) -> std::io::Result<Vec<u8>> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 {
+        return Err(invalid_data("gzip: bad header"));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        pos += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            while pos < data.len() && data[pos] != 0 {
+                pos += 1;
+            }
+            pos += 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    if pos >= data.len() {
+        return Err(invalid_data("gzip: truncated header"));
+    }
+    let (out, len) = inflate(&data[pos..], max_len)?;
+    let trailer = data
+        .get(pos + len..pos + len + 8)
+        .ok_or_else(|| invalid_data("gzip: no trailer"))?;
+    let word =
+        |i: usize| u32::from_le_bytes([trailer[i], trailer[i + 1], trailer[i + 2], trailer[i + 3]]);
+    if word(0) != crc32(&out) || word(4) != out.len() as u32 {
+        return Err(invalid_data("gzip: bad checksum"));
+    }
+    Ok(out)
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn create() -> Self {
+        Self {
+            out: vec![],
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        self.bit_buf |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// huffman codes are written from their most significant bit
+    fn code(&mut self, code: u32, len: u32) {
+        let mut rev = 0;
+        for i in 0..len {
+            rev |= ((code >> i) & 1) << (len - 1 - i);
+        }
+        self.bits(rev, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push(self.bit_buf as u8);
+        }
+        self.out
+    }
+}
+
+fn write_fixed_literal(writer: &mut BitWriter, sym: u32) {
+    match sym {
+        0..=143 => writer.code(0x30 + sym, 8),
+        144..=255 => writer.code(0x190 + sym - 144, 9),
+        256..=279 => writer.code(sym - 256, 7),
+        _ => writer.code(0xc0 + sym - 280, 8),
+    }
+}
+
+fn write_fixed_match(writer: &mut BitWriter, len: usize, dist: usize) {
+    let l = LENGTH_BASE
+        .iter()
+        .rposition(|b| *b as usize <= len)
+        .unwrap();
+    write_fixed_literal(writer, 257 + l as u32);
+    writer.bits(
+        (len - LENGTH_BASE[l] as usize) as u32,
+        LENGTH_EXTRA[l] as u32,
+    );
+    let d = DIST_BASE.iter().rposition(|b| *b as usize <= dist).unwrap();
+    writer.code(d as u32, 5);
+    writer.bits((dist - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+}
+
+/// compress to a raw deflate stream, with a greedy lz77 and the fixed huffman codes
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    const WINDOW: usize = 32768;
+    const MAX_CHAIN: usize = 32;
+    const HASH_SIZE: usize = 1 << 15;
+    let hash = |p: usize| -> usize {
+        let v = (data[p] as usize) << 16 | (data[p + 1] as usize) << 8 | data[p + 2] as usize;
+        (v.wrapping_mul(2654435761) >> 7) & (HASH_SIZE - 1)
+    };
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut prev = vec![usize::MAX; data.len()];
+    let mut writer = BitWriter::create();
+    writer.bits(1, 1); // last block
+    writer.bits(1, 2); // fixed huffman
+    let mut p = 0;
+    while p < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if p + 3 <= data.len() {
+            let h = hash(p);
+            let mut candidate = head[h];
+            let mut chain = 0;
+            while candidate != usize::MAX && p - candidate <= WINDOW && chain < MAX_CHAIN {
+                let max_len = usize::min(258, data.len() - p);
+                let mut len = 0;
+                while len < max_len && data[candidate + len] == data[p + len] {
+                    len += 1;
+                }
+                if len > best_len {
+                    best_len = len;
+                    best_dist = p - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+        let step = if best_len >= 3 {
+            write_fixed_match(&mut writer, best_len, best_dist);
+            best_len
+        } else {
+            write_fixed_literal(&mut writer, data[p] as u32);
+            1
+        };
+        let end = usize::max(p, usize::min(p + step, data.len().saturating_sub(2)));
+        for (k, pv) in prev[p..end].iter_mut().enumerate() {
+            let h = hash(p + k);
+            *pv = head[h];
+            head[h] = p + k;
+        }
+        p += step;
+    }
+    write_fixed_literal(&mut writer, 256);
+    writer.finish()
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for chunk in data.chunks(5552) {
+        for v in chunk {
+            a += *v as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// compress to a zlib stream
+pub(crate) fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// compress to a gzip member, without file name
+pub(crate) fn gzip_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// the crc32 of png chunks and gzip members
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, t) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *t = c;
+    }
+    let mut crc = 0xffffffffu32;
+    for v in data {
+        crc = table[((crc ^ *v as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xffffffff
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::{Compression, GzBuilder};
+    use std::io::{Read, Write};
+
+    /// the copies of data with a few bytes changed, or truncated, from a fixed seed
+    pub(crate) fn mutations(data: &[u8], count: usize) -> Vec<Vec<u8>> {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+        (0..count)
+            .map(|_| {
+                let mut copy = data.to_vec();
+                if copy.is_empty() {
+                    return copy;
+                }
+                if next() % 4 == 0 {
+                    copy.truncate(next() % copy.len());
+                } else {
+                    for _ in 0..1 + next() % 4 {
+                        let i = next() % copy.len();
+                        copy[i] = next() as u8;
+                    }
+                }
+                copy
+            })
+            .collect()
+    }
+
+    /// some data with runs, repeats and noise
+    fn sample_data() -> Vec<u8> {
+        (0..50000u32)
+            .map(|i| match (i / 5000) % 3 {
+                0 => (i / 100) as u8,
+                1 => b"the voxels of the volume "[(i % 25) as usize],
+                _ => (i.wrapping_mul(2654435761) >> 24) as u8,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_zlib_decompress() {
+        let data = [
+            120, 218, 203, 72, 205, 201, 201, 87, 200, 64, 39, 1, 104, 3, 8, 177,
+        ];
+        assert_eq!(
+            zlib_decompress(&data, usize::MAX).unwrap(),
+            b"hello hello hello hello".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_inflate_stored() {
+        let data = [1, 3, 0, 252, 255, b'a', b'b', b'c'];
+        assert_eq!(inflate(&data, 3).unwrap(), (b"abc".to_vec(), 8));
+        assert!(inflate(&data, 2).is_err());
+    }
+
+    #[test]
+    fn test_zlib_round_trip() {
+        let data: Vec<u8> = (0..20000u32)
+            .map(|i| ((i * i) % 7 + i / 1000) as u8)
+            .collect();
+        let compressed = zlib_compress(&data);
+        assert!(compressed.len() < data.len() / 2);
+        assert_eq!(zlib_decompress(&compressed, usize::MAX).unwrap(), data);
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+    }
+
+    #[test]
+    fn test_decompress_checks() {
+        let data = sample_data();
+        let mut compressed = zlib_compress(&data);
+        assert!(zlib_decompress(&compressed, data.len() - 1).is_err());
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(zlib_decompress(&compressed, data.len()).is_err());
+        compressed.truncate(last);
+        assert!(zlib_decompress(&compressed, data.len()).is_err());
+
+        let mut compressed = gzip_compress(&data);
+        assert_eq!(gzip_decompress(&compressed, data.len()).unwrap(), data);
+        assert!(gzip_decompress(&compressed, data.len() - 1).is_err());
+        let crc = compressed.len() - 8;
+        compressed[crc] ^= 1;
+        assert!(gzip_decompress(&compressed, data.len()).is_err());
+    }
+
+    #[test]
+    fn test_decompress_flate2() {
+        let data = sample_data();
+        for level in [0, 1, 6, 9] {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::new(level));
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(zlib_decompress(&compressed, usize::MAX).unwrap(), data);
+            let mut encoder = GzEncoder::new(vec![], Compression::new(level));
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(gzip_decompress(&compressed, usize::MAX).unwrap(), data);
+        }
+        // the optional fields of the gzip header
+        let mut encoder = GzBuilder::new()
+            .filename("volume.raw")
+            .comment("a comment")
+            .extra(vec![1, 2, 3])
+            .write(vec![], Compression::default());
+        encoder.write_all(&data).unwrap();
+        assert_eq!(
+            gzip_decompress(&encoder.finish().unwrap(), usize::MAX).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_compress_flate2() {
+        let data = sample_data();
+        let mut out = vec![];
+        ZlibDecoder::new(&zlib_compress(&data)[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+        let mut out = vec![];
+        GzDecoder::new(&gzip_compress(&data)[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_decompress_mutations() {
+        // the damaged streams give an error or some data, never a panic
+        let data = sample_data();
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&data[..8000]).unwrap();
+        for m in mutations(&encoder.finish().unwrap(), 3000) {
+            let _ = zlib_decompress(&m, 1 << 20);
+        }
+        let mut encoder = GzBuilder::new()
+            .filename("a")
+            .extra(vec![0; 4])
+            .write(vec![], Compression::fast());
+        encoder.write_all(&data[..8000]).unwrap();
+        for m in mutations(&encoder.finish().unwrap(), 3000) {
+            let _ = gzip_decompress(&m, 1 << 20);
+        }
+        for m in mutations(&deflate(&data[..8000]), 3000) {
+            let _ = inflate(&m, 1 << 20);
+        }
+    }
+}